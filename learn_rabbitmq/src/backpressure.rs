@@ -0,0 +1,537 @@
+// Backpressure cho publisher:
+//   - RateLimiter: token bucket (rate msg/s, burst) - giới hạn tốc độ publish của 1 publisher
+//   - BlockedWatch: broker gửi `connection.blocked` khi chạm memory/disk alarm. lapin vẫn nhận publish
+//     nhưng ngừng ghi ra socket → message dồn trong RAM không giới hạn. Watch trạng thái này (lapin
+//     không có callback, chỉ có connection.status().blocked() → poll) và tạm dừng publish.
+//   - PublishBuffer: hàng đợi trong RAM có giới hạn, đầy thì theo OverflowPolicy (chờ / bỏ cũ nhất / báo lỗi)
+//   - BufferedPublisher: ghép cả 3 - producers đẩy vào buffer, 1 task nền rút ra publish qua BatchPublisher
+
+use crate::batch::{BatchPublisher, OutgoingMessage, PublishOutcome};
+use lapin::{Connection, ConnectionStatus, Result as LapinResult};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+
+// Token bucket: `rate` token/giây, tích tối đa `burst` token
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimiter {
+            rate: rate.max(f64::MIN_POSITIVE),
+            burst,
+            // Bắt đầu đầy → cho phép burst ngay
+            bucket: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst as u32
+    }
+
+    // Lấy `n` token nếu đủ; không thì trả về thời gian phải chờ
+    fn take(&self, n: f64) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, last) = &mut *bucket;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.burst);
+        *last = now;
+
+        if *tokens >= n {
+            *tokens -= n;
+            Ok(())
+        } else {
+            // rate ~0 → thời gian chờ vượt Duration → chờ "mãi mãi" thay vì panic
+            Err(Duration::try_from_secs_f64((n - *tokens) / self.rate).unwrap_or(Duration::MAX))
+        }
+    }
+
+    pub fn try_acquire(&self, n: u32) -> bool {
+        self.take(f64::from(n)).is_ok()
+    }
+
+    // Chờ tới khi lấy được `n` token (n > burst → lấy từng phần)
+    pub async fn acquire(&self, n: u32) {
+        let mut remaining = f64::from(n);
+        while remaining > 0.0 {
+            let chunk = remaining.min(self.burst);
+            match self.take(chunk) {
+                Ok(()) => remaining -= chunk,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+}
+
+// Trạng thái connection.blocked, cập nhật bởi task poll (watch_blocked)
+#[derive(Clone)]
+pub struct BlockedWatch {
+    blocked: watch::Receiver<bool>,
+}
+
+impl BlockedWatch {
+    pub fn is_blocked(&self) -> bool {
+        *self.blocked.borrow()
+    }
+
+    // Trả về ngay nếu không bị block; task poll dừng → coi như không block
+    pub async fn wait_unblocked(&mut self) {
+        let _ = self.blocked.wait_for(|blocked| !blocked).await;
+    }
+}
+
+// Poll connection.status().blocked() mỗi `interval` cho tới khi connection đóng
+pub fn watch_blocked(connection: &Connection, interval: Duration) -> (BlockedWatch, JoinHandle<()>) {
+    watch_status(connection.status().clone(), interval)
+}
+
+fn watch_status(status: ConnectionStatus, interval: Duration) -> (BlockedWatch, JoinHandle<()>) {
+    let (tx, rx) = watch::channel(status.blocked());
+    let handle = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if status.closing() || status.closed() || status.errored() {
+                let _ = tx.send(false);
+                return;
+            }
+            let blocked = status.blocked();
+            if tx.send_if_modified(|current| std::mem::replace(current, blocked) != blocked) {
+                if blocked {
+                    println!("⚠️  Connection blocked by broker (memory/disk alarm) - publishing paused");
+                } else {
+                    println!("✓ Connection unblocked - publishing resumed");
+                }
+            }
+            if tx.is_closed() {
+                return;
+            }
+        }
+    });
+    (BlockedWatch { blocked: rx }, handle)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // Producer chờ tới khi buffer có chỗ (backpressure ngược lên producer)
+    #[default]
+    Block,
+    // Bỏ message cũ nhất để nhận message mới (dữ liệu kiểu metrics/telemetry)
+    DropOldest,
+    // Trả lỗi BufferError::Full ngay cho producer
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferError {
+    Full,
+    Closed,
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::Full => write!(f, "publish buffer is full"),
+            BufferError::Closed => write!(f, "publish buffer is closed"),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: AtomicU64,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+    acked: AtomicU64,
+    not_acked: AtomicU64,
+    blocked_pauses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct BufferStats {
+    // Được nhận vào buffer
+    pub accepted: u64,
+    // Bị bỏ do DropOldest
+    pub dropped: u64,
+    // Bị từ chối do Error policy
+    pub rejected: u64,
+    pub acked: u64,
+    // Nacked / returned / failed
+    pub not_acked: u64,
+    // Số lần task publish phải dừng vì connection.blocked
+    pub blocked_pauses: u64,
+    pub queued: usize,
+}
+
+struct BufferState {
+    queue: VecDeque<OutgoingMessage>,
+    closed: bool,
+}
+
+// Kết quả push_now: Wait = Block policy và buffer đầy, trả message lại để chờ rồi thử lại
+enum Push {
+    Done(Result<(), BufferError>),
+    Wait(Box<OutgoingMessage>),
+}
+
+pub struct PublishBuffer {
+    state: Mutex<BufferState>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    changed: Notify,
+    counters: Counters,
+}
+
+impl PublishBuffer {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        PublishBuffer {
+            state: Mutex::new(BufferState {
+                queue: VecDeque::new(),
+                closed: false,
+            }),
+            capacity: capacity.max(1),
+            policy,
+            changed: Notify::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Không chờ: Block policy mà đầy → Err(Full)
+    pub fn try_push(&self, message: OutgoingMessage) -> Result<(), BufferError> {
+        match self.push_now(message, false) {
+            Push::Done(result) => result,
+            Push::Wait(_) => Err(BufferError::Full),
+        }
+    }
+
+    pub async fn push(&self, message: OutgoingMessage) -> Result<(), BufferError> {
+        let mut message = message;
+        loop {
+            // Đăng ký nhận notify TRƯỚC khi kiểm tra → không lỡ wakeup
+            let changed = self.changed.notified();
+            match self.push_now(message, self.policy == OverflowPolicy::Block) {
+                Push::Done(result) => return result,
+                Push::Wait(returned) => message = *returned,
+            }
+            changed.await;
+        }
+    }
+
+    fn push_now(&self, message: OutgoingMessage, wait: bool) -> Push {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Push::Done(Err(BufferError::Closed));
+        }
+        if state.queue.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::Block if wait => return Push::Wait(Box::new(message)),
+                OverflowPolicy::Block | OverflowPolicy::Error => {
+                    self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Push::Done(Err(BufferError::Full));
+                }
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        state.queue.push_back(message);
+        self.counters.accepted.fetch_add(1, Ordering::Relaxed);
+        drop(state);
+        self.changed.notify_waiters();
+        Push::Done(Ok(()))
+    }
+
+    // Chờ có message, lấy tối đa `max`. None = đã close và hết message
+    pub async fn pop_batch(&self, max: usize) -> Option<Vec<OutgoingMessage>> {
+        loop {
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if !state.queue.is_empty() {
+                    let n = max.max(1).min(state.queue.len());
+                    let batch: Vec<OutgoingMessage> = state.queue.drain(..n).collect();
+                    drop(state);
+                    self.changed.notify_waiters();
+                    return Some(batch);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            changed.await;
+        }
+    }
+
+    // Không nhận thêm; message còn lại vẫn được pop
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_waiters();
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        let c = &self.counters;
+        BufferStats {
+            accepted: c.accepted.load(Ordering::Relaxed),
+            dropped: c.dropped.load(Ordering::Relaxed),
+            rejected: c.rejected.load(Ordering::Relaxed),
+            acked: c.acked.load(Ordering::Relaxed),
+            not_acked: c.not_acked.load(Ordering::Relaxed),
+            blocked_pauses: c.blocked_pauses.load(Ordering::Relaxed),
+            queued: self.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackpressureConfig {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    // (msg/s, burst); None = không giới hạn
+    pub rate: Option<(f64, u32)>,
+    // Số message tối đa mỗi lần publish_batch
+    pub max_batch: usize,
+    pub blocked_poll_interval: Duration,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        BackpressureConfig {
+            capacity: 10_000,
+            overflow: OverflowPolicy::Block,
+            rate: None,
+            max_batch: 100,
+            blocked_poll_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+// Producers (clone thoải mái) → PublishBuffer → task nền: chờ unblocked → token → publish_batch
+pub struct BufferedPublisher {
+    buffer: Arc<PublishBuffer>,
+    task: JoinHandle<BatchPublisher>,
+    watcher: JoinHandle<()>,
+}
+
+impl BufferedPublisher {
+    pub fn start(connection: &Connection, publisher: BatchPublisher, config: BackpressureConfig) -> Self {
+        let buffer = Arc::new(PublishBuffer::new(config.capacity, config.overflow));
+        let limiter = config.rate.map(|(rate, burst)| RateLimiter::new(rate, burst));
+        let (blocked, watcher) = watch_blocked(connection, config.blocked_poll_interval);
+        // Có rate limit → batch không lớn hơn burst để tốc độ đều hơn
+        let max_batch = match &limiter {
+            Some(limiter) => config.max_batch.min(limiter.burst() as usize),
+            None => config.max_batch,
+        };
+
+        let task = tokio::spawn(drain(buffer.clone(), publisher, limiter, blocked, max_batch));
+        BufferedPublisher { buffer, task, watcher }
+    }
+
+    pub fn buffer(&self) -> Arc<PublishBuffer> {
+        self.buffer.clone()
+    }
+
+    pub async fn publish(&self, message: OutgoingMessage) -> Result<(), BufferError> {
+        self.buffer.push(message).await
+    }
+
+    pub fn try_publish(&self, message: OutgoingMessage) -> Result<(), BufferError> {
+        self.buffer.try_push(message)
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        self.buffer.stats()
+    }
+
+    // Đóng buffer, chờ publish hết message còn lại. Trả lại BatchPublisher để dùng tiếp
+    pub async fn shutdown(self) -> LapinResult<(BatchPublisher, BufferStats)> {
+        self.buffer.close();
+        let publisher = self
+            .task
+            .await
+            .map_err(|e| lapin::Error::IOError(Arc::new(std::io::Error::other(e))))?;
+        self.watcher.abort();
+        Ok((publisher, self.buffer.stats()))
+    }
+}
+
+async fn drain(
+    buffer: Arc<PublishBuffer>,
+    mut publisher: BatchPublisher,
+    limiter: Option<RateLimiter>,
+    mut blocked: BlockedWatch,
+    max_batch: usize,
+) -> BatchPublisher {
+    while let Some(batch) = buffer.pop_batch(max_batch).await {
+        if blocked.is_blocked() {
+            buffer.counters.blocked_pauses.fetch_add(1, Ordering::Relaxed);
+            blocked.wait_unblocked().await;
+        }
+        if let Some(limiter) = &limiter {
+            limiter.acquire(batch.len() as u32).await;
+        }
+
+        let report = publisher.publish_batch(batch).await;
+        let acked = report.acked() as u64;
+        buffer.counters.acked.fetch_add(acked, Ordering::Relaxed);
+        buffer
+            .counters
+            .not_acked
+            .fetch_add(report.results.len() as u64 - acked, Ordering::Relaxed);
+        for result in report.results.iter().filter(|r| r.outcome != PublishOutcome::Acked).take(3) {
+            println!("✗ buffered publish failed: {:?}", result.outcome);
+        }
+    }
+    publisher
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_full_then_refuses_until_refilled() {
+        let limiter = RateLimiter::new(1.0, 3);
+        assert!(limiter.try_acquire(3));
+        assert!(!limiter.try_acquire(1));
+        // Thiếu 1 token ở 1 token/s → chờ ~1s
+        let wait = limiter.take(1.0).unwrap_err();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1), "{:?}", wait);
+    }
+
+    #[test]
+    fn refills_at_rate_but_never_above_burst() {
+        let limiter = RateLimiter::new(1000.0, 5);
+        assert!(limiter.try_acquire(5));
+        std::thread::sleep(Duration::from_millis(20));
+        // 20ms × 1000/s = 20 token, cắt còn burst = 5
+        assert!(!limiter.try_acquire(6));
+        assert!(limiter.try_acquire(5));
+    }
+
+    #[test]
+    fn clamps_invalid_configuration() {
+        let limiter = RateLimiter::new(0.0, 0);
+        assert_eq!(limiter.burst(), 1);
+        assert!(limiter.rate() > 0.0);
+        assert!(limiter.try_acquire(1));
+        assert!(!limiter.try_acquire(1));
+        assert_eq!(limiter.take(1.0), Err(Duration::MAX));
+    }
+
+    // n > burst → lấy từng phần, tổng thời gian ≈ (n - burst) / rate
+    #[tokio::test]
+    async fn acquire_more_than_burst_waits_for_the_rest() {
+        let limiter = RateLimiter::new(200.0, 10);
+        let started = Instant::now();
+        limiter.acquire(30).await;
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(90), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+        assert!(!limiter.try_acquire(10));
+    }
+
+    fn message(n: u8) -> OutgoingMessage {
+        OutgoingMessage::new("logs", "app.info", vec![n])
+    }
+
+    fn payloads(batch: Vec<OutgoingMessage>) -> Vec<u8> {
+        batch.into_iter().map(|m| m.payload[0]).collect()
+    }
+
+    #[tokio::test]
+    async fn block_policy_waits_until_pop() {
+        let buffer = Arc::new(PublishBuffer::new(1, OverflowPolicy::Block));
+        buffer.push(message(1)).await.unwrap();
+        assert_eq!(buffer.try_push(message(2)), Err(BufferError::Full));
+
+        let producer = tokio::spawn({
+            let buffer = buffer.clone();
+            async move { buffer.push(message(2)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!producer.is_finished());
+
+        assert_eq!(payloads(buffer.pop_batch(10).await.unwrap()), [1]);
+        tokio::time::timeout(Duration::from_secs(1), producer).await.unwrap().unwrap().unwrap();
+        assert_eq!(payloads(buffer.pop_batch(10).await.unwrap()), [2]);
+        // try_push lúc đầy có tính vào rejected, push chờ thì không
+        let stats = buffer.stats();
+        assert_eq!((stats.accepted, stats.rejected, stats.dropped), (2, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_evicts_head() {
+        let buffer = PublishBuffer::new(2, OverflowPolicy::DropOldest);
+        for n in 1..=4 {
+            buffer.push(message(n)).await.unwrap();
+        }
+        assert_eq!(payloads(buffer.pop_batch(10).await.unwrap()), [3, 4]);
+        let stats = buffer.stats();
+        assert_eq!((stats.accepted, stats.dropped, stats.queued), (4, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn error_policy_rejects_when_full() {
+        let buffer = PublishBuffer::new(2, OverflowPolicy::Error);
+        buffer.push(message(1)).await.unwrap();
+        buffer.push(message(2)).await.unwrap();
+        assert_eq!(buffer.push(message(3)).await, Err(BufferError::Full));
+        assert_eq!(payloads(buffer.pop_batch(1).await.unwrap()), [1]);
+        buffer.push(message(3)).await.unwrap();
+        assert_eq!(payloads(buffer.pop_batch(10).await.unwrap()), [2, 3]);
+        assert_eq!(buffer.stats().rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn close_drains_then_ends() {
+        let buffer = Arc::new(PublishBuffer::new(4, OverflowPolicy::Block));
+        buffer.push(message(1)).await.unwrap();
+
+        // Consumer đang chờ message mới được đánh thức bởi close
+        let idle = Arc::new(PublishBuffer::new(4, OverflowPolicy::Block));
+        let waiting = tokio::spawn({
+            let idle = idle.clone();
+            async move { idle.pop_batch(10).await.is_none() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        idle.close();
+        assert!(tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap());
+
+        buffer.close();
+        assert_eq!(buffer.push(message(2)).await, Err(BufferError::Closed));
+        assert_eq!(buffer.try_push(message(2)), Err(BufferError::Closed));
+        assert_eq!(payloads(buffer.pop_batch(10).await.unwrap()), [1]);
+        assert!(buffer.pop_batch(10).await.is_none());
+    }
+}
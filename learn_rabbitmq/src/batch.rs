@@ -0,0 +1,295 @@
+// Batch publishing với publisher confirms
+// Thay vì publish → chờ confirm → publish tiếp, gửi nhiều messages liên tục (pipelining)
+// rồi mới chờ confirms. Mỗi message được theo dõi bằng delivery tag của channel.
+
+use crate::schema::SchemaRegistry;
+use lapin::{
+    options::{BasicPublishOptions, ConfirmSelectOptions},
+    publisher_confirm::{Confirmation, PublisherConfirm},
+    BasicProperties, Channel, Result as LapinResult,
+};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    pub exchange: String,
+    pub routing_key: String,
+    pub payload: Vec<u8>,
+    pub properties: BasicProperties,
+}
+
+impl OutgoingMessage {
+    pub fn new(exchange: &str, routing_key: &str, payload: impl Into<Vec<u8>>) -> Self {
+        OutgoingMessage {
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+            payload: payload.into(),
+            properties: BasicProperties::default(),
+        }
+    }
+
+    pub fn with_properties(mut self, properties: BasicProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PublishOutcome {
+    // Broker đã nhận và route message
+    Acked,
+    // Broker từ chối message (basic.nack)
+    Nacked,
+    // Message `mandatory` không route được đến queue nào (basic.return)
+    Returned { reply_code: u16, reply_text: String },
+    // Lỗi channel/connection - không biết broker đã nhận hay chưa
+    Failed(String),
+    // Sai JSON Schema - KHÔNG được publish
+    Invalid(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct MessageReport {
+    // Vị trí của message trong batch
+    pub index: usize,
+    pub delivery_tag: u64,
+    pub outcome: PublishOutcome,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchReport {
+    pub results: Vec<MessageReport>,
+    pub elapsed: Duration,
+}
+
+impl BatchReport {
+    pub fn count(&self, outcome: fn(&PublishOutcome) -> bool) -> usize {
+        self.results.iter().filter(|r| outcome(&r.outcome)).count()
+    }
+
+    pub fn acked(&self) -> usize {
+        self.count(|o| *o == PublishOutcome::Acked)
+    }
+
+    pub fn nacked(&self) -> usize {
+        self.count(|o| *o == PublishOutcome::Nacked)
+    }
+
+    pub fn returned(&self) -> usize {
+        self.count(|o| matches!(o, PublishOutcome::Returned { .. }))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, PublishOutcome::Failed(_)))
+    }
+
+    pub fn invalid(&self) -> usize {
+        self.count(|o| matches!(o, PublishOutcome::Invalid(_)))
+    }
+
+    pub fn all_acked(&self) -> bool {
+        self.acked() == self.results.len()
+    }
+
+    pub fn messages_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.results.len() as f64 / secs
+    }
+}
+
+// Publisher dùng RIÊNG 1 channel ở chế độ confirm.
+// ⚠️  Không publish trên channel này từ chỗ khác, nếu không delivery tag sẽ lệch.
+pub struct BatchPublisher {
+    channel: Channel,
+    next_delivery_tag: u64,
+    max_in_flight: usize,
+    mandatory: bool,
+    schemas: Option<Arc<SchemaRegistry>>,
+}
+
+impl BatchPublisher {
+    pub async fn new(channel: Channel) -> LapinResult<Self> {
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
+
+        Ok(BatchPublisher {
+            channel,
+            next_delivery_tag: 1,
+            max_in_flight: 1000,
+            mandatory: false,
+            schemas: None,
+        })
+    }
+
+    // Số messages tối đa đang chờ confirm cùng lúc
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    // Bật `mandatory` để phát hiện messages không route được (Returned)
+    pub fn with_mandatory(mut self, mandatory: bool) -> Self {
+        self.mandatory = mandatory;
+        self
+    }
+
+    // Validate payload theo `type` + version trước khi publish (xem src/schema.rs)
+    pub fn with_schema_registry(mut self, schemas: Arc<SchemaRegistry>) -> Self {
+        self.schemas = Some(schemas);
+        self
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    // Pipelined: publish liên tục, chỉ chờ confirm khi vượt quá `max_in_flight`
+    pub async fn publish_batch<I>(&mut self, messages: I) -> BatchReport
+    where
+        I: IntoIterator<Item = OutgoingMessage>,
+    {
+        self.publish_with_window(messages, self.max_in_flight).await
+    }
+
+    // Tuần tự: publish → chờ confirm → publish tiếp (giống vòng lặp trong work_queue_producer)
+    pub async fn publish_sequential<I>(&mut self, messages: I) -> BatchReport
+    where
+        I: IntoIterator<Item = OutgoingMessage>,
+    {
+        self.publish_with_window(messages, 1).await
+    }
+
+    async fn publish_with_window<I>(&mut self, messages: I, window: usize) -> BatchReport
+    where
+        I: IntoIterator<Item = OutgoingMessage>,
+    {
+        let started = Instant::now();
+        let mut results = Vec::new();
+        let mut in_flight: VecDeque<(usize, u64, PublisherConfirm)> = VecDeque::new();
+
+        for (index, message) in messages.into_iter().enumerate() {
+            if let Some(schemas) = &self.schemas
+                && let Err(violation) = schemas.validate_message(&message.properties, &message.payload)
+            {
+                results.push(MessageReport {
+                    index,
+                    delivery_tag: 0,
+                    outcome: PublishOutcome::Invalid(violation.errors()),
+                });
+                continue;
+            }
+
+            let published = self
+                .channel
+                .basic_publish(
+                    &message.exchange,
+                    &message.routing_key,
+                    BasicPublishOptions {
+                        mandatory: self.mandatory,
+                        ..Default::default()
+                    },
+                    &message.payload,
+                    message.properties,
+                )
+                .await;
+
+            match published {
+                Ok(confirm) => {
+                    let delivery_tag = self.next_delivery_tag;
+                    self.next_delivery_tag += 1;
+                    in_flight.push_back((index, delivery_tag, confirm));
+                }
+                Err(e) => results.push(MessageReport {
+                    index,
+                    delivery_tag: 0,
+                    outcome: PublishOutcome::Failed(e.to_string()),
+                }),
+            }
+
+            while in_flight.len() >= window {
+                let (index, delivery_tag, confirm) = in_flight.pop_front().unwrap();
+                results.push(wait_confirm(index, delivery_tag, confirm).await);
+            }
+        }
+
+        while let Some((index, delivery_tag, confirm)) = in_flight.pop_front() {
+            results.push(wait_confirm(index, delivery_tag, confirm).await);
+        }
+
+        results.sort_by_key(|r| r.index);
+
+        BatchReport {
+            results,
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+async fn wait_confirm(index: usize, delivery_tag: u64, confirm: PublisherConfirm) -> MessageReport {
+    MessageReport {
+        index,
+        delivery_tag,
+        outcome: confirm_outcome(confirm.await),
+    }
+}
+
+fn confirm_outcome(confirmation: LapinResult<Confirmation>) -> PublishOutcome {
+    match confirmation {
+        Ok(Confirmation::Ack(None)) => PublishOutcome::Acked,
+        Ok(Confirmation::Ack(Some(returned))) | Ok(Confirmation::Nack(Some(returned))) => {
+            PublishOutcome::Returned {
+                reply_code: returned.reply_code,
+                reply_text: returned.reply_text.to_string(),
+            }
+        }
+        Ok(Confirmation::Nack(None)) => PublishOutcome::Nacked,
+        // Channel không ở chế độ confirm (confirm_select bị tắt / channel dùng chung) → không biết broker đã nhận chưa
+        Ok(Confirmation::NotRequested) => PublishOutcome::Failed("confirms not enabled on channel".to_string()),
+        Err(e) => PublishOutcome::Failed(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_requested_is_not_reported_as_acked() {
+        assert_eq!(
+            confirm_outcome(Ok(Confirmation::NotRequested)),
+            PublishOutcome::Failed("confirms not enabled on channel".to_string())
+        );
+        assert_eq!(confirm_outcome(Ok(Confirmation::Ack(None))), PublishOutcome::Acked);
+        assert_eq!(confirm_outcome(Ok(Confirmation::Nack(None))), PublishOutcome::Nacked);
+    }
+
+    #[test]
+    fn report_counts_by_outcome() {
+        let report = BatchReport {
+            results: [
+                PublishOutcome::Acked,
+                PublishOutcome::Acked,
+                PublishOutcome::Nacked,
+                PublishOutcome::Failed("x".to_string()),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(index, outcome)| MessageReport {
+                index,
+                delivery_tag: index as u64 + 1,
+                outcome,
+            })
+            .collect(),
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!((report.acked(), report.nacked(), report.failed()), (2, 1, 1));
+        assert!(!report.all_acked());
+        assert_eq!(report.messages_per_sec(), 2.0);
+    }
+}
@@ -0,0 +1,432 @@
+// Load generation & benchmark cho các loại exchange (direct / topic / fanout)
+// N producers + M consumers, đo throughput và end-to-end latency.
+// Latency = thời điểm consumer nhận - timestamp producer ghi vào header `x-sent-at-us`.
+
+use crate::management::key_value_table;
+use crate::pool::{ChannelPool, PoolConfig};
+use futures::StreamExt;
+use lapin::{
+    options::*,
+    types::{AMQPValue, FieldTable},
+    BasicProperties, Connection, ExchangeKind, Result as LapinResult,
+};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const SENT_AT_HEADER: &str = "x-sent-at-us";
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub exchange: String,
+    pub exchange_kind: ExchangeKind,
+    // Producers lần lượt dùng các routing keys này (round-robin)
+    pub routing_keys: Vec<String>,
+    // Mỗi consumer queue được bind với các binding keys này
+    pub binding_keys: Vec<String>,
+    pub producers: usize,
+    pub consumers: usize,
+    // true: consumers dùng CHUNG 1 queue (work queue), false: mỗi consumer 1 queue riêng
+    pub shared_queue: bool,
+    pub message_size: usize,
+    // Messages/giây cho MỖI producer, 0 = không giới hạn
+    pub rate: u64,
+    pub duration: Duration,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            exchange: "bench_topic".to_string(),
+            exchange_kind: ExchangeKind::Topic,
+            routing_keys: vec!["bench.event".to_string()],
+            binding_keys: vec!["bench.#".to_string()],
+            producers: 1,
+            consumers: 1,
+            shared_queue: false,
+            message_size: 256,
+            rate: 0,
+            duration: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyStats {
+    pub min_us: u64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+    pub mean_us: u64,
+}
+
+impl LatencyStats {
+    pub fn from_samples(samples: &mut [u64]) -> Self {
+        if samples.is_empty() {
+            return LatencyStats::default();
+        }
+        samples.sort_unstable();
+
+        let percentile = |p: f64| {
+            let rank = ((p / 100.0) * samples.len() as f64).ceil() as usize;
+            samples[rank.clamp(1, samples.len()) - 1]
+        };
+        let sum: u64 = samples.iter().sum();
+
+        LatencyStats {
+            min_us: samples[0],
+            p50_us: percentile(50.0),
+            p90_us: percentile(90.0),
+            p99_us: percentile(99.0),
+            max_us: samples[samples.len() - 1],
+            mean_us: sum / samples.len() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub exchange: String,
+    pub exchange_type: String,
+    pub producers: usize,
+    pub consumers: usize,
+    pub message_size: usize,
+    pub sent: u64,
+    pub received: u64,
+    pub publish_secs: f64,
+    pub publish_rate: f64,
+    pub consume_rate: f64,
+    pub latency: LatencyStats,
+}
+
+impl BenchReport {
+    pub fn to_table(&self) -> String {
+        let rows = [
+            ("exchange", format!("{} ({})", self.exchange, self.exchange_type)),
+            ("producers / consumers", format!("{} / {}", self.producers, self.consumers)),
+            ("message size", format!("{} bytes", self.message_size)),
+            ("sent", self.sent.to_string()),
+            ("received", self.received.to_string()),
+            ("publish rate", format!("{:.0} msg/s", self.publish_rate)),
+            ("consume rate", format!("{:.0} msg/s", self.consume_rate)),
+            ("latency min", format_us(self.latency.min_us)),
+            ("latency p50", format_us(self.latency.p50_us)),
+            ("latency p90", format_us(self.latency.p90_us)),
+            ("latency p99", format_us(self.latency.p99_us)),
+            ("latency max", format_us(self.latency.max_us)),
+            ("latency mean", format_us(self.latency.mean_us)),
+        ];
+        key_value_table(&rows)
+    }
+}
+
+fn format_us(us: u64) -> String {
+    if us >= 1000 {
+        format!("{:.2} ms", us as f64 / 1000.0)
+    } else {
+        format!("{} µs", us)
+    }
+}
+
+impl BenchConfig {
+    // Binding keys thật sự dùng: direct exchange so khớp nguyên văn ("bench.#" không khớp "bench.event")
+    // → binding key có wildcard thì bind bằng chính các routing keys
+    pub fn effective_binding_keys(&self) -> Vec<String> {
+        let has_wildcard = self.binding_keys.iter().any(|key| key.contains(['*', '#']));
+        if self.exchange_kind == ExchangeKind::Direct && has_wildcard {
+            self.routing_keys.clone()
+        } else {
+            self.binding_keys.clone()
+        }
+    }
+}
+
+pub fn exchange_type_name(kind: &ExchangeKind) -> String {
+    match kind {
+        ExchangeKind::Direct => "direct".to_string(),
+        ExchangeKind::Fanout => "fanout".to_string(),
+        ExchangeKind::Topic => "topic".to_string(),
+        ExchangeKind::Headers => "headers".to_string(),
+        ExchangeKind::Custom(kind) => kind.clone(),
+    }
+}
+
+pub fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+#[derive(Default)]
+struct ConsumerStats {
+    received: AtomicU64,
+    // now_micros() của message nhận cuối cùng
+    last_received_us: AtomicI64,
+    latencies: Mutex<Vec<u64>>,
+}
+
+// Chạy benchmark: producers publish trên `publish_conn`, consumers nhận trên `consume_conn`
+// (2 connections riêng để consumer không bị ảnh hưởng bởi flow control của publisher)
+pub async fn run(
+    publish_conn: Connection,
+    consume_conn: Connection,
+    config: BenchConfig,
+) -> LapinResult<BenchReport> {
+    // Producers chọn routing key theo `seq % routing_keys.len()`
+    if config.routing_keys.is_empty() {
+        return Err(crate::tls::invalid_config("bench needs at least one routing key"));
+    }
+    let binding_keys = config.effective_binding_keys();
+    if binding_keys != config.binding_keys {
+        println!(
+            "ℹ️  Direct exchange does not match wildcards, binding with routing keys {:?}",
+            binding_keys
+        );
+    }
+    let config = BenchConfig { binding_keys, ..config };
+
+    let setup = consume_conn.create_channel().await?;
+    setup
+        .exchange_declare(
+            &config.exchange,
+            config.exchange_kind.clone(),
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    // BƯỚC 1: Consumers - mỗi consumer 1 queue exclusive, hoặc chung 1 queue
+    let shared_queue = if config.shared_queue {
+        Some(declare_bound_queue(&setup, &config).await?)
+    } else {
+        None
+    };
+
+    let stats = Arc::new(ConsumerStats::default());
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut consumer_handles = Vec::new();
+
+    for index in 0..config.consumers {
+        let channel = consume_conn.create_channel().await?;
+        let queue_name = match &shared_queue {
+            Some(name) => name.clone(),
+            None => declare_bound_queue(&channel, &config).await?,
+        };
+
+        let mut consumer = channel
+            .basic_consume(
+                &queue_name,
+                &format!("bench_consumer_{}", index),
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        let stats = stats.clone();
+        let stop = stop.clone();
+        consumer_handles.push(tokio::spawn(async move {
+            while !stop.load(Ordering::Relaxed) {
+                let delivery = tokio::time::timeout(Duration::from_millis(200), consumer.next()).await;
+                let delivery = match delivery {
+                    Ok(Some(Ok(delivery))) => delivery,
+                    Ok(_) => break,
+                    Err(_) => continue,
+                };
+
+                let received_at = now_micros();
+                stats.received.fetch_add(1, Ordering::Relaxed);
+                stats.last_received_us.fetch_max(received_at, Ordering::Relaxed);
+
+                let sent_at = delivery
+                    .properties
+                    .headers()
+                    .as_ref()
+                    .and_then(|h| h.inner().get(SENT_AT_HEADER))
+                    .and_then(|v| v.as_long_long_int());
+                if let Some(sent_at) = sent_at {
+                    let latency = (received_at - sent_at).max(0) as u64;
+                    stats.latencies.lock().unwrap().push(latency);
+                }
+            }
+            let _ = channel.close(200, "bench finished").await;
+        }));
+    }
+
+    // BƯỚC 2: Producers - dùng chung 1 connection, mỗi producer mượn 1 channel từ pool
+    let pool = ChannelPool::new(
+        publish_conn,
+        PoolConfig {
+            size: config.producers.max(1),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let payload = Arc::new(vec![b'x'; config.message_size]);
+    let sent = Arc::new(AtomicU64::new(0));
+    let started = Instant::now();
+    let started_us = now_micros();
+    let deadline = started + config.duration;
+    let mut producer_handles = Vec::new();
+
+    for index in 0..config.producers {
+        let pool = pool.clone();
+        let payload = payload.clone();
+        let sent = sent.clone();
+        let exchange = config.exchange.clone();
+        let routing_keys = config.routing_keys.clone();
+        let rate = config.rate;
+
+        producer_handles.push(tokio::spawn(async move {
+            let channel = pool.get().await?;
+            let mut ticker = (rate > 0).then(|| {
+                let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / rate as f64));
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+                ticker
+            });
+
+            let mut seq: usize = index;
+            while Instant::now() < deadline {
+                if let Some(ticker) = ticker.as_mut() {
+                    ticker.tick().await;
+                }
+
+                let routing_key = &routing_keys[seq % routing_keys.len()];
+                seq += 1;
+
+                let mut headers = FieldTable::default();
+                headers.insert(SENT_AT_HEADER.into(), AMQPValue::LongLongInt(now_micros()));
+
+                channel
+                    .basic_publish(
+                        &exchange,
+                        routing_key,
+                        BasicPublishOptions::default(),
+                        &payload,
+                        BasicProperties::default().with_headers(headers),
+                    )
+                    .await?;
+                sent.fetch_add(1, Ordering::Relaxed);
+            }
+            LapinResult::Ok(())
+        }));
+    }
+
+    for handle in producer_handles {
+        handle
+            .await
+            .map_err(|e| lapin::Error::IOError(Arc::new(std::io::Error::other(e))))??;
+    }
+    let publish_secs = started.elapsed().as_secs_f64();
+
+    // BƯỚC 3: Chờ consumers nhận hết (dừng khi không còn message mới trong 1s, tối đa 10s)
+    let drain_deadline = Instant::now() + Duration::from_secs(10);
+    let mut last_received = stats.received.load(Ordering::Relaxed);
+    while Instant::now() < drain_deadline {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let received = stats.received.load(Ordering::Relaxed);
+        if received == last_received {
+            break;
+        }
+        last_received = received;
+    }
+    // Không tính thời gian chờ drain: dừng đồng hồ ở message cuối cùng
+    let consume_secs = (stats.last_received_us.load(Ordering::Relaxed) - started_us).max(0) as f64 / 1_000_000.0;
+
+    stop.store(true, Ordering::Relaxed);
+    for handle in consumer_handles {
+        let _ = handle.await;
+    }
+
+    if let Some(queue_name) = shared_queue {
+        setup
+            .queue_delete(&queue_name, QueueDeleteOptions::default())
+            .await?;
+    }
+
+    let sent = sent.load(Ordering::Relaxed);
+    let received = stats.received.load(Ordering::Relaxed);
+    let mut latencies = std::mem::take(&mut *stats.latencies.lock().unwrap());
+
+    Ok(BenchReport {
+        exchange: config.exchange.clone(),
+        exchange_type: exchange_type_name(&config.exchange_kind),
+        producers: config.producers,
+        consumers: config.consumers,
+        message_size: config.message_size,
+        sent,
+        received,
+        publish_secs,
+        publish_rate: sent as f64 / publish_secs.max(f64::EPSILON),
+        consume_rate: received as f64 / consume_secs.max(f64::EPSILON),
+        latency: LatencyStats::from_samples(&mut latencies),
+    })
+}
+
+async fn declare_bound_queue(channel: &lapin::Channel, config: &BenchConfig) -> LapinResult<String> {
+    let queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: !config.shared_queue,
+                auto_delete: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    let queue_name = queue.name().to_string();
+
+    for binding_key in &config.binding_keys {
+        channel
+            .queue_bind(
+                &queue_name,
+                &config.exchange,
+                binding_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+    }
+
+    Ok(queue_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_exchange_binds_routing_keys_instead_of_wildcards() {
+        let config = BenchConfig {
+            exchange_kind: ExchangeKind::Direct,
+            ..Default::default()
+        };
+        assert_eq!(config.effective_binding_keys(), vec!["bench.event".to_string()]);
+
+        let topic = BenchConfig::default();
+        assert_eq!(topic.effective_binding_keys(), vec!["bench.#".to_string()]);
+
+        let exact = BenchConfig {
+            exchange_kind: ExchangeKind::Direct,
+            binding_keys: vec!["a".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(exact.effective_binding_keys(), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let mut samples: Vec<u64> = (1..=100).rev().collect();
+        let stats = LatencyStats::from_samples(&mut samples);
+        assert_eq!((stats.min_us, stats.p50_us, stats.p90_us, stats.p99_us, stats.max_us), (1, 50, 90, 99, 100));
+        assert_eq!(stats.mean_us, 50);
+        assert_eq!(LatencyStats::from_samples(&mut []).max_us, 0);
+    }
+}
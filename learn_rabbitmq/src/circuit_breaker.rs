@@ -0,0 +1,461 @@
+// Circuit breaker cho consumer: dependency của handler (DB, HTTP API...) chết → ngừng kéo message
+// thay vì nhận rồi fail từng cái (retry vô ích, message bị reject/dead-letter oan).
+//
+//   Closed ──(tỉ lệ lỗi ≥ failure_rate trong `window` lần gần nhất)──▶ Open
+//   Open ──(sau open_for)──▶ HalfOpen: cho `half_open_probes` message chạy thử
+//   HalfOpen ──(tất cả probe OK)──▶ Closed      HalfOpen ──(1 probe lỗi)──▶ Open
+//
+// Chỉ Failed / Timeout / Panic tính là lỗi (lỗi của dependency). Decode / Reject / Invalid là lỗi
+// của message → không làm mở breaker.
+//
+//   let breaker = Arc::new(CircuitBreaker::new("orders", BreakerConfig::default()));
+//   let pipeline = Pipeline::builder()
+//       .layer(LoggingLayer::new("orders"))
+//       .layer(CircuitBreakerLayer::new(breaker.clone()))   // sau RetryLayer nếu có → 1 lần gọi = 1 kết quả
+//       .json(handler);
+//   BreakerConsumer::new(channel, "orders", "orders_worker", pipeline, breaker).run().await?;
+//
+// BreakerConsumer mở → basic_cancel: message nằm lại trong queue (consumer khác vẫn nhận được).
+// Message đã prefetch về trước khi cancel → HandlerError::CircuitOpen → nack + requeue.
+// RabbitMQ không hỗ trợ channel.flow từ phía client để dừng delivery → dùng basic_cancel / basic_consume.
+
+use crate::event_bus::FailurePolicy;
+use crate::middleware::{BoxHandler, HandlerError, HandlerResult, Layer, Pipeline};
+use futures::{FutureExt, StreamExt};
+use lapin::{options::*, types::FieldTable, Channel, Result as LapinResult};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerState::Closed => write!(f, "closed"),
+            BreakerState::Open => write!(f, "open"),
+            BreakerState::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakerConfig {
+    // Số kết quả gần nhất dùng để tính tỉ lệ lỗi
+    pub window: usize,
+    // Chưa đủ số lần gọi này trong window → không mở (tránh mở vì 1/1 lỗi)
+    pub min_calls: usize,
+    // 0.0 - 1.0
+    pub failure_rate: f64,
+    // Thời gian Open trước khi chuyển HalfOpen
+    pub open_for: Duration,
+    // Số message chạy thử ở HalfOpen (cũng là prefetch khi HalfOpen)
+    pub half_open_probes: u32,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            window: 20,
+            min_calls: 10,
+            failure_rate: 0.5,
+            open_for: Duration::from_secs(30),
+            half_open_probes: 3,
+        }
+    }
+}
+
+struct BreakerInner {
+    state: BreakerState,
+    // true = lỗi
+    outcomes: VecDeque<bool>,
+    opened_at: Instant,
+    probes_started: u32,
+    probes_succeeded: u32,
+}
+
+pub struct CircuitBreaker {
+    name: String,
+    config: BreakerConfig,
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            name: name.to_string(),
+            config: BreakerConfig {
+                window: config.window.max(1),
+                min_calls: config.min_calls.max(1),
+                half_open_probes: config.half_open_probes.max(1),
+                ..config
+            },
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                outcomes: VecDeque::new(),
+                opened_at: Instant::now(),
+                probes_started: 0,
+                probes_succeeded: 0,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &BreakerConfig {
+        &self.config
+    }
+
+    // Open mà đã hết open_for → chuyển luôn HalfOpen
+    pub fn state(&self) -> BreakerState {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        inner.state
+    }
+
+    // Tỉ lệ lỗi hiện tại trong window
+    pub fn failure_rate(&self) -> f64 {
+        let inner = self.inner.lock().unwrap();
+        if inner.outcomes.is_empty() {
+            return 0.0;
+        }
+        inner.outcomes.iter().filter(|failed| **failed).count() as f64 / inner.outcomes.len() as f64
+    }
+
+    // Còn bao lâu nữa thì HalfOpen (Duration::ZERO nếu không Open)
+    pub fn retry_in(&self) -> Duration {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        match inner.state {
+            BreakerState::Open => self.config.open_for.saturating_sub(inner.opened_at.elapsed()),
+            _ => Duration::ZERO,
+        }
+    }
+
+    // Có được chạy handler không. HalfOpen: chỉ `half_open_probes` lần đầu
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen if inner.probes_started < self.config.half_open_probes => {
+                inner.probes_started += 1;
+                true
+            }
+            BreakerState::HalfOpen => false,
+        }
+    }
+
+    // Lỗi của dependency (tính vào tỉ lệ lỗi) hay lỗi của message
+    pub fn is_failure(result: &HandlerResult) -> bool {
+        matches!(
+            result,
+            Err(HandlerError::Failed(_) | HandlerError::Timeout(_) | HandlerError::Panic(_))
+        )
+    }
+
+    pub fn record(&self, failed: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        match inner.state {
+            BreakerState::Closed => {
+                inner.outcomes.push_back(failed);
+                while inner.outcomes.len() > self.config.window {
+                    inner.outcomes.pop_front();
+                }
+                let failures = inner.outcomes.iter().filter(|failed| **failed).count();
+                let calls = inner.outcomes.len();
+                if calls >= self.config.min_calls && failures as f64 / calls as f64 >= self.config.failure_rate {
+                    println!(
+                        "⚠️  [{}] circuit breaker OPEN: {}/{} recent calls failed, pausing for {:?}",
+                        self.name, failures, calls, self.config.open_for
+                    );
+                    self.transition(&mut inner, BreakerState::Open);
+                }
+            }
+            BreakerState::HalfOpen if failed => {
+                println!("⚠️  [{}] probe failed, circuit breaker OPEN again", self.name);
+                self.transition(&mut inner, BreakerState::Open);
+            }
+            BreakerState::HalfOpen => {
+                inner.probes_succeeded += 1;
+                if inner.probes_succeeded >= self.config.half_open_probes {
+                    println!("✓ [{}] circuit breaker CLOSED: probes succeeded", self.name);
+                    self.transition(&mut inner, BreakerState::Closed);
+                }
+            }
+            // Kết quả của message chạy trước khi mở → bỏ qua
+            BreakerState::Open => {}
+        }
+    }
+
+    fn refresh(&self, inner: &mut BreakerInner) {
+        if inner.state == BreakerState::Open && inner.opened_at.elapsed() >= self.config.open_for {
+            println!("ℹ️  [{}] circuit breaker HALF-OPEN: probing", self.name);
+            self.transition(inner, BreakerState::HalfOpen);
+        }
+    }
+
+    fn transition(&self, inner: &mut BreakerInner, state: BreakerState) {
+        inner.state = state;
+        inner.outcomes.clear();
+        inner.probes_started = 0;
+        inner.probes_succeeded = 0;
+        if state == BreakerState::Open {
+            inner.opened_at = Instant::now();
+        }
+    }
+}
+
+// Không cho handler chạy khi breaker mở (→ HandlerError::CircuitOpen → requeue), ghi nhận kết quả
+pub struct CircuitBreakerLayer {
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(breaker: Arc<CircuitBreaker>) -> Self {
+        CircuitBreakerLayer { breaker }
+    }
+}
+
+impl Layer for CircuitBreakerLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let breaker = self.breaker.clone();
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            let breaker = breaker.clone();
+            async move {
+                if !breaker.allow() {
+                    return Err(HandlerError::CircuitOpen);
+                }
+                let result = inner(ctx).await;
+                breaker.record(CircuitBreaker::is_failure(&result));
+                result
+            }
+            .boxed()
+        })
+    }
+}
+
+// Consume `queue` qua pipeline (phải có CircuitBreakerLayer cùng breaker):
+//   Closed → basic_qos(prefetch) + basic_consume
+//   Open → basic_cancel, chờ open_for
+//   HalfOpen → basic_qos(half_open_probes) + basic_consume, probe xong → Closed hoặc Open lại
+pub struct BreakerConsumer {
+    channel: Channel,
+    queue: String,
+    consumer_tag: String,
+    prefetch: u16,
+    pipeline: Pipeline,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl BreakerConsumer {
+    pub fn new(channel: Channel, queue: &str, consumer_tag: &str, pipeline: Pipeline, breaker: Arc<CircuitBreaker>) -> Self {
+        BreakerConsumer {
+            channel,
+            queue: queue.to_string(),
+            consumer_tag: consumer_tag.to_string(),
+            prefetch: 10,
+            pipeline,
+            breaker,
+        }
+    }
+
+    pub fn with_prefetch(mut self, prefetch: u16) -> Self {
+        self.prefetch = prefetch.max(1);
+        self
+    }
+
+    pub fn breaker(&self) -> Arc<CircuitBreaker> {
+        self.breaker.clone()
+    }
+
+    // Chạy tới khi consumer bị đóng từ ngoài (channel đóng, queue bị xóa...)
+    pub async fn run(&self) -> LapinResult<()> {
+        loop {
+            let wait = self.breaker.retry_in();
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+                continue;
+            }
+
+            let state = self.breaker.state();
+            let prefetch = match state {
+                BreakerState::HalfOpen => self.breaker.config().half_open_probes.min(u16::MAX as u32) as u16,
+                _ => self.prefetch,
+            };
+            self.channel.basic_qos(prefetch, BasicQosOptions::default()).await?;
+            let mut consumer = self
+                .channel
+                .basic_consume(
+                    &self.queue,
+                    &self.consumer_tag,
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            // Xử lý tới khi breaker đổi trạng thái
+            loop {
+                match consumer.next().await {
+                    Some(delivery) => {
+                        self.pipeline.handle(delivery?).await?;
+                    }
+                    None => return Ok(()),
+                }
+                if self.breaker.state() != state {
+                    break;
+                }
+            }
+
+            self.channel
+                .basic_cancel(&self.consumer_tag, BasicCancelOptions::default())
+                .await?;
+            // Message đã prefetch: Open → CircuitOpen → requeue; Closed → xử lý bình thường
+            let mut requeued = 0;
+            while let Some(delivery) = consumer.next().await {
+                if self.pipeline.handle(delivery?).await? == Some(FailurePolicy::Requeue) {
+                    requeued += 1;
+                }
+            }
+            if requeued > 0 {
+                println!("ℹ️  {} prefetched message(s) returned to '{}'", requeued, self.queue);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::message::Delivery;
+
+    fn breaker(open_for: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "test",
+            BreakerConfig {
+                window: 4,
+                min_calls: 4,
+                failure_rate: 0.5,
+                open_for,
+                half_open_probes: 2,
+            },
+        )
+    }
+
+    fn open(breaker: &CircuitBreaker) {
+        for _ in 0..4 {
+            breaker.record(true);
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+    }
+
+    #[test]
+    fn opens_only_after_min_calls_at_failure_rate() {
+        let breaker = breaker(Duration::from_secs(60));
+        breaker.record(true);
+        breaker.record(true);
+        breaker.record(true);
+        // 3/3 lỗi nhưng chưa đủ min_calls
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.allow());
+
+        breaker.record(false);
+        // 3/4 ≥ 0.5
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.allow());
+        assert!(breaker.retry_in() > Duration::from_secs(59));
+        // Window đã reset khi chuyển trạng thái
+        assert_eq!(breaker.failure_rate(), 0.0);
+    }
+
+    #[test]
+    fn window_forgets_old_failures() {
+        let breaker = breaker(Duration::from_secs(60));
+        breaker.record(true);
+        for _ in 0..4 {
+            breaker.record(false);
+        }
+        breaker.record(true);
+        // Window = [ok, ok, ok, lỗi] → 0.25
+        assert_eq!(breaker.failure_rate(), 0.25);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn half_open_closes_after_all_probes_succeed() {
+        let breaker = breaker(Duration::from_millis(20));
+        open(&breaker);
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert_eq!(breaker.retry_in(), Duration::ZERO);
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+        // Chỉ `half_open_probes` message chạy thử
+        assert!(!breaker.allow());
+
+        breaker.record(false);
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        breaker.record(false);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = breaker(Duration::from_millis(20));
+        open(&breaker);
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert!(breaker.allow());
+        breaker.record(false);
+        breaker.record(true);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.allow());
+        assert!(breaker.retry_in() > Duration::ZERO);
+    }
+
+    #[test]
+    fn only_dependency_errors_count_as_failures() {
+        assert!(CircuitBreaker::is_failure(&Err(HandlerError::Failed("db".to_string()))));
+        assert!(CircuitBreaker::is_failure(&Err(HandlerError::Timeout(Duration::from_secs(1)))));
+        assert!(CircuitBreaker::is_failure(&Err(HandlerError::Panic("boom".to_string()))));
+        assert!(!CircuitBreaker::is_failure(&Err(HandlerError::Decode("bad json".to_string()))));
+        assert!(!CircuitBreaker::is_failure(&Err(HandlerError::Reject("no".to_string()))));
+        assert!(!CircuitBreaker::is_failure(&Ok(())));
+    }
+
+    // Breaker mở → handler không chạy, message được requeue (kể cả khi redelivered)
+    #[tokio::test]
+    async fn layer_requeues_while_open() {
+        let breaker = Arc::new(breaker(Duration::from_secs(60)));
+        let pipeline = Pipeline::builder()
+            .layer(CircuitBreakerLayer::new(breaker.clone()))
+            .handler(|_ctx| async { Err(HandlerError::Failed("dependency down".to_string())) });
+        let delivery = || Delivery {
+            delivery_tag: 1,
+            exchange: "".into(),
+            routing_key: "orders".into(),
+            redelivered: true,
+            properties: Default::default(),
+            data: b"{}".to_vec(),
+            acker: Default::default(),
+        };
+
+        for _ in 0..4 {
+            let (_, result) = pipeline.call(delivery()).await;
+            assert!(matches!(result, Err(HandlerError::Failed(_))));
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+        let (_, result) = pipeline.call(delivery()).await;
+        assert!(matches!(result, Err(HandlerError::CircuitOpen)));
+        assert_eq!(pipeline.handle(delivery()).await.unwrap(), Some(FailurePolicy::Requeue));
+    }
+}
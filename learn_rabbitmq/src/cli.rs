@@ -0,0 +1,570 @@
+// Command line: không có subcommand → chạy các examples trong main() như trước
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "learn_rabbitmq", about = "🐰 RabbitMQ Learning Examples & tools")]
+pub struct Cli {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Override cấu hình connection trong RABBITMQ_CONFIG (dùng được với mọi subcommand)
+#[derive(Args, Debug)]
+pub struct ConnectionArgs {
+    /// JSON config file (default: $RABBITMQ_CONFIG_FILE, then built-in defaults)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Accept a config file whose url contains a password
+    #[arg(long, global = true)]
+    pub allow_inline_password: bool,
+
+    /// Broker URL, amqp:// or amqps:// (credentials: $RABBITMQ_USERNAME / $RABBITMQ_PASSWORD)
+    #[arg(long, global = true)]
+    pub url: Option<String>,
+
+    /// PEM bundle of trusted CA certificates (default: system roots)
+    #[arg(long, global = true)]
+    pub tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS
+    #[arg(long, global = true, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for the client certificate
+    #[arg(long, global = true, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Authenticate with SASL EXTERNAL (identity from the client certificate)
+    #[arg(long, global = true, requires = "tls_cert")]
+    pub tls_external_auth: bool,
+
+    /// Accept a trusted certificate even if it does not match the hostname
+    #[arg(long, global = true)]
+    pub tls_no_verify_hostname: bool,
+
+    /// Server name for SNI and hostname verification (default: URL host)
+    #[arg(long, global = true)]
+    pub tls_server_name: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Benchmark direct / topic / fanout routing under load
+    Bench(BenchArgs),
+    /// Local TLS-terminating stand-in in front of a plain-text broker
+    TlsProxy(TlsProxyArgs),
+    /// Record deliveries from an exchange (or queue) to an NDJSON file
+    Record(RecordArgs),
+    /// Publish a recorded NDJSON file again
+    Replay(ReplayArgs),
+    /// Live view of the traffic on an exchange
+    Tail(TailArgs),
+    /// Show messages in a queue without consuming them
+    Peek(PeekArgs),
+    /// Move selected messages from one queue to another
+    Move(MoveArgs),
+    /// Delete all messages in a queue
+    Purge(PurgeArgs),
+    /// Inspect broker state through the management HTTP API
+    Mgmt(MgmtArgs),
+    /// Draw exchanges, queues and bindings as a Graphviz or Mermaid diagram
+    Topology(TopologyArgs),
+    /// Publish configured messages on a cron schedule
+    Schedule(ScheduleArgs),
+    /// Generate a signing or encryption key file for message envelopes
+    Keygen(KeygenArgs),
+    /// HTTP bridge: publish over HTTP, forward a queue to a webhook, or run a local test receiver
+    Webhook(WebhookArgs),
+    /// Move messages from a queue to an exchange on another vhost or broker (ack after confirm)
+    Shovel(ShovelArgs),
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ExchangeType {
+    Direct,
+    Topic,
+    Fanout,
+}
+
+impl ExchangeType {
+    pub fn kind(self) -> lapin::ExchangeKind {
+        match self {
+            ExchangeType::Direct => lapin::ExchangeKind::Direct,
+            ExchangeType::Topic => lapin::ExchangeKind::Topic,
+            ExchangeType::Fanout => lapin::ExchangeKind::Fanout,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum KeyAlgorithm {
+    #[value(name = "hmac-sha256")]
+    HmacSha256,
+    Ed25519,
+    #[value(name = "aes-256-gcm")]
+    Aes256Gcm,
+    #[value(name = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+impl KeyAlgorithm {
+    pub fn algorithm(self) -> learn_rabbitmq::envelope::Algorithm {
+        use learn_rabbitmq::envelope::Algorithm;
+        match self {
+            KeyAlgorithm::HmacSha256 => Algorithm::HmacSha256,
+            KeyAlgorithm::Ed25519 => Algorithm::Ed25519,
+            KeyAlgorithm::Aes256Gcm => Algorithm::Aes256Gcm,
+            KeyAlgorithm::ChaCha20Poly1305 => Algorithm::ChaCha20Poly1305,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum DiagramFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Exchange type to benchmark
+    #[arg(long = "type", value_enum, default_value = "topic")]
+    pub exchange_type: ExchangeType,
+
+    /// Exchange name (default: bench_<type>)
+    #[arg(long)]
+    pub exchange: Option<String>,
+
+    /// Routing keys used by producers, round-robin (repeatable)
+    #[arg(long = "routing-key", default_value = "bench.event")]
+    pub routing_keys: Vec<String>,
+
+    /// Binding keys for every consumer queue (repeatable)
+    #[arg(long = "binding-key", default_value = "bench.#")]
+    pub binding_keys: Vec<String>,
+
+    /// Number of concurrent producers
+    #[arg(short, long, default_value_t = 1)]
+    pub producers: usize,
+
+    /// Number of concurrent consumers
+    #[arg(short, long, default_value_t = 1)]
+    pub consumers: usize,
+
+    /// Consumers compete on one shared queue instead of one queue each
+    #[arg(long)]
+    pub shared_queue: bool,
+
+    /// Message body size in bytes
+    #[arg(short = 's', long, default_value_t = 256)]
+    pub message_size: usize,
+
+    /// Messages per second per producer (0 = unlimited)
+    #[arg(short, long, default_value_t = 0)]
+    pub rate: u64,
+
+    /// How long producers publish, in seconds
+    #[arg(short, long, default_value_t = 10)]
+    pub duration: u64,
+
+    /// Report format
+    #[arg(short, long, value_enum, default_value = "table")]
+    pub output: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct TlsProxyArgs {
+    /// Address to accept amqps:// connections on
+    #[arg(long, default_value = "127.0.0.1:5671")]
+    pub listen: String,
+
+    /// Plain-text broker to forward to
+    #[arg(long, default_value = "127.0.0.1:5672")]
+    pub upstream: String,
+
+    /// PEM server certificate (chain)
+    #[arg(long)]
+    pub cert: PathBuf,
+
+    /// PEM server private key
+    #[arg(long)]
+    pub key: PathBuf,
+
+    /// Require client certificates signed by this CA (mutual TLS)
+    #[arg(long)]
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct RecordArgs {
+    /// Exchange to record (a temporary exclusive queue is bound to it)
+    #[arg(required_unless_present = "queue", conflicts_with = "queue")]
+    pub exchange: Option<String>,
+
+    /// Binding keys for the temporary queue (repeatable). Without -k the exchange type is looked up
+    /// via the management API: "#" on topic, everything on fanout/headers; direct exchanges need -k
+    #[arg(short = 'k', long = "binding-key")]
+    pub binding_keys: Vec<String>,
+
+    /// Dump an existing queue instead; messages are never acked and return to the queue on exit
+    #[arg(long)]
+    pub queue: Option<String>,
+
+    /// Output file (default: stdout)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Stop after this many messages
+    #[arg(short = 'n', long)]
+    pub count: Option<usize>,
+
+    /// Stop after this many seconds
+    #[arg(short, long)]
+    pub duration: Option<u64>,
+
+    /// Stop when no message arrives for this many seconds (default for --queue: 2)
+    #[arg(long)]
+    pub idle_timeout: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// NDJSON recording to publish
+    pub input: PathBuf,
+
+    /// Publish to this exchange instead of the recorded one
+    #[arg(short, long)]
+    pub exchange: Option<String>,
+
+    /// Only replay messages whose routing key matches this regex
+    #[arg(short, long)]
+    pub filter: Option<String>,
+
+    /// Rewrite routing keys, REGEX=REPLACEMENT (e.g. '^order\.(.*)=audit.order.$1')
+    #[arg(long)]
+    pub rewrite: Option<String>,
+
+    /// Messages per second (0 = as fast as possible)
+    #[arg(short, long, default_value_t = 0)]
+    pub rate: u64,
+
+    /// Print what would be published without publishing
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Keep the recorded user_id (the broker rejects it unless it matches the replaying user)
+    #[arg(long)]
+    pub keep_user_id: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TailArgs {
+    /// Exchange to watch
+    pub exchange: String,
+
+    /// Binding pattern for the temporary queue (repeatable). Without -p the exchange type is looked up
+    /// via the management API: "#" on topic, everything on fanout/headers; direct exchanges need -p
+    #[arg(short, long = "pattern")]
+    pub patterns: Vec<String>,
+
+    /// Only show routing keys matching this regex
+    #[arg(short = 'k', long)]
+    pub routing_key: Option<String>,
+
+    /// jq-like filter on the JSON body, e.g. '.id > 100', '.content ~ ^Payment' (repeatable, all must match)
+    #[arg(short, long = "filter")]
+    pub filters: Vec<String>,
+
+    /// Also print message properties and headers
+    #[arg(short = 'P', long)]
+    pub properties: bool,
+
+    /// Print JSON bodies on a single line
+    #[arg(short, long)]
+    pub compact: bool,
+
+    /// Exit after showing this many messages
+    #[arg(short = 'n', long)]
+    pub count: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct PeekArgs {
+    /// Queue to look into
+    pub queue: String,
+
+    /// Number of messages to show (from the head of the queue)
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub count: usize,
+
+    /// Also print message properties and headers
+    #[arg(short = 'P', long)]
+    pub properties: bool,
+
+    /// Print JSON bodies on a single line
+    #[arg(short, long)]
+    pub compact: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct MoveArgs {
+    /// Queue to take messages from
+    pub source: String,
+
+    /// Queue to move messages to (published via the default exchange)
+    pub destination: String,
+
+    /// Only move routing keys matching this regex
+    #[arg(short = 'k', long)]
+    pub routing_key: Option<String>,
+
+    /// jq-like filter on the JSON body, e.g. '.id > 100' (repeatable, all must match)
+    #[arg(short, long = "filter")]
+    pub filters: Vec<String>,
+
+    /// Stop after moving this many messages
+    #[arg(short = 'n', long)]
+    pub count: Option<usize>,
+
+    /// Only count matching messages, leave both queues untouched
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PurgeArgs {
+    /// Queue to purge
+    pub queue: String,
+
+    /// Only print how many messages would be deleted
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Do not ask for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+// Management API dùng chung cho `mgmt` và `topology`
+#[derive(Args, Debug)]
+pub struct ManagementArgs {
+    /// Management API base URL (default: $RABBITMQ_MANAGEMENT_URL, then http://<broker host>:15672)
+    #[arg(long, global = true)]
+    pub management_url: Option<String>,
+
+    /// Only show this vhost (default: the vhost in the broker URL)
+    #[arg(long, global = true, conflicts_with = "all_vhosts")]
+    pub vhost: Option<String>,
+
+    /// Show every vhost the user can see
+    #[arg(short = 'A', long, global = true)]
+    pub all_vhosts: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct MgmtArgs {
+    #[command(subcommand)]
+    pub resource: MgmtResource,
+
+    #[command(flatten)]
+    pub target: ManagementArgs,
+
+    /// Output format
+    #[arg(short, long, value_enum, global = true, default_value = "table")]
+    pub output: OutputFormat,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy)]
+pub enum MgmtResource {
+    /// Cluster name, versions and totals
+    Overview,
+    /// Virtual hosts with message counts
+    Vhosts,
+    /// Exchanges with type and flags
+    Exchanges,
+    /// Queues with depth (ready / unacked) and consumer counts
+    Queues,
+    /// Bindings (source exchange → destination, routing key)
+    Bindings,
+    /// Client connections
+    Connections,
+}
+
+#[derive(Args, Debug)]
+pub struct TopologyArgs {
+    /// Definitions file (management export / `GET /api/definitions`) instead of querying the broker
+    #[arg(short, long)]
+    pub definitions: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub target: ManagementArgs,
+
+    /// Diagram format
+    #[arg(short, long, value_enum, default_value = "mermaid")]
+    pub format: DiagramFormat,
+
+    /// Output file (default: stdout)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Also draw the default exchange, amq.* exchanges and their implicit bindings
+    #[arg(long)]
+    pub include_builtins: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ScheduleArgs {
+    /// Schedule file with the jobs to run (see schedule.example.json)
+    pub file: PathBuf,
+
+    /// Print the next runs of each job without connecting
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Number of upcoming runs per job shown by --dry-run
+    #[arg(short = 'n', long, default_value_t = 3)]
+    pub count: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct KeygenArgs {
+    /// Key algorithm
+    #[arg(value_enum)]
+    pub algorithm: KeyAlgorithm,
+
+    /// Key id, sent in message headers so consumers can pick the key (e.g. 2024-06)
+    pub key_id: String,
+
+    /// Key directory
+    #[arg(short, long, default_value = "keys")]
+    pub dir: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct WebhookArgs {
+    #[command(subcommand)]
+    pub mode: WebhookMode,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum WebhookMode {
+    /// Accept POST /exchanges/{name}/publish?routing_key=... and publish with confirms
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Only allow these exchanges (repeatable; "amq.default" = default exchange). Default: any
+        #[arg(short, long = "exchange")]
+        exchanges: Vec<String>,
+    },
+    /// Consume a queue and POST every message to a webhook URL
+    Sink {
+        /// Queue to consume
+        queue: String,
+
+        /// Webhook URL
+        url: String,
+
+        /// Sign requests with this key id (from --keys)
+        #[arg(long)]
+        key_id: Option<String>,
+
+        /// Signature algorithm
+        #[arg(long, value_enum, default_value = "hmac-sha256")]
+        algorithm: KeyAlgorithm,
+
+        /// Key directory
+        #[arg(long, default_value = "keys")]
+        keys: PathBuf,
+
+        /// Attempts per message (including the first) for 5xx / 429 / network errors
+        #[arg(long, default_value_t = 5)]
+        attempts: u32,
+
+        /// Delay before the first retry in ms, doubled on every retry
+        #[arg(long, default_value_t = 500)]
+        backoff_ms: u64,
+
+        /// Requeue instead of dead-lettering when retries are exhausted
+        #[arg(long)]
+        requeue: bool,
+
+        /// Unacked messages in flight
+        #[arg(long, default_value_t = 1)]
+        prefetch: u16,
+    },
+    /// Local stand-in receiver that prints requests (for trying out `webhook sink`)
+    Receive {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:9000")]
+        listen: String,
+
+        /// Verify signatures with the keys in this directory
+        #[arg(long)]
+        keys: Option<PathBuf>,
+
+        /// Allowed clock skew for signed requests, in seconds
+        #[arg(long, default_value_t = 300)]
+        tolerance_secs: u64,
+
+        /// Answer 503 to the first N requests to exercise retries
+        #[arg(long, default_value_t = 0)]
+        fail_first: u64,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ShovelArgs {
+    /// Source queue (on --url / --config)
+    pub queue: String,
+
+    /// Destination broker URL, e.g. amqp://new-cluster/orders (default: the --dest-config url)
+    #[arg(long, required_unless_present = "dest_config")]
+    pub dest_url: Option<String>,
+
+    /// JSON config file for the destination (credentials, TLS). Default: same settings as the source
+    #[arg(long)]
+    pub dest_config: Option<PathBuf>,
+
+    /// Destination exchange ("" = default exchange)
+    #[arg(short, long, default_value = "")]
+    pub exchange: String,
+
+    /// Publish every message with this routing key
+    #[arg(short = 'k', long, conflicts_with = "rewrites")]
+    pub routing_key: Option<String>,
+
+    /// Rewrite routing keys, REGEX=REPLACEMENT (repeatable, first match wins), e.g. '^order\.(.*)$=orders.$1'
+    #[arg(short, long = "rewrite")]
+    pub rewrites: Vec<String>,
+
+    /// Unacked messages from the source
+    #[arg(long, default_value_t = 100)]
+    pub prefetch: u16,
+
+    /// Messages published per confirm round
+    #[arg(long, default_value_t = 50)]
+    pub batch: usize,
+
+    /// Stop once the source queue has been idle this many seconds
+    #[arg(long)]
+    pub idle_exit: Option<u64>,
+
+    /// Stop after moving this many messages
+    #[arg(short = 'n', long)]
+    pub max: Option<u64>,
+}
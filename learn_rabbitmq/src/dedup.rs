@@ -0,0 +1,512 @@
+// Idempotent consumer - chống xử lý trùng khi message bị redeliver
+// (vd: consumer crash TRƯỚC khi `delivery.ack` → RabbitMQ gửi lại message).
+// Publisher đặt `message_id`, consumer "claim" message_id trong dedup store trước khi chạy handler:
+//   - claim thành công → id ở trạng thái in-progress (có lease) → chạy handler
+//     → thành công: đánh dấu done rồi ack; lỗi: bỏ claim, nack + requeue
+//   - đã done trong cửa sổ TTL → duplicate → ack luôn, KHÔNG chạy handler
+//   - đang in-progress (lease còn hạn) → consumer khác đang xử lý, hoặc vừa crash giữa handler
+//     → requeue, KHÔNG ack. Lease hết hạn thì id claim lại được, nên crash không làm mất message.
+
+use crate::util::now_millis;
+use lapin::{
+    message::Delivery,
+    options::{BasicAckOptions, BasicNackOptions},
+    Result as LapinResult,
+};
+use lru::LruCache;
+use rusqlite::{params, Connection as SqliteConnection};
+use serde::Serialize;
+use std::fmt::Display;
+use std::future::Future;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    // Claim được (id mới, done đã hết TTL, hoặc in-progress đã hết lease) → chạy handler
+    Claimed,
+    // Đã xử lý xong trong cửa sổ TTL
+    Duplicate,
+    // Đang được xử lý, lease chưa hết hạn
+    InProgress,
+}
+
+pub trait DedupStore: Send + Sync {
+    // Claim `message_id` ở trạng thái in-progress trong `lease`
+    fn try_claim(&self, message_id: &str, lease: Duration) -> io::Result<Claim>;
+
+    // Handler thành công → đánh dấu done, cửa sổ TTL tính từ lúc này
+    fn complete(&self, message_id: &str) -> io::Result<()>;
+
+    // Bỏ claim (handler lỗi) để lần redeliver sau được xử lý lại
+    fn release(&self, message_id: &str) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ClaimState {
+    InProgress { lease_until: Instant },
+    Done { at: Instant },
+}
+
+// In-memory LRU + TTL: nhanh, nhưng mất khi restart và không share giữa các process
+pub struct MemoryDedupStore {
+    seen: Mutex<LruCache<String, ClaimState>>,
+    ttl: Duration,
+}
+
+impl MemoryDedupStore {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        MemoryDedupStore {
+            seen: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl DedupStore for MemoryDedupStore {
+    fn try_claim(&self, message_id: &str, lease: Duration) -> io::Result<Claim> {
+        let mut seen = self.seen.lock().unwrap();
+        let now = Instant::now();
+        match seen.get(message_id) {
+            Some(ClaimState::Done { at }) if now.duration_since(*at) < self.ttl => {
+                return Ok(Claim::Duplicate);
+            }
+            Some(ClaimState::InProgress { lease_until }) if now < *lease_until => {
+                return Ok(Claim::InProgress);
+            }
+            _ => {}
+        }
+        seen.put(
+            message_id.to_string(),
+            ClaimState::InProgress {
+                lease_until: now + lease,
+            },
+        );
+        Ok(Claim::Claimed)
+    }
+
+    fn complete(&self, message_id: &str) -> io::Result<()> {
+        self.seen
+            .lock()
+            .unwrap()
+            .put(message_id.to_string(), ClaimState::Done { at: Instant::now() });
+        Ok(())
+    }
+
+    fn release(&self, message_id: &str) -> io::Result<()> {
+        self.seen.lock().unwrap().pop(message_id);
+        Ok(())
+    }
+}
+
+// SQLite: giữ được qua restart, nhiều consumer process có thể dùng chung 1 file
+pub struct SqliteDedupStore {
+    conn: Mutex<SqliteConnection>,
+    ttl: Duration,
+}
+
+impl SqliteDedupStore {
+    pub fn open(path: impl AsRef<Path>, ttl: Duration) -> io::Result<Self> {
+        let conn = SqliteConnection::open(path).map_err(sqlite_error)?;
+        conn.busy_timeout(Duration::from_secs(5)).map_err(sqlite_error)?;
+        // lease_until_ms: NULL = done, có giá trị = in-progress đến thời điểm đó
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS processed_messages (
+                message_id TEXT PRIMARY KEY,
+                claimed_at_ms INTEGER NOT NULL,
+                lease_until_ms INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS processed_messages_claimed_at
+                ON processed_messages (claimed_at_ms);",
+        )
+        .map_err(sqlite_error)?;
+
+        Ok(SqliteDedupStore {
+            conn: Mutex::new(conn),
+            ttl,
+        })
+    }
+
+    // Xóa các id done đã hết TTL và in-progress đã hết lease. Trả về số dòng đã xóa.
+    pub fn purge_expired(&self) -> io::Result<usize> {
+        let now = now_millis();
+        let cutoff = now - self.ttl.as_millis() as i64;
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM processed_messages
+                 WHERE (lease_until_ms IS NULL AND claimed_at_ms < ?1)
+                    OR lease_until_ms < ?2",
+                params![cutoff, now],
+            )
+            .map_err(sqlite_error)
+    }
+}
+
+impl DedupStore for SqliteDedupStore {
+    fn try_claim(&self, message_id: &str, lease: Duration) -> io::Result<Claim> {
+        let now = now_millis();
+        let cutoff = now - self.ttl.as_millis() as i64;
+        let lease_until = now + lease.as_millis() as i64;
+        let conn = self.conn.lock().unwrap();
+        // Insert mới, hoặc claim lại id done đã hết TTL / in-progress đã hết lease
+        let changed = conn
+            .execute(
+                "INSERT INTO processed_messages (message_id, claimed_at_ms, lease_until_ms) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (message_id) DO UPDATE
+                 SET claimed_at_ms = excluded.claimed_at_ms, lease_until_ms = excluded.lease_until_ms
+                 WHERE (processed_messages.lease_until_ms IS NULL AND processed_messages.claimed_at_ms < ?4)
+                    OR processed_messages.lease_until_ms < ?2",
+                params![message_id, now, lease_until, cutoff],
+            )
+            .map_err(sqlite_error)?;
+        if changed == 1 {
+            return Ok(Claim::Claimed);
+        }
+
+        let lease_until: Option<i64> = conn
+            .query_row(
+                "SELECT lease_until_ms FROM processed_messages WHERE message_id = ?1",
+                params![message_id],
+                |row| row.get(0),
+            )
+            .map_err(sqlite_error)?;
+        Ok(match lease_until {
+            None => Claim::Duplicate,
+            Some(_) => Claim::InProgress,
+        })
+    }
+
+    fn complete(&self, message_id: &str) -> io::Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE processed_messages SET lease_until_ms = NULL, claimed_at_ms = ?2
+                 WHERE message_id = ?1",
+                params![message_id, now_millis()],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn release(&self, message_id: &str) -> io::Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM processed_messages WHERE message_id = ?1",
+                params![message_id],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+}
+
+pub(crate) fn sqlite_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[derive(Debug, Default)]
+pub struct DedupMetrics {
+    processed: AtomicU64,
+    duplicates: AtomicU64,
+    in_progress: AtomicU64,
+    failed: AtomicU64,
+    missing_id: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DedupMetricsSnapshot {
+    pub processed: u64,
+    pub duplicates: u64,
+    pub in_progress: u64,
+    pub failed: u64,
+    pub missing_id: u64,
+}
+
+impl DedupMetrics {
+    pub fn snapshot(&self) -> DedupMetricsSnapshot {
+        DedupMetricsSnapshot {
+            processed: self.processed.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            in_progress: self.in_progress.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            missing_id: self.missing_id.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DedupOutcome {
+    // Handler đã chạy thành công, message được ack
+    Processed,
+    // message_id đã xử lý trong cửa sổ TTL → ack, bỏ qua handler
+    Duplicate,
+    // message_id đang được xử lý ở nơi khác (lease còn hạn) → requeue, bỏ qua handler
+    InProgress,
+    // Handler lỗi → nack + requeue, claim đã được bỏ
+    Failed(String),
+}
+
+pub struct IdempotentConsumer {
+    store: Arc<dyn DedupStore>,
+    metrics: Arc<DedupMetrics>,
+    // false: message không có message_id vẫn được xử lý (không chống trùng được)
+    require_message_id: bool,
+    // Thời gian giữ claim in-progress. Phải dài hơn thời gian chạy handler lâu nhất,
+    // nếu không consumer khác có thể claim lại khi handler vẫn đang chạy.
+    lease: Duration,
+    // Chờ trước khi requeue message đang in-progress, tránh vòng requeue liên tục
+    in_progress_delay: Duration,
+}
+
+impl IdempotentConsumer {
+    pub fn new(store: Arc<dyn DedupStore>) -> Self {
+        IdempotentConsumer {
+            store,
+            metrics: Arc::new(DedupMetrics::default()),
+            require_message_id: false,
+            lease: Duration::from_secs(30),
+            in_progress_delay: Duration::from_secs(1),
+        }
+    }
+
+    pub fn with_require_message_id(mut self, require: bool) -> Self {
+        self.require_message_id = require;
+        self
+    }
+
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    pub fn with_in_progress_delay(mut self, delay: Duration) -> Self {
+        self.in_progress_delay = delay;
+        self
+    }
+
+    pub fn metrics(&self) -> Arc<DedupMetrics> {
+        self.metrics.clone()
+    }
+
+    // Chạy `handler` tối đa 1 lần cho mỗi message_id, rồi ack/nack `delivery`
+    pub async fn handle<F, Fut, E>(&self, delivery: &Delivery, handler: F) -> LapinResult<DedupOutcome>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        let message_id = delivery
+            .properties
+            .message_id()
+            .as_ref()
+            .map(|id| id.to_string());
+
+        let claim = match &message_id {
+            Some(id) => match self.store.try_claim(id, self.lease) {
+                Ok(claim) => claim,
+                Err(e) => return self.fail(delivery, None, format!("dedup store: {}", e)).await,
+            },
+            None if self.require_message_id => {
+                self.metrics.missing_id.fetch_add(1, Ordering::Relaxed);
+                // Không có id → không thể đảm bảo idempotent, reject (không requeue)
+                delivery
+                    .nack(BasicNackOptions {
+                        requeue: false,
+                        ..Default::default()
+                    })
+                    .await?;
+                return Ok(DedupOutcome::Failed("missing message_id".to_string()));
+            }
+            None => {
+                self.metrics.missing_id.fetch_add(1, Ordering::Relaxed);
+                Claim::Claimed
+            }
+        };
+
+        match claim {
+            Claim::Claimed => {}
+            Claim::Duplicate => {
+                self.metrics.duplicates.fetch_add(1, Ordering::Relaxed);
+                delivery.ack(BasicAckOptions::default()).await?;
+                return Ok(DedupOutcome::Duplicate);
+            }
+            Claim::InProgress => {
+                self.metrics.in_progress.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(self.in_progress_delay).await;
+                delivery
+                    .nack(BasicNackOptions {
+                        requeue: true,
+                        ..Default::default()
+                    })
+                    .await?;
+                return Ok(DedupOutcome::InProgress);
+            }
+        }
+
+        match handler().await {
+            Ok(()) => {
+                // Crash trước bước này → claim hết lease rồi message được xử lý lại (at-least-once)
+                if let Some(id) = &message_id
+                    && let Err(e) = self.store.complete(id)
+                {
+                    println!("✗ Failed to mark message_id '{}' as done: {}", id, e);
+                }
+                self.metrics.processed.fetch_add(1, Ordering::Relaxed);
+                delivery.ack(BasicAckOptions::default()).await?;
+                Ok(DedupOutcome::Processed)
+            }
+            Err(e) => self.fail(delivery, message_id.as_deref(), e.to_string()).await,
+        }
+    }
+
+    async fn fail(
+        &self,
+        delivery: &Delivery,
+        claimed_id: Option<&str>,
+        error: String,
+    ) -> LapinResult<DedupOutcome> {
+        self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+        if let Some(id) = claimed_id
+            && let Err(e) = self.store.release(id)
+        {
+            println!("✗ Failed to release message_id '{}': {}", id, e);
+        }
+        delivery
+            .nack(BasicNackOptions {
+                requeue: true,
+                ..Default::default()
+            })
+            .await?;
+        Ok(DedupOutcome::Failed(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::{BasicProperties, types::ShortString};
+
+    const LEASE: Duration = Duration::from_secs(30);
+
+    fn delivery(message_id: &str) -> Delivery {
+        Delivery {
+            delivery_tag: 1,
+            exchange: ShortString::from(""),
+            routing_key: ShortString::from("tasks"),
+            redelivered: true,
+            properties: BasicProperties::default().with_message_id(message_id.into()),
+            data: b"{}".to_vec(),
+            acker: Default::default(),
+        }
+    }
+
+    fn claim_lifecycle(store: &dyn DedupStore) {
+        assert_eq!(store.try_claim("a", LEASE).unwrap(), Claim::Claimed);
+        // Chưa complete → vẫn in-progress, KHÔNG phải duplicate
+        assert_eq!(store.try_claim("a", LEASE).unwrap(), Claim::InProgress);
+        store.complete("a").unwrap();
+        assert_eq!(store.try_claim("a", LEASE).unwrap(), Claim::Duplicate);
+
+        assert_eq!(store.try_claim("b", LEASE).unwrap(), Claim::Claimed);
+        store.release("b").unwrap();
+        assert_eq!(store.try_claim("b", LEASE).unwrap(), Claim::Claimed);
+    }
+
+    fn expired_lease_is_claimable(store: &dyn DedupStore) {
+        assert_eq!(store.try_claim("crashed", Duration::ZERO).unwrap(), Claim::Claimed);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.try_claim("crashed", LEASE).unwrap(), Claim::Claimed);
+    }
+
+    fn sqlite_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("learn_rabbitmq_dedup_{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn memory_store_claims_until_completed() {
+        claim_lifecycle(&MemoryDedupStore::new(16, Duration::from_secs(60)));
+        expired_lease_is_claimable(&MemoryDedupStore::new(16, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn memory_store_done_expires_after_ttl() {
+        let store = MemoryDedupStore::new(16, Duration::ZERO);
+        assert_eq!(store.try_claim("a", LEASE).unwrap(), Claim::Claimed);
+        store.complete("a").unwrap();
+        assert_eq!(store.try_claim("a", LEASE).unwrap(), Claim::Claimed);
+    }
+
+    #[test]
+    fn sqlite_store_claims_until_completed() {
+        let path = sqlite_path("lifecycle");
+        let store = SqliteDedupStore::open(&path, Duration::from_secs(60)).unwrap();
+        claim_lifecycle(&store);
+        expired_lease_is_claimable(&store);
+        drop(store);
+
+        // Done vẫn là duplicate sau khi mở lại file
+        let store = SqliteDedupStore::open(&path, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.try_claim("a", LEASE).unwrap(), Claim::Duplicate);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn crash_mid_handler_is_not_acked_as_duplicate() {
+        let store = Arc::new(MemoryDedupStore::new(16, Duration::from_secs(60)));
+        // Consumer trước claim rồi crash giữa handler (không complete, không release)
+        assert_eq!(store.try_claim("task-1", Duration::from_millis(20)).unwrap(), Claim::Claimed);
+
+        let consumer = IdempotentConsumer::new(store.clone()).with_in_progress_delay(Duration::ZERO);
+        let outcome = consumer
+            .handle(&delivery("task-1"), || async { Ok::<(), String>(()) })
+            .await
+            .unwrap();
+        assert_eq!(outcome, DedupOutcome::InProgress);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let outcome = consumer
+            .handle(&delivery("task-1"), || async { Ok::<(), String>(()) })
+            .await
+            .unwrap();
+        assert_eq!(outcome, DedupOutcome::Processed);
+
+        let outcome = consumer
+            .handle(&delivery("task-1"), || async { Ok::<(), String>(()) })
+            .await
+            .unwrap();
+        assert_eq!(outcome, DedupOutcome::Duplicate);
+        let stats = consumer.metrics().snapshot();
+        assert_eq!((stats.processed, stats.duplicates, stats.in_progress), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn failed_handler_releases_claim() {
+        let store = Arc::new(MemoryDedupStore::new(16, Duration::from_secs(60)));
+        let consumer = IdempotentConsumer::new(store.clone());
+        let outcome = consumer
+            .handle(&delivery("task-2"), || async { Err::<(), _>("boom") })
+            .await
+            .unwrap();
+        assert_eq!(outcome, DedupOutcome::Failed("boom".to_string()));
+        assert_eq!(store.try_claim("task-2", LEASE).unwrap(), Claim::Claimed);
+    }
+}
@@ -0,0 +1,277 @@
+// Delayed delivery: publish bây giờ, consumer nhận sau `delay`
+// 2 cách:
+//   - TtlQueues (mặc định, không cần plugin): mỗi (exchange đích, delay) có 1 cặp
+//       exchange fanout + queue "delay.<exchange>.<ms>ms"  (x-message-ttl = delay, x-dead-letter-exchange = đích)
+//     Message nằm trong queue không có consumer, hết TTL thì broker dead-letter sang exchange đích
+//     với routing key GỐC. Mọi message trong 1 queue có cùng TTL → không bị kẹt sau message delay dài hơn
+//     (per-message `expiration` chỉ hết hạn ở đầu queue).
+//   - Plugin (rabbitmq_delayed_message_exchange): exchange đích có type "x-delayed-message",
+//     delay nằm trong header `x-delay`. Cần khai báo exchange bằng declare_delayed_exchange.
+//
+// ⚠️  Với TtlQueues, lỗi route ở exchange đích (không queue nào khớp) xảy ra SAU delay → publisher không biết.
+
+use crate::batch::{BatchPublisher, BatchReport, OutgoingMessage, PublishOutcome};
+use crate::bench::exchange_type_name;
+use lapin::{
+    options::*,
+    types::{AMQPValue, FieldTable, LongString},
+    Connection, ExchangeKind, Result as LapinResult,
+};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const DELAYED_EXCHANGE_TYPE: &str = "x-delayed-message";
+pub const DELAY_HEADER: &str = "x-delay";
+
+// Queue delay tự xóa sau khoảng này kể từ lần declare cuối (message cuối đã hết TTL từ lâu)
+const QUEUE_EXPIRES_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelayMode {
+    TtlQueues,
+    Plugin,
+}
+
+impl DelayMode {
+    // Plugin nếu broker có exchange type x-delayed-message, không thì TtlQueues.
+    // `probe` phải là connection RIÊNG (xem delayed_plugin_available) - không dùng lại được sau đó
+    pub async fn detect(probe: Connection) -> LapinResult<DelayMode> {
+        if delayed_plugin_available(probe).await? {
+            Ok(DelayMode::Plugin)
+        } else {
+            Ok(DelayMode::TtlQueues)
+        }
+    }
+
+    // Plugin: delay giữ nguyên (độ chính xác ms). TtlQueues: làm tròn LÊN bội số của `granularity`
+    pub fn effective_delay(self, delay: Duration, granularity: Duration) -> Duration {
+        match self {
+            DelayMode::Plugin => Duration::from_millis(delay.as_millis() as u64),
+            DelayMode::TtlQueues => {
+                let step = granularity.as_millis().max(1);
+                Duration::from_millis(delay.as_millis().div_ceil(step).saturating_mul(step) as u64)
+            }
+        }
+    }
+}
+
+// Thử declare 1 exchange x-delayed-message. Không có plugin → broker trả 503 COMMAND_INVALID,
+// lỗi ở mức CONNECTION (không phải channel) → đóng cả connection. Vì vậy nhận connection riêng,
+// mở chỉ để probe, và đóng nó khi xong
+pub async fn delayed_plugin_available(probe: Connection) -> LapinResult<bool> {
+    let channel = probe.create_channel().await?;
+    let exchange = "learn_rabbitmq.delayed_probe";
+    match declare_delayed_exchange(&channel, exchange, ExchangeKind::Direct).await {
+        Ok(()) => {
+            channel
+                .exchange_delete(exchange, ExchangeDeleteOptions::default())
+                .await?;
+            probe.close(200, "OK").await?;
+            Ok(true)
+        }
+        // Connection đã bị broker đóng, không cần close
+        Err(lapin::Error::ProtocolError(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Exchange của plugin: route như `kind` (direct/topic/...) nhưng giữ message tới khi hết `x-delay`
+pub async fn declare_delayed_exchange(
+    channel: &lapin::Channel,
+    name: &str,
+    kind: ExchangeKind,
+) -> LapinResult<()> {
+    let mut arguments = FieldTable::default();
+    arguments.insert(
+        "x-delayed-type".into(),
+        AMQPValue::LongString(LongString::from(exchange_type_name(&kind))),
+    );
+    channel
+        .exchange_declare(
+            name,
+            ExchangeKind::Custom(DELAYED_EXCHANGE_TYPE.to_string()),
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            arguments,
+        )
+        .await
+}
+
+// Tên exchange + queue chờ cho (exchange đích, delay)
+pub fn delay_queue_name(exchange: &str, delay: Duration) -> String {
+    let exchange = if exchange.is_empty() { "default" } else { exchange };
+    format!("delay.{}.{}ms", exchange, delay.as_millis())
+}
+
+pub struct DelayedPublisher {
+    publisher: BatchPublisher,
+    mode: DelayMode,
+    // TtlQueues: delay được làm tròn LÊN bội số của granularity → ít queue hơn
+    granularity: Duration,
+    // Queue delay → lần declare cuối (declare lại để gia hạn x-expires)
+    declared: HashMap<String, Instant>,
+}
+
+impl DelayedPublisher {
+    pub fn new(publisher: BatchPublisher, mode: DelayMode) -> Self {
+        DelayedPublisher {
+            publisher,
+            mode,
+            granularity: Duration::from_secs(1),
+            declared: HashMap::new(),
+        }
+    }
+
+    pub fn with_granularity(mut self, granularity: Duration) -> Self {
+        self.granularity = granularity.max(Duration::from_millis(1));
+        self
+    }
+
+    pub fn mode(&self) -> DelayMode {
+        self.mode
+    }
+
+    // Delay thực tế sẽ dùng cho `delay`
+    pub fn effective_delay(&self, delay: Duration) -> Duration {
+        self.mode.effective_delay(delay, self.granularity)
+    }
+
+    pub async fn publish_delayed(&mut self, message: OutgoingMessage, delay: Duration) -> LapinResult<PublishOutcome> {
+        let report = self.publish_delayed_batch(vec![(message, delay)]).await?;
+        Ok(report
+            .results
+            .into_iter()
+            .next()
+            .map(|r| r.outcome)
+            .unwrap_or(PublishOutcome::Failed("nothing published".to_string())))
+    }
+
+    // Lỗi declare queue delay → Err; lỗi publish từng message → trong BatchReport
+    pub async fn publish_delayed_batch(
+        &mut self,
+        messages: Vec<(OutgoingMessage, Duration)>,
+    ) -> LapinResult<BatchReport> {
+        let mut outgoing = Vec::with_capacity(messages.len());
+        for (message, delay) in messages {
+            outgoing.push(self.prepare(message, delay).await?);
+        }
+        Ok(self.publisher.publish_batch(outgoing).await)
+    }
+
+    async fn prepare(&mut self, message: OutgoingMessage, delay: Duration) -> LapinResult<OutgoingMessage> {
+        let delay = self.effective_delay(delay);
+        if delay.is_zero() {
+            return Ok(message);
+        }
+
+        match self.mode {
+            DelayMode::Plugin => {
+                let mut headers = message.properties.headers().clone().unwrap_or_default();
+                headers.insert(DELAY_HEADER.into(), AMQPValue::LongLongInt(delay.as_millis() as i64));
+                let properties = message.properties.clone().with_headers(headers);
+                Ok(message.with_properties(properties))
+            }
+            DelayMode::TtlQueues => {
+                let name = self.declare_delay_queue(&message.exchange, delay).await?;
+                // Fanout bỏ qua routing key khi route, nhưng dead-letter dùng lại routing key gốc
+                Ok(OutgoingMessage {
+                    exchange: name,
+                    ..message
+                })
+            }
+        }
+    }
+
+    async fn declare_delay_queue(&mut self, exchange: &str, delay: Duration) -> LapinResult<String> {
+        let name = delay_queue_name(exchange, delay);
+        let expires = delay + QUEUE_EXPIRES_MARGIN;
+        if let Some(declared_at) = self.declared.get(&name)
+            && declared_at.elapsed() < QUEUE_EXPIRES_MARGIN / 2
+        {
+            return Ok(name);
+        }
+
+        let channel = self.publisher.channel();
+        channel
+            .exchange_declare(
+                &name,
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        let mut arguments = FieldTable::default();
+        arguments.insert("x-message-ttl".into(), AMQPValue::LongLongInt(delay.as_millis() as i64));
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString(LongString::from(exchange.to_string())),
+        );
+        arguments.insert("x-expires".into(), AMQPValue::LongLongInt(expires.as_millis() as i64));
+        channel
+            .queue_declare(
+                &name,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                arguments,
+            )
+            .await?;
+        channel
+            .queue_bind(&name, &name, "", QueueBindOptions::default(), FieldTable::default())
+            .await?;
+
+        self.declared.insert(name.clone(), Instant::now());
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn ttl_queues_round_up_to_granularity() {
+        let mode = DelayMode::TtlQueues;
+        assert_eq!(mode.effective_delay(Duration::from_millis(1), SECOND), SECOND);
+        assert_eq!(mode.effective_delay(Duration::from_millis(1000), SECOND), SECOND);
+        assert_eq!(mode.effective_delay(Duration::from_millis(1001), SECOND), 2 * SECOND);
+        assert_eq!(
+            mode.effective_delay(Duration::from_millis(2500), Duration::from_millis(200)),
+            Duration::from_millis(2600)
+        );
+        // Sub-millisecond bị bỏ, granularity 0 coi như 1ms
+        assert_eq!(mode.effective_delay(Duration::from_micros(1500), Duration::ZERO), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn zero_delay_passes_through() {
+        assert_eq!(DelayMode::TtlQueues.effective_delay(Duration::ZERO, SECOND), Duration::ZERO);
+        assert_eq!(DelayMode::Plugin.effective_delay(Duration::ZERO, SECOND), Duration::ZERO);
+    }
+
+    #[test]
+    fn plugin_keeps_delay_unchanged() {
+        assert_eq!(
+            DelayMode::Plugin.effective_delay(Duration::from_millis(1234), SECOND),
+            Duration::from_millis(1234)
+        );
+    }
+
+    #[test]
+    fn delay_queue_name_per_exchange_and_delay() {
+        assert_eq!(delay_queue_name("", SECOND), "delay.default.1000ms");
+        assert_eq!(delay_queue_name("orders", Duration::from_millis(1500)), "delay.orders.1500ms");
+        assert_ne!(delay_queue_name("orders", SECOND), delay_queue_name("orders", 2 * SECOND));
+        assert_ne!(delay_queue_name("orders", SECOND), delay_queue_name("payments", SECOND));
+    }
+}
@@ -0,0 +1,661 @@
+// Envelope: ký (HMAC-SHA256 / Ed25519) và mã hóa (AES-256-GCM / ChaCha20-Poly1305) payload
+// Producer: Sealer::seal → body = ciphertext (nếu mã hóa), thuật toán + key id + chữ ký nằm trong headers
+// Consumer: Opener::open → kiểm tra chữ ký TRƯỚC, rồi mới giải mã; message không ký / sai chữ ký bị từ chối
+//
+// Key files trong 1 thư mục (base64, tạo bằng `cargo run -- keygen`), tên file = <key id>.<thuật toán>:
+//   keys/2024-01.hmac-sha256        keys/2024-01.aes-256-gcm
+//   keys/orders.ed25519             keys/orders.ed25519.pub   (consumer chỉ cần .pub)
+// Rotate key: thêm key mới, đổi key id ở producer; consumer giữ key cũ tới khi message cũ hết.
+//
+// ⚠️  Chữ ký chỉ phủ body (+ các header envelope), KHÔNG phủ routing key hay các headers khác.
+
+use crate::batch::OutgoingMessage;
+use crate::middleware::{BoxHandler, Context, HandlerError, Layer};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::ChaCha20Poly1305;
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use futures::FutureExt;
+use hmac::{Hmac, Mac};
+use lapin::{
+    message::Delivery,
+    types::{AMQPValue, FieldTable, LongString},
+    BasicProperties,
+};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const SIGNATURE_ALG_HEADER: &str = "x-signature-alg";
+pub const SIGNATURE_KEY_HEADER: &str = "x-signature-key-id";
+pub const SIGNATURE_HEADER: &str = "x-signature";
+pub const ENCRYPTION_ALG_HEADER: &str = "x-encryption-alg";
+pub const ENCRYPTION_KEY_HEADER: &str = "x-encryption-key-id";
+pub const NONCE_HEADER: &str = "x-encryption-nonce";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Algorithm {
+    HmacSha256,
+    Ed25519,
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 4] = [
+        Algorithm::HmacSha256,
+        Algorithm::Ed25519,
+        Algorithm::Aes256Gcm,
+        Algorithm::ChaCha20Poly1305,
+    ];
+
+    // Tên trong header và đuôi key file
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::HmacSha256 => "hmac-sha256",
+            Algorithm::Ed25519 => "ed25519",
+            Algorithm::Aes256Gcm => "aes-256-gcm",
+            Algorithm::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        Algorithm::ALL.into_iter().find(|alg| alg.name() == name)
+    }
+
+    pub fn is_signature(self) -> bool {
+        matches!(self, Algorithm::HmacSha256 | Algorithm::Ed25519)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvelopeError {
+    Unsigned,
+    Unencrypted,
+    UnknownKey { algorithm: String, key_id: String },
+    UnsupportedAlgorithm(String),
+    MalformedHeader(&'static str),
+    BadSignature { key_id: String },
+    DecryptFailed { key_id: String },
+    Encrypt(String),
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::Unsigned => write!(f, "message is not signed"),
+            EnvelopeError::Unencrypted => write!(f, "message is not encrypted"),
+            EnvelopeError::UnknownKey { algorithm, key_id } => write!(f, "no {} key '{}'", algorithm, key_id),
+            EnvelopeError::UnsupportedAlgorithm(alg) => write!(f, "unsupported algorithm '{}'", alg),
+            EnvelopeError::MalformedHeader(header) => write!(f, "malformed header {}", header),
+            EnvelopeError::BadSignature { key_id } => write!(f, "signature does not match (key '{}')", key_id),
+            EnvelopeError::DecryptFailed { key_id } => write!(f, "decryption failed (key '{}')", key_id),
+            EnvelopeError::Encrypt(e) => write!(f, "encryption failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+#[derive(Clone)]
+enum KeyMaterial {
+    Hmac(Vec<u8>),
+    Ed25519Signing(SigningKey),
+    Ed25519Verifying(VerifyingKey),
+    Cipher([u8; 32]),
+}
+
+#[derive(Clone, Default)]
+pub struct KeyRing {
+    keys: BTreeMap<(Algorithm, String), KeyMaterial>,
+}
+
+impl KeyRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Đọc mọi `<key id>.<thuật toán>` (và `<key id>.ed25519.pub`), bỏ qua file khác
+    pub fn load_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut ring = KeyRing::new();
+        for entry in std::fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let (stem, public) = match file_name.strip_suffix(".pub") {
+                Some(stem) => (stem, true),
+                None => (file_name, false),
+            };
+            let Some((key_id, algorithm)) = stem
+                .rsplit_once('.')
+                .and_then(|(key_id, alg)| Algorithm::from_name(alg).map(|alg| (key_id, alg)))
+            else {
+                continue;
+            };
+
+            let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+            let bytes = BASE64
+                .decode(std::fs::read_to_string(&path)?.trim())
+                .map_err(|e| invalid(e.to_string()))?;
+            match (algorithm, public) {
+                (Algorithm::Ed25519, true) => ring.insert_ed25519_public(key_id, &bytes).map_err(invalid)?,
+                (_, true) => continue,
+                _ => ring.insert(key_id, algorithm, &bytes).map_err(invalid)?,
+            }
+        }
+        Ok(ring)
+    }
+
+    // Secret key (Ed25519: 32-byte seed - public key được suy ra)
+    pub fn insert(&mut self, key_id: &str, algorithm: Algorithm, bytes: &[u8]) -> Result<(), String> {
+        let material = match algorithm {
+            Algorithm::HmacSha256 if bytes.len() < 16 => {
+                return Err(format!("HMAC key too short ({} bytes, need at least 16)", bytes.len()));
+            }
+            Algorithm::HmacSha256 => KeyMaterial::Hmac(bytes.to_vec()),
+            Algorithm::Ed25519 => KeyMaterial::Ed25519Signing(SigningKey::from_bytes(&key_32(bytes)?)),
+            Algorithm::Aes256Gcm | Algorithm::ChaCha20Poly1305 => KeyMaterial::Cipher(key_32(bytes)?),
+        };
+        self.keys.insert((algorithm, key_id.to_string()), material);
+        Ok(())
+    }
+
+    // Chỉ verify được, không ký được
+    pub fn insert_ed25519_public(&mut self, key_id: &str, bytes: &[u8]) -> Result<(), String> {
+        let key = (Algorithm::Ed25519, key_id.to_string());
+        // Đã có secret key (verify được bằng nó) → giữ nguyên
+        if matches!(self.keys.get(&key), Some(KeyMaterial::Ed25519Signing(_))) {
+            return Ok(());
+        }
+        let public = VerifyingKey::from_bytes(&key_32(bytes)?).map_err(|e| e.to_string())?;
+        self.keys.insert(key, KeyMaterial::Ed25519Verifying(public));
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (Algorithm, &str)> {
+        self.keys.keys().map(|(alg, key_id)| (*alg, key_id.as_str()))
+    }
+
+    fn get(&self, algorithm: Algorithm, key_id: &str) -> Result<&KeyMaterial, EnvelopeError> {
+        self.keys
+            .get(&(algorithm, key_id.to_string()))
+            .ok_or_else(|| EnvelopeError::UnknownKey {
+                algorithm: algorithm.name().to_string(),
+                key_id: key_id.to_string(),
+            })
+    }
+
+    pub(crate) fn sign(&self, algorithm: Algorithm, key_id: &str, data: &[u8]) -> Result<Vec<u8>, EnvelopeError> {
+        match self.get(algorithm, key_id)? {
+            KeyMaterial::Hmac(secret) => Ok(hmac_sha256(secret, data)),
+            KeyMaterial::Ed25519Signing(key) => Ok(key.sign(data).to_bytes().to_vec()),
+            // Chỉ có public key / key mã hóa → không ký được
+            _ => Err(EnvelopeError::UnknownKey {
+                algorithm: algorithm.name().to_string(),
+                key_id: key_id.to_string(),
+            }),
+        }
+    }
+
+    pub(crate) fn verify(&self, algorithm: Algorithm, key_id: &str, data: &[u8], signature: &[u8]) -> Result<(), EnvelopeError> {
+        let bad = || EnvelopeError::BadSignature {
+            key_id: key_id.to_string(),
+        };
+        let public = match self.get(algorithm, key_id)? {
+            KeyMaterial::Hmac(secret) => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).map_err(|_| bad())?;
+                mac.update(data);
+                // So sánh constant-time
+                return mac.verify_slice(signature).map_err(|_| bad());
+            }
+            KeyMaterial::Ed25519Signing(key) => key.verifying_key(),
+            KeyMaterial::Ed25519Verifying(key) => *key,
+            KeyMaterial::Cipher(_) => return Err(EnvelopeError::UnsupportedAlgorithm(algorithm.name().to_string())),
+        };
+        let signature = ed25519_dalek::Signature::from_slice(signature).map_err(|_| bad())?;
+        public.verify_strict(data, &signature).map_err(|_| bad())
+    }
+
+    fn cipher_key(&self, algorithm: Algorithm, key_id: &str) -> Result<&[u8; 32], EnvelopeError> {
+        match self.get(algorithm, key_id)? {
+            KeyMaterial::Cipher(key) => Ok(key),
+            _ => Err(EnvelopeError::UnsupportedAlgorithm(algorithm.name().to_string())),
+        }
+    }
+}
+
+fn key_32(bytes: &[u8]) -> Result<[u8; 32], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("expected a 32-byte key, got {} bytes", bytes.len()))
+}
+
+fn hmac_sha256(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC nhận key độ dài bất kỳ
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Ghi key mới vào `dir` (Ed25519: thêm file .pub để phát cho consumers). Trả về các file đã tạo
+pub fn generate_key_files(dir: &Path, key_id: &str, algorithm: Algorithm) -> io::Result<Vec<PathBuf>> {
+    if key_id.is_empty() || key_id.contains(['/', '\\']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid key id '{}'", key_id)));
+    }
+    std::fs::create_dir_all(dir)?;
+
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let secret_path = dir.join(format!("{}.{}", key_id, algorithm.name()));
+    let mut files = vec![(secret_path, BASE64.encode(secret))];
+    if algorithm == Algorithm::Ed25519 {
+        let public = SigningKey::from_bytes(&secret).verifying_key();
+        let public_path = dir.join(format!("{}.{}.pub", key_id, algorithm.name()));
+        files.push((public_path, BASE64.encode(public.as_bytes())));
+    }
+
+    for (path, _) in &files {
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+    }
+    for (path, content) in &files {
+        std::fs::write(path, format!("{}\n", content))?;
+    }
+    // Secret key: chỉ owner đọc được
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&files[0].0, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+// Dữ liệu được ký: các header envelope + body (ciphertext nếu có mã hóa)
+fn signed_bytes(encryption: Option<(&str, &str, &str)>, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(body.len() + 64);
+    if let Some((algorithm, key_id, nonce)) = encryption {
+        data.extend_from_slice(format!("{}\n{}\n{}\n", algorithm, key_id, nonce).as_bytes());
+    }
+    data.extend_from_slice(body);
+    data
+}
+
+// Additional data của AEAD: ciphertext không dùng được với thuật toán / key id khác
+fn aead_payload<'a>(algorithm: Algorithm, key_id: &str, msg: &'a [u8], aad: &'a mut Vec<u8>) -> Payload<'a, 'a> {
+    aad.extend_from_slice(format!("{}:{}", algorithm.name(), key_id).as_bytes());
+    Payload { msg, aad }
+}
+
+fn header_str<'a>(headers: &'a FieldTable, name: &'static str) -> Result<Option<&'a str>, EnvelopeError> {
+    match headers.inner().get(name) {
+        None => Ok(None),
+        Some(AMQPValue::LongString(value)) => std::str::from_utf8(value.as_bytes())
+            .map(Some)
+            .map_err(|_| EnvelopeError::MalformedHeader(name)),
+        Some(_) => Err(EnvelopeError::MalformedHeader(name)),
+    }
+}
+
+fn header_algorithm(headers: &FieldTable, name: &'static str) -> Result<Option<Algorithm>, EnvelopeError> {
+    header_str(headers, name)?
+        .map(|alg| Algorithm::from_name(alg).ok_or_else(|| EnvelopeError::UnsupportedAlgorithm(alg.to_string())))
+        .transpose()
+}
+
+#[derive(Clone)]
+pub struct Sealer {
+    keys: Arc<KeyRing>,
+    signing: Option<(Algorithm, String)>,
+    encryption: Option<(Algorithm, String)>,
+}
+
+impl Sealer {
+    pub fn new(keys: Arc<KeyRing>) -> Self {
+        Sealer {
+            keys,
+            signing: None,
+            encryption: None,
+        }
+    }
+
+    pub fn with_signing(mut self, algorithm: Algorithm, key_id: &str) -> Self {
+        self.signing = Some((algorithm, key_id.to_string()));
+        self
+    }
+
+    pub fn with_encryption(mut self, algorithm: Algorithm, key_id: &str) -> Self {
+        self.encryption = Some((algorithm, key_id.to_string()));
+        self
+    }
+
+    // Encrypt-then-sign. Trả về body mới + properties có thêm headers envelope
+    pub fn seal(&self, payload: &[u8], properties: BasicProperties) -> Result<(Vec<u8>, BasicProperties), EnvelopeError> {
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        let mut body = payload.to_vec();
+        let mut encryption_fields = None;
+
+        if let Some((algorithm, key_id)) = &self.encryption {
+            let key = self.keys.cipher_key(*algorithm, key_id)?;
+            let mut nonce = [0u8; 12];
+            OsRng.fill_bytes(&mut nonce);
+            let mut aad = Vec::new();
+            let data = aead_payload(*algorithm, key_id, payload, &mut aad);
+            let encrypted = match algorithm {
+                Algorithm::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(Nonce::from_slice(&nonce), data),
+                Algorithm::ChaCha20Poly1305 => {
+                    ChaCha20Poly1305::new(key.into()).encrypt(chacha20poly1305::Nonce::from_slice(&nonce), data)
+                }
+                other => return Err(EnvelopeError::UnsupportedAlgorithm(other.name().to_string())),
+            };
+            body = encrypted.map_err(|e| EnvelopeError::Encrypt(e.to_string()))?;
+
+            let nonce = BASE64.encode(nonce);
+            headers.insert(ENCRYPTION_ALG_HEADER.into(), long_string(algorithm.name()));
+            headers.insert(ENCRYPTION_KEY_HEADER.into(), long_string(key_id));
+            headers.insert(NONCE_HEADER.into(), long_string(&nonce));
+            encryption_fields = Some((algorithm.name(), key_id.as_str(), nonce));
+        }
+
+        if let Some((algorithm, key_id)) = &self.signing {
+            let fields = encryption_fields.as_ref().map(|(alg, key_id, nonce)| (*alg, *key_id, nonce.as_str()));
+            let signature = self.keys.sign(*algorithm, key_id, &signed_bytes(fields, &body))?;
+            headers.insert(SIGNATURE_ALG_HEADER.into(), long_string(algorithm.name()));
+            headers.insert(SIGNATURE_KEY_HEADER.into(), long_string(key_id));
+            headers.insert(SIGNATURE_HEADER.into(), long_string(&BASE64.encode(signature)));
+        }
+
+        Ok((body, properties.with_headers(headers)))
+    }
+
+    pub fn seal_outgoing(&self, message: OutgoingMessage) -> Result<OutgoingMessage, EnvelopeError> {
+        let (payload, properties) = self.seal(&message.payload, message.properties.clone())?;
+        Ok(OutgoingMessage {
+            payload,
+            properties,
+            ..message
+        })
+    }
+}
+
+fn long_string(value: &str) -> AMQPValue {
+    AMQPValue::LongString(LongString::from(value.to_string()))
+}
+
+#[derive(Clone)]
+pub struct Opener {
+    keys: Arc<KeyRing>,
+    require_signature: bool,
+    require_encryption: bool,
+}
+
+impl Opener {
+    // Mặc định: bắt buộc có chữ ký, mã hóa tùy message
+    pub fn new(keys: Arc<KeyRing>) -> Self {
+        Opener {
+            keys,
+            require_signature: true,
+            require_encryption: false,
+        }
+    }
+
+    pub fn with_require_signature(mut self, required: bool) -> Self {
+        self.require_signature = required;
+        self
+    }
+
+    pub fn with_require_encryption(mut self, required: bool) -> Self {
+        self.require_encryption = required;
+        self
+    }
+
+    // Verify rồi decrypt, trả về plaintext
+    pub fn open(&self, properties: &BasicProperties, body: &[u8]) -> Result<Vec<u8>, EnvelopeError> {
+        let empty = FieldTable::default();
+        let headers = properties.headers().as_ref().unwrap_or(&empty);
+
+        let encryption = match header_algorithm(headers, ENCRYPTION_ALG_HEADER)? {
+            Some(algorithm) => {
+                let key_id = header_str(headers, ENCRYPTION_KEY_HEADER)?
+                    .ok_or(EnvelopeError::MalformedHeader(ENCRYPTION_KEY_HEADER))?;
+                let nonce = header_str(headers, NONCE_HEADER)?.ok_or(EnvelopeError::MalformedHeader(NONCE_HEADER))?;
+                Some((algorithm, key_id, nonce))
+            }
+            None if self.require_encryption => return Err(EnvelopeError::Unencrypted),
+            None => None,
+        };
+
+        match header_algorithm(headers, SIGNATURE_ALG_HEADER)? {
+            Some(algorithm) if algorithm.is_signature() => {
+                let key_id = header_str(headers, SIGNATURE_KEY_HEADER)?
+                    .ok_or(EnvelopeError::MalformedHeader(SIGNATURE_KEY_HEADER))?;
+                let signature = header_str(headers, SIGNATURE_HEADER)?
+                    .and_then(|s| BASE64.decode(s).ok())
+                    .ok_or(EnvelopeError::MalformedHeader(SIGNATURE_HEADER))?;
+                let fields = encryption.map(|(alg, key_id, nonce)| (alg.name(), key_id, nonce));
+                self.keys
+                    .verify(algorithm, key_id, &signed_bytes(fields, body), &signature)?;
+            }
+            Some(other) => return Err(EnvelopeError::UnsupportedAlgorithm(other.name().to_string())),
+            None if self.require_signature => return Err(EnvelopeError::Unsigned),
+            None => {}
+        }
+
+        let Some((algorithm, key_id, nonce)) = encryption else {
+            return Ok(body.to_vec());
+        };
+        let key = self.keys.cipher_key(algorithm, key_id)?;
+        let nonce = BASE64
+            .decode(nonce)
+            .ok()
+            .filter(|nonce| nonce.len() == 12)
+            .ok_or(EnvelopeError::MalformedHeader(NONCE_HEADER))?;
+        let mut aad = Vec::new();
+        let data = aead_payload(algorithm, key_id, body, &mut aad);
+        let decrypted = match algorithm {
+            Algorithm::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(Nonce::from_slice(&nonce), data),
+            Algorithm::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).decrypt(chacha20poly1305::Nonce::from_slice(&nonce), data)
+            }
+            other => return Err(EnvelopeError::UnsupportedAlgorithm(other.name().to_string())),
+        };
+        decrypted.map_err(|_| EnvelopeError::DecryptFailed {
+            key_id: key_id.to_string(),
+        })
+    }
+
+    pub fn open_delivery(&self, delivery: &Delivery) -> Result<Vec<u8>, EnvelopeError> {
+        self.open(&delivery.properties, &delivery.data)
+    }
+}
+
+// Middleware layer (src/middleware.rs): handler phía trong nhận delivery đã giải mã.
+// Không ký / sai chữ ký / không giải mã được → HandlerError::Reject (không retry, không requeue)
+pub struct EnvelopeLayer {
+    opener: Opener,
+}
+
+impl EnvelopeLayer {
+    pub fn new(opener: Opener) -> Self {
+        EnvelopeLayer { opener }
+    }
+}
+
+impl Layer for EnvelopeLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let opener = self.opener.clone();
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            let opener = opener.clone();
+            async move {
+                let data = opener
+                    .open_delivery(&ctx.delivery)
+                    .map_err(|e| HandlerError::Reject(format!("envelope: {}", e)))?;
+                // Ack/nack vẫn đi qua ctx gốc trong Pipeline::handle
+                let delivery = &ctx.delivery;
+                let opened = Context {
+                    delivery: Delivery {
+                        delivery_tag: delivery.delivery_tag,
+                        exchange: delivery.exchange.clone(),
+                        routing_key: delivery.routing_key.clone(),
+                        redelivered: delivery.redelivered,
+                        properties: delivery.properties.clone(),
+                        data,
+                        acker: delivery.acker.clone(),
+                    },
+                    received_at: ctx.received_at,
+                };
+                inner(Arc::new(opened)).await
+            }
+            .boxed()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring() -> KeyRing {
+        let mut ring = KeyRing::new();
+        ring.insert("k1", Algorithm::HmacSha256, &[1; 32]).unwrap();
+        ring.insert("k1", Algorithm::Ed25519, &[2; 32]).unwrap();
+        ring.insert("k1", Algorithm::Aes256Gcm, &[3; 32]).unwrap();
+        ring.insert("k1", Algorithm::ChaCha20Poly1305, &[4; 32]).unwrap();
+        ring
+    }
+
+    fn set_header(properties: BasicProperties, name: &str, value: &str) -> BasicProperties {
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        headers.insert(name.into(), long_string(value));
+        properties.with_headers(headers)
+    }
+
+    #[test]
+    fn every_combination_round_trips() {
+        let keys = Arc::new(ring());
+        let opener = Opener::new(keys.clone());
+        for signing in [Algorithm::HmacSha256, Algorithm::Ed25519] {
+            for encryption in [None, Some(Algorithm::Aes256Gcm), Some(Algorithm::ChaCha20Poly1305)] {
+                let mut sealer = Sealer::new(keys.clone()).with_signing(signing, "k1");
+                if let Some(encryption) = encryption {
+                    sealer = sealer.with_encryption(encryption, "k1");
+                }
+                let (body, properties) = sealer.seal(b"hello", BasicProperties::default()).unwrap();
+                assert_eq!(body == b"hello", encryption.is_none(), "{:?} {:?}", signing, encryption);
+                assert_eq!(opener.open(&properties, &body).unwrap(), b"hello");
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_body_or_envelope_header_is_rejected() {
+        let keys = Arc::new(ring());
+        let sealer = Sealer::new(keys.clone())
+            .with_signing(Algorithm::HmacSha256, "k1")
+            .with_encryption(Algorithm::Aes256Gcm, "k1");
+        let opener = Opener::new(keys);
+        let (mut body, properties) = sealer.seal(b"hello", BasicProperties::default()).unwrap();
+
+        // Nonce nằm trong dữ liệu được ký
+        let moved = set_header(properties.clone(), NONCE_HEADER, &BASE64.encode([0u8; 12]));
+        assert_eq!(
+            opener.open(&moved, &body),
+            Err(EnvelopeError::BadSignature { key_id: "k1".to_string() })
+        );
+
+        body[0] ^= 1;
+        assert_eq!(
+            opener.open(&properties, &body),
+            Err(EnvelopeError::BadSignature { key_id: "k1".to_string() })
+        );
+        // Không ký, không bắt buộc chữ ký → AEAD vẫn phát hiện ciphertext bị sửa
+        let keys = Arc::new(ring());
+        let encrypt_only = Sealer::new(keys.clone()).with_encryption(Algorithm::ChaCha20Poly1305, "k1");
+        let (mut body, properties) = encrypt_only.seal(b"hello", BasicProperties::default()).unwrap();
+        body[0] ^= 1;
+        let lenient = Opener::new(keys).with_require_signature(false);
+        assert_eq!(
+            lenient.open(&properties, &body),
+            Err(EnvelopeError::DecryptFailed { key_id: "k1".to_string() })
+        );
+    }
+
+    #[test]
+    fn requirements_and_unknown_keys() {
+        let keys = Arc::new(ring());
+        let plain = BasicProperties::default();
+        assert_eq!(Opener::new(keys.clone()).open(&plain, b"x"), Err(EnvelopeError::Unsigned));
+
+        let signed_only = Sealer::new(keys.clone()).with_signing(Algorithm::HmacSha256, "k1");
+        let (body, properties) = signed_only.seal(b"x", plain.clone()).unwrap();
+        let strict = Opener::new(keys.clone()).with_require_encryption(true);
+        assert_eq!(strict.open(&properties, &body), Err(EnvelopeError::Unencrypted));
+
+        let rotated = set_header(properties, SIGNATURE_KEY_HEADER, "k2");
+        assert_eq!(
+            Opener::new(keys.clone()).open(&rotated, &body),
+            Err(EnvelopeError::UnknownKey {
+                algorithm: "hmac-sha256".to_string(),
+                key_id: "k2".to_string()
+            })
+        );
+
+        let bogus = set_header(BasicProperties::default(), SIGNATURE_ALG_HEADER, "md5");
+        assert_eq!(
+            Opener::new(keys.clone()).open(&bogus, b"x"),
+            Err(EnvelopeError::UnsupportedAlgorithm("md5".to_string()))
+        );
+        assert!(Sealer::new(keys).with_signing(Algorithm::HmacSha256, "missing").seal(b"x", plain).is_err());
+    }
+
+    #[test]
+    fn ed25519_public_key_verifies_but_cannot_sign() {
+        let dir = std::env::temp_dir().join(format!("learn_rabbitmq_keys_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let files = generate_key_files(&dir, "orders", Algorithm::Ed25519).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(generate_key_files(&dir, "orders", Algorithm::Ed25519).is_err());
+
+        let producer = Arc::new(KeyRing::load_dir(&dir).unwrap());
+        std::fs::remove_file(&files[0]).unwrap();
+        let consumer = Arc::new(KeyRing::load_dir(&dir).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (body, properties) = Sealer::new(producer)
+            .with_signing(Algorithm::Ed25519, "orders")
+            .seal(b"order", BasicProperties::default())
+            .unwrap();
+        assert_eq!(Opener::new(consumer.clone()).open(&properties, &body).unwrap(), b"order");
+        assert!(Sealer::new(consumer)
+            .with_signing(Algorithm::Ed25519, "orders")
+            .seal(b"order", BasicProperties::default())
+            .is_err());
+    }
+
+    #[test]
+    fn short_or_wrong_size_keys_are_refused() {
+        let mut ring = KeyRing::new();
+        assert!(ring.insert("k", Algorithm::HmacSha256, &[0; 8]).is_err());
+        assert!(ring.insert("k", Algorithm::Aes256Gcm, &[0; 16]).is_err());
+        assert!(ring.is_empty());
+    }
+}
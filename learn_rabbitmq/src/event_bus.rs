@@ -0,0 +1,498 @@
+// EventBus: 1 queue + 1 consumer cho cả service, thay vì mỗi binding key 1 subscriber
+// (topic_exchange_subscriber("user.*", ...), topic_exchange_subscriber("order.#", ...) ...)
+//   - Đăng ký handler async theo pattern (`order.payment.*`), payload được decode sẵn thành type T
+//   - Bus tự tính tập binding TỐI THIỂU (vd: "order.#" đã bao "order.payment.*" → chỉ bind "order.#")
+//   - Mỗi delivery được dispatch tới MỌI handler có pattern khớp routing key
+//   - Mỗi handler có FailurePolicy riêng; delivery được ack/nack 1 lần theo policy "nặng" nhất
+
+use futures::future::{join_all, BoxFuture};
+use futures::{FutureExt, StreamExt};
+use lapin::{
+    message::Delivery,
+    options::*,
+    types::FieldTable,
+    BasicProperties, Channel, ExchangeKind, Result as LapinResult,
+};
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Thứ tự = mức độ: khi nhiều handler lỗi, policy lớn nhất thắng
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum FailurePolicy {
+    // Log lỗi rồi ack - message không quan trọng với handler này
+    #[default]
+    Ignore,
+    // nack không requeue → dead-letter exchange (nếu queue có cấu hình DLX), không thì bỏ
+    Reject,
+    // nack + requeue → MỌI handler khớp sẽ chạy lại (handlers phải idempotent, xem src/dedup.rs)
+    Requeue,
+}
+
+// Payload đã decode + thông tin delivery cho handler
+#[derive(Debug, Clone)]
+pub struct Event<T> {
+    pub routing_key: String,
+    pub redelivered: bool,
+    pub properties: BasicProperties,
+    pub payload: T,
+}
+
+type ErasedHandler = Arc<dyn Fn(&Delivery) -> BoxFuture<'static, Result<(), Failure>> + Send + Sync>;
+
+enum Failure {
+    // Payload không decode được thành T - chạy lại cũng vô ích
+    Decode(String),
+    Handler(String),
+}
+
+struct Registration {
+    name: String,
+    pattern: String,
+    policy: FailurePolicy,
+    // Số lần chạy lại ngay trong process trước khi áp dụng `policy`
+    retries: u32,
+    handler: ErasedHandler,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandlerOutcome {
+    Ok,
+    Failed { error: String, policy: FailurePolicy },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DispatchReport {
+    pub routing_key: String,
+    // (tên handler, kết quả) theo thứ tự đăng ký
+    pub handlers: Vec<(String, HandlerOutcome)>,
+    // None = ack
+    pub nack: Option<FailurePolicy>,
+}
+
+pub struct EventBus {
+    exchange: String,
+    // "" = queue exclusive tên random (mất khi disconnect); đặt tên → queue durable của service
+    queue: String,
+    prefetch: u16,
+    registrations: Vec<Registration>,
+}
+
+impl EventBus {
+    // `exchange` phải là topic exchange (được declare nếu chưa có)
+    pub fn new(exchange: &str) -> Self {
+        EventBus {
+            exchange: exchange.to_string(),
+            queue: String::new(),
+            prefetch: 10,
+            registrations: Vec::new(),
+        }
+    }
+
+    // Queue durable dùng chung giữa các instance của service (work sharing + giữ message khi restart).
+    // ⚠️  Bindings cũ không còn handler KHÔNG tự bị unbind
+    pub fn with_queue(mut self, queue: &str) -> Self {
+        self.queue = queue.to_string();
+        self
+    }
+
+    pub fn with_prefetch(mut self, prefetch: u16) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    // Handler với FailurePolicy::Ignore, không retry
+    pub fn subscribe<T, F, Fut, E>(&mut self, pattern: &str, name: &str, handler: F) -> &mut Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(Event<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        self.subscribe_with(pattern, name, FailurePolicy::default(), 0, handler)
+    }
+
+    pub fn subscribe_with<T, F, Fut, E>(
+        &mut self,
+        pattern: &str,
+        name: &str,
+        policy: FailurePolicy,
+        retries: u32,
+        handler: F,
+    ) -> &mut Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(Event<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let handler = Arc::new(handler);
+        let erased: ErasedHandler = Arc::new(move |delivery: &Delivery| {
+            // Decode lỗi: không retry, và tối đa Reject (requeue message hỏng → lặp vô hạn)
+            let payload = serde_json::from_slice::<T>(&delivery.data);
+            let event = payload.map(|payload| Event {
+                routing_key: delivery.routing_key.to_string(),
+                redelivered: delivery.redelivered,
+                properties: delivery.properties.clone(),
+                payload,
+            });
+            let handler = handler.clone();
+            async move {
+                let event = event.map_err(|e| Failure::Decode(format!("decode: {}", e)))?;
+                handler(event).await.map_err(|e| Failure::Handler(e.to_string()))
+            }
+            .boxed()
+        });
+
+        self.registrations.push(Registration {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            policy,
+            retries,
+            handler: erased,
+        });
+        self
+    }
+
+    pub fn patterns(&self) -> Vec<&str> {
+        self.registrations.iter().map(|r| r.pattern.as_str()).collect()
+    }
+
+    // Tập binding tối thiểu phủ mọi pattern đã đăng ký
+    pub fn bindings(&self) -> Vec<String> {
+        minimal_bindings(&self.patterns())
+    }
+
+    // Declare exchange + queue, bind, rồi consume cho tới khi channel đóng
+    pub async fn run(&self, channel: &Channel) -> LapinResult<()> {
+        channel
+            .exchange_declare(
+                &self.exchange,
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        let options = if self.queue.is_empty() {
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            }
+        } else {
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            }
+        };
+        let queue = channel
+            .queue_declare(&self.queue, options, FieldTable::default())
+            .await?;
+        let queue_name = queue.name().as_str();
+
+        for binding in self.bindings() {
+            channel
+                .queue_bind(
+                    queue_name,
+                    &self.exchange,
+                    &binding,
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            println!("✓ EventBus bound '{}' → '{}' with '{}'", self.exchange, queue_name, binding);
+        }
+
+        channel
+            .basic_qos(self.prefetch, BasicQosOptions::default())
+            .await?;
+        let mut consumer = channel
+            .basic_consume(
+                queue_name,
+                "event_bus",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        println!("✓ EventBus consuming '{}' ({} handlers)", queue_name, self.registrations.len());
+
+        while let Some(delivery) = consumer.next().await {
+            let delivery = delivery?;
+            let report = self.dispatch(&delivery).await;
+            for (name, outcome) in &report.handlers {
+                if let HandlerOutcome::Failed { error, policy } = outcome {
+                    println!("✗ [{}] {} failed ({:?}): {}", name, report.routing_key, policy, error);
+                }
+            }
+
+            match report.nack {
+                None => delivery.ack(BasicAckOptions::default()).await?,
+                Some(policy) => {
+                    delivery
+                        .nack(BasicNackOptions {
+                            requeue: policy == FailurePolicy::Requeue,
+                            ..Default::default()
+                        })
+                        .await?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Chạy mọi handler khớp (song song) và quyết định ack/nack - không đụng tới broker
+    pub async fn dispatch(&self, delivery: &Delivery) -> DispatchReport {
+        let routing_key = delivery.routing_key.as_str();
+        let matched: Vec<&Registration> = self
+            .registrations
+            .iter()
+            .filter(|r| topic_matches(&r.pattern, routing_key))
+            .collect();
+
+        let outcomes = join_all(matched.iter().map(|registration| async move {
+            let mut attempt = 0;
+            loop {
+                match (registration.handler)(delivery).await {
+                    Ok(()) => return HandlerOutcome::Ok,
+                    Err(Failure::Handler(_)) if attempt < registration.retries => {
+                        attempt += 1;
+                        tokio::time::sleep(Duration::from_millis(100 * 2u64.pow(attempt.min(6)))).await;
+                    }
+                    Err(Failure::Handler(error)) => {
+                        return HandlerOutcome::Failed {
+                            error,
+                            policy: registration.policy,
+                        };
+                    }
+                    Err(Failure::Decode(error)) => {
+                        return HandlerOutcome::Failed {
+                            error,
+                            policy: registration.policy.min(FailurePolicy::Reject),
+                        };
+                    }
+                }
+            }
+        }))
+        .await;
+
+        let nack = outcomes
+            .iter()
+            .filter_map(|outcome| match outcome {
+                HandlerOutcome::Failed { policy, .. } if *policy != FailurePolicy::Ignore => Some(*policy),
+                _ => None,
+            })
+            .max();
+
+        DispatchReport {
+            routing_key: routing_key.to_string(),
+            handlers: matched
+                .iter()
+                .map(|r| r.name.clone())
+                .zip(outcomes)
+                .collect(),
+            nack,
+        }
+    }
+}
+
+// Topic matching như RabbitMQ: `*` = đúng 1 từ, `#` = 0 hoặc nhiều từ
+pub fn topic_matches(pattern: &str, routing_key: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let key: Vec<&str> = routing_key.split('.').collect();
+    covers(&pattern, &key, false)
+}
+
+// `general` có bao `specific` không (mọi routing key khớp `specific` đều khớp `general`)
+pub fn pattern_covers(general: &str, specific: &str) -> bool {
+    let general: Vec<&str> = general.split('.').collect();
+    let specific: Vec<&str> = specific.split('.').collect();
+    covers(&general, &specific, true)
+}
+
+// `words` là routing key (wildcards = ký tự thường) hoặc pattern (`wildcards` = true)
+fn covers(pattern: &[&str], words: &[&str], wildcards: bool) -> bool {
+    match pattern.split_first() {
+        None => words.is_empty(),
+        Some((&"#", rest)) => {
+            // # khớp 0 từ, hoặc nuốt 1 từ (kể cả * / # của pattern kia) rồi thử tiếp
+            covers(rest, words, wildcards) || (!words.is_empty() && covers(pattern, &words[1..], wildcards))
+        }
+        Some((&"*", rest)) => match words.split_first() {
+            // * không bao được # (# có thể là 0 hoặc nhiều từ)
+            Some((&"#", _)) if wildcards => false,
+            Some((_, words_rest)) => covers(rest, words_rest, wildcards),
+            None => false,
+        },
+        Some((literal, rest)) => match words.split_first() {
+            Some((&"*" | &"#", _)) if wildcards => false,
+            Some((word, words_rest)) => word == literal && covers(rest, words_rest, wildcards),
+            None => false,
+        },
+    }
+}
+
+// Bỏ patterns trùng hoặc đã được pattern khác bao (giữ thứ tự đăng ký)
+pub fn minimal_bindings(patterns: &[&str]) -> Vec<String> {
+    let mut unique: Vec<&str> = Vec::new();
+    for pattern in patterns {
+        if !unique.contains(pattern) {
+            unique.push(pattern);
+        }
+    }
+
+    unique
+        .iter()
+        .enumerate()
+        .filter(|(i, pattern)| {
+            !unique.iter().enumerate().any(|(j, other)| {
+                // 2 patterns tương đương (vd: "#.#" và "#") → giữ cái đăng ký trước
+                j != *i && pattern_covers(other, pattern) && (!pattern_covers(pattern, other) || j < *i)
+            })
+        })
+        .map(|(_, pattern)| pattern.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug, Deserialize)]
+    struct OrderPaid {
+        #[allow(dead_code)]
+        order_id: u64,
+    }
+
+    fn delivery(routing_key: &str, data: &[u8]) -> Delivery {
+        Delivery {
+            delivery_tag: 1,
+            exchange: "events".into(),
+            routing_key: routing_key.into(),
+            redelivered: false,
+            properties: BasicProperties::default(),
+            data: data.to_vec(),
+            acker: Default::default(),
+        }
+    }
+
+    fn failed(report: &DispatchReport, name: &str) -> Option<FailurePolicy> {
+        report.handlers.iter().find(|(n, _)| n == name).and_then(|(_, outcome)| match outcome {
+            HandlerOutcome::Failed { policy, .. } => Some(*policy),
+            HandlerOutcome::Ok => None,
+        })
+    }
+
+    #[tokio::test]
+    async fn strictest_failure_policy_wins() {
+        let mut bus = EventBus::new("events");
+        bus.subscribe_with("order.#", "audit", FailurePolicy::Reject, 0, |_: Event<OrderPaid>| async {
+            Err::<(), _>("audit db down")
+        });
+        bus.subscribe_with("order.paid", "billing", FailurePolicy::Requeue, 0, |_: Event<OrderPaid>| async {
+            Err::<(), _>("billing timeout")
+        });
+        bus.subscribe("user.*", "users", |_: Event<OrderPaid>| async { Err::<(), _>("not called") });
+
+        let report = bus.dispatch(&delivery("order.paid", br#"{"order_id": 1}"#)).await;
+        assert_eq!(report.handlers.len(), 2);
+        assert_eq!(failed(&report, "audit"), Some(FailurePolicy::Reject));
+        assert_eq!(failed(&report, "billing"), Some(FailurePolicy::Requeue));
+        assert_eq!(report.nack, Some(FailurePolicy::Requeue));
+    }
+
+    #[tokio::test]
+    async fn ignored_failures_are_acked() {
+        let mut bus = EventBus::new("events");
+        bus.subscribe("order.*", "metrics", |_: Event<OrderPaid>| async { Err::<(), _>("statsd down") });
+        bus.subscribe("order.*", "email", |_: Event<OrderPaid>| async { Ok::<(), String>(()) });
+
+        let report = bus.dispatch(&delivery("order.paid", br#"{"order_id": 1}"#)).await;
+        assert_eq!(failed(&report, "metrics"), Some(FailurePolicy::Ignore));
+        assert_eq!(report.handlers[1], ("email".to_string(), HandlerOutcome::Ok));
+        assert_eq!(report.nack, None);
+    }
+
+    #[tokio::test]
+    async fn decode_errors_are_capped_at_reject() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let mut bus = EventBus::new("events");
+        bus.subscribe_with("order.*", "billing", FailurePolicy::Requeue, 3, move |_: Event<OrderPaid>| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Ok::<(), String>(()) }
+        });
+
+        let report = bus.dispatch(&delivery("order.paid", b"not json")).await;
+        assert_eq!(failed(&report, "billing"), Some(FailurePolicy::Reject));
+        assert_eq!(report.nack, Some(FailurePolicy::Reject));
+        // Decode lỗi → không retry, handler không được gọi
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn retries_are_per_handler() {
+        let flaky_calls = Arc::new(AtomicU32::new(0));
+        let strict_calls = Arc::new(AtomicU32::new(0));
+        let mut bus = EventBus::new("events");
+        let counter = flaky_calls.clone();
+        bus.subscribe_with("order.*", "flaky", FailurePolicy::Requeue, 2, move |_: Event<OrderPaid>| {
+            let attempt = counter.fetch_add(1, Ordering::SeqCst);
+            async move { if attempt == 0 { Err("first attempt fails") } else { Ok(()) } }
+        });
+        let counter = strict_calls.clone();
+        bus.subscribe_with("order.*", "strict", FailurePolicy::Reject, 0, move |_: Event<OrderPaid>| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Err::<(), _>("always fails") }
+        });
+
+        let report = bus.dispatch(&delivery("order.paid", br#"{"order_id": 1}"#)).await;
+        assert_eq!(failed(&report, "flaky"), None);
+        assert_eq!(flaky_calls.load(Ordering::SeqCst), 2);
+        assert_eq!(failed(&report, "strict"), Some(FailurePolicy::Reject));
+        assert_eq!(strict_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(report.nack, Some(FailurePolicy::Reject));
+    }
+
+    #[test]
+    fn topic_wildcards_match_like_rabbitmq() {
+        assert!(topic_matches("order.*", "order.created"));
+        assert!(!topic_matches("order.*", "order.payment.success"));
+        assert!(!topic_matches("order.*", "order"));
+        assert!(topic_matches("order.#", "order"));
+        assert!(topic_matches("order.#", "order.payment.success"));
+        assert!(topic_matches("#.success", "order.payment.success"));
+        assert!(topic_matches("*.payment.*", "order.payment.failed"));
+        assert!(topic_matches("#", "anything.at.all"));
+        assert!(topic_matches("user.login", "user.login"));
+        assert!(!topic_matches("user.login", "user.logout"));
+        assert!(!topic_matches("user.login", "user.login.extra"));
+    }
+
+    #[test]
+    fn pattern_coverage() {
+        assert!(pattern_covers("order.#", "order.payment.*"));
+        assert!(pattern_covers("#", "user.*"));
+        assert!(pattern_covers("*.*", "user.login"));
+        assert!(!pattern_covers("order.*", "order.#"));
+        assert!(!pattern_covers("order.payment.*", "order.#"));
+        assert!(!pattern_covers("user.*", "order.*"));
+    }
+
+    #[test]
+    fn minimal_bindings_drop_covered_and_duplicate_patterns() {
+        assert_eq!(
+            minimal_bindings(&["user.*", "order.payment.*", "order.#", "user.*"]),
+            ["user.*", "order.#"]
+        );
+        // Tương đương nhau → giữ pattern đăng ký trước
+        assert_eq!(minimal_bindings(&["#.#", "#", "order.created"]), ["#.#"]);
+        assert_eq!(minimal_bindings(&["a.*", "*.b"]), ["a.*", "*.b"]);
+        assert!(minimal_bindings(&[]).is_empty());
+    }
+}
@@ -0,0 +1,27 @@
+pub mod backpressure;
+pub mod batch;
+pub mod bench;
+pub mod circuit_breaker;
+pub mod config;
+pub mod dedup;
+pub mod delay;
+pub mod envelope;
+pub mod event_bus;
+pub mod management;
+pub mod middleware;
+pub mod outbox;
+pub mod partition;
+pub mod pool;
+pub mod queue_args;
+pub mod queue_tools;
+pub mod record;
+pub mod saga;
+pub mod scheduler;
+pub mod schema;
+pub mod shovel;
+pub mod tail;
+pub mod tls;
+pub mod topology;
+pub mod util;
+pub mod versioning;
+pub mod webhook;
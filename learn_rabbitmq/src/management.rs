@@ -0,0 +1,567 @@
+// Client cho RabbitMQ Management HTTP API (plugin rabbitmq_management, cổng 15672)
+// Xem trạng thái broker mà không cần mở Management UI:
+// vhosts, exchanges, queues (số messages, consumers), bindings, connections.
+// User cần tag `monitoring` (hoặc `administrator`) để đọc được mọi vhost.
+
+use crate::config::{percent_encode, RabbitMQConfig, Secret};
+use lapin::Result as LapinResult;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageTotals {
+    pub messages: u64,
+    pub messages_ready: u64,
+    pub messages_unacknowledged: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObjectTotals {
+    pub connections: u64,
+    pub channels: u64,
+    pub exchanges: u64,
+    pub queues: u64,
+    pub consumers: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overview {
+    pub cluster_name: String,
+    pub rabbitmq_version: String,
+    pub erlang_version: String,
+    pub queue_totals: MessageTotals,
+    pub object_totals: ObjectTotals,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VhostInfo {
+    pub name: String,
+    pub description: String,
+    pub messages: u64,
+    pub messages_ready: u64,
+    pub messages_unacknowledged: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExchangeInfo {
+    // Default exchange có name = ""
+    pub name: String,
+    pub vhost: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub durable: bool,
+    pub auto_delete: bool,
+    pub internal: bool,
+    pub arguments: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueInfo {
+    pub name: String,
+    pub vhost: String,
+    pub durable: bool,
+    pub auto_delete: bool,
+    pub exclusive: bool,
+    // "running", "idle", "flow"...; queue vừa tạo có thể chưa có stats
+    pub state: Option<String>,
+    pub messages: u64,
+    pub messages_ready: u64,
+    pub messages_unacknowledged: u64,
+    pub consumers: u64,
+    // Chỉ có với queue x-single-active-consumer
+    pub single_active_consumer_tag: Option<String>,
+    pub arguments: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BindingInfo {
+    // source = "" → default exchange (binding ngầm định theo tên queue)
+    pub source: String,
+    pub vhost: String,
+    pub destination: String,
+    // "queue" hoặc "exchange" (exchange-to-exchange binding)
+    pub destination_type: String,
+    pub routing_key: String,
+    pub arguments: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionInfo {
+    pub name: String,
+    pub vhost: String,
+    pub user: String,
+    pub state: String,
+    pub protocol: String,
+    pub peer_host: String,
+    pub peer_port: u16,
+    pub channels: u64,
+    pub ssl: bool,
+}
+
+#[derive(Clone)]
+pub struct ManagementClient {
+    http: reqwest::Client,
+    base_url: String,
+    username: String,
+    password: Secret,
+}
+
+impl ManagementClient {
+    // `base_url`: vd http://localhost:15672 (không kèm /api)
+    pub fn new(base_url: &str, username: &str, password: Secret) -> io::Result<Self> {
+        Self::build(base_url, username, password, reqwest::Client::builder())
+    }
+
+    // Dùng URL/credentials/CA từ RabbitMQConfig (xem config.rs: management_url)
+    pub fn from_config(config: &RabbitMQConfig) -> LapinResult<Self> {
+        let (username, password) = config.credentials()?;
+        let mut builder = reqwest::Client::builder();
+        if let Some(ca_file) = &config.tls.ca_file {
+            let pem = std::fs::read(ca_file)
+                .map_err(|e| crate::tls::invalid_config(format!("{}: {}", ca_file.display(), e)))?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| crate::tls::invalid_config(format!("{}: {}", ca_file.display(), e)))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        Self::build(&config.management_url()?, &username, password, builder)
+            .map_err(|e| lapin::Error::IOError(e.into()))
+    }
+
+    fn build(
+        base_url: &str,
+        username: &str,
+        password: Secret,
+        builder: reqwest::ClientBuilder,
+    ) -> io::Result<Self> {
+        let http = builder
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(ManagementClient {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            password,
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub async fn overview(&self) -> io::Result<Overview> {
+        self.get("/api/overview").await
+    }
+
+    pub async fn vhosts(&self) -> io::Result<Vec<VhostInfo>> {
+        self.get("/api/vhosts").await
+    }
+
+    // vhost = None → tất cả vhosts mà user được xem
+    pub async fn exchanges(&self, vhost: Option<&str>) -> io::Result<Vec<ExchangeInfo>> {
+        self.get(&scoped("/api/exchanges", vhost)).await
+    }
+
+    pub async fn queues(&self, vhost: Option<&str>) -> io::Result<Vec<QueueInfo>> {
+        self.get(&scoped("/api/queues", vhost)).await
+    }
+
+    pub async fn bindings(&self, vhost: Option<&str>) -> io::Result<Vec<BindingInfo>> {
+        self.get(&scoped("/api/bindings", vhost)).await
+    }
+
+    pub async fn connections(&self, vhost: Option<&str>) -> io::Result<Vec<ConnectionInfo>> {
+        match vhost {
+            Some(vhost) => {
+                self.get(&format!("/api/vhosts/{}/connections", percent_encode(vhost)))
+                    .await
+            }
+            None => self.get("/api/connections").await,
+        }
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> io::Result<T> {
+        let url = format!("{}{}", self.base_url, path);
+        let response = self
+            .http
+            .get(&url)
+            .basic_auth(&self.username, Some(self.password.expose()))
+            .send()
+            .await
+            .map_err(|e| io::Error::other(format!("GET {}: {}", url, e)))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let kind = match status.as_u16() {
+                401 | 403 => io::ErrorKind::PermissionDenied,
+                404 => io::ErrorKind::NotFound,
+                _ => io::ErrorKind::Other,
+            };
+            return Err(io::Error::new(
+                kind,
+                format!("GET {}: {} - {}", url, status, api_reason(&body)),
+            ));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("GET {}: {}", url, e)))
+    }
+}
+
+// /api/queues + Some("sos") → /api/queues/sos, vhost "/" → /api/queues/%2F
+fn scoped(path: &str, vhost: Option<&str>) -> String {
+    match vhost {
+        Some(vhost) => format!("{}/{}", path, percent_encode(vhost)),
+        None => path.to_string(),
+    }
+}
+
+// Lỗi từ API có dạng {"error":"not_found","reason":"Object Not Found"}
+fn api_reason(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| v.get("reason").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or_else(|| body.chars().take(200).collect())
+}
+
+// In dạng bảng cho CLI
+pub trait TableRow {
+    const HEADERS: &'static [&'static str];
+    fn row(&self) -> Vec<String>;
+}
+
+pub fn to_table<T: TableRow>(items: &[T]) -> String {
+    let rows: Vec<Vec<String>> = items.iter().map(TableRow::row).collect();
+    let mut widths: Vec<usize> = T::HEADERS.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<String>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        format!("{}\n", line.trim_end())
+    };
+
+    let mut out = format_row(T::HEADERS.iter().map(|h| h.to_string()).collect());
+    out.push_str(&format_row(widths.iter().map(|w| "-".repeat(*w)).collect()));
+    for row in rows {
+        out.push_str(&format_row(row));
+    }
+    out
+}
+
+// Bảng 2 cột "key | value" (overview, báo cáo bench...)
+pub fn key_value_table(rows: &[(&str, String)]) -> String {
+    let width = rows.iter().map(|(k, _)| k.chars().count()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (key, value) in rows {
+        out.push_str(&format!("{:<width$} | {}\n", key, value, width = width));
+    }
+    out
+}
+
+fn flags(pairs: &[(bool, &str)]) -> String {
+    pairs
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn display_arguments(arguments: &Map<String, Value>) -> String {
+    arguments
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl TableRow for VhostInfo {
+    const HEADERS: &'static [&'static str] = &["vhost", "messages", "ready", "unacked", "description"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.messages.to_string(),
+            self.messages_ready.to_string(),
+            self.messages_unacknowledged.to_string(),
+            self.description.clone(),
+        ]
+    }
+}
+
+impl TableRow for ExchangeInfo {
+    const HEADERS: &'static [&'static str] = &["vhost", "exchange", "type", "flags", "arguments"];
+
+    fn row(&self) -> Vec<String> {
+        let name = if self.name.is_empty() {
+            "(default)".to_string()
+        } else {
+            self.name.clone()
+        };
+        vec![
+            self.vhost.clone(),
+            name,
+            self.kind.clone(),
+            flags(&[
+                (self.durable, "durable"),
+                (self.auto_delete, "auto-delete"),
+                (self.internal, "internal"),
+            ]),
+            display_arguments(&self.arguments),
+        ]
+    }
+}
+
+impl TableRow for QueueInfo {
+    const HEADERS: &'static [&'static str] = &[
+        "vhost", "queue", "state", "ready", "unacked", "total", "consumers", "flags", "arguments",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.vhost.clone(),
+            self.name.clone(),
+            self.state.clone().unwrap_or_else(|| "-".to_string()),
+            self.messages_ready.to_string(),
+            self.messages_unacknowledged.to_string(),
+            self.messages.to_string(),
+            match &self.single_active_consumer_tag {
+                Some(tag) => format!("{} (active: {})", self.consumers, tag),
+                None => self.consumers.to_string(),
+            },
+            flags(&[
+                (self.durable, "durable"),
+                (self.auto_delete, "auto-delete"),
+                (self.exclusive, "exclusive"),
+            ]),
+            display_arguments(&self.arguments),
+        ]
+    }
+}
+
+impl TableRow for BindingInfo {
+    const HEADERS: &'static [&'static str] = &["vhost", "source", "routing key", "destination", "arguments"];
+
+    fn row(&self) -> Vec<String> {
+        let source = if self.source.is_empty() {
+            "(default)".to_string()
+        } else {
+            self.source.clone()
+        };
+        let destination = match self.destination_type.as_str() {
+            "exchange" => format!("exchange:{}", self.destination),
+            _ => self.destination.clone(),
+        };
+        vec![
+            self.vhost.clone(),
+            source,
+            self.routing_key.clone(),
+            destination,
+            display_arguments(&self.arguments),
+        ]
+    }
+}
+
+impl TableRow for ConnectionInfo {
+    const HEADERS: &'static [&'static str] = &["vhost", "user", "peer", "state", "channels", "protocol", "tls"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.vhost.clone(),
+            self.user.clone(),
+            format!("{}:{}", self.peer_host, self.peer_port),
+            self.state.clone(),
+            self.channels.to_string(),
+            self.protocol.clone(),
+            if self.ssl { "yes" } else { "no" }.to_string(),
+        ]
+    }
+}
+
+impl Overview {
+    pub fn to_table(&self) -> String {
+        let rows = [
+            ("cluster", self.cluster_name.clone()),
+            ("rabbitmq / erlang", format!("{} / {}", self.rabbitmq_version, self.erlang_version)),
+            ("connections", self.object_totals.connections.to_string()),
+            ("channels", self.object_totals.channels.to_string()),
+            ("exchanges", self.object_totals.exchanges.to_string()),
+            ("queues", self.object_totals.queues.to_string()),
+            ("consumers", self.object_totals.consumers.to_string()),
+            ("messages ready", self.queue_totals.messages_ready.to_string()),
+            ("messages unacked", self.queue_totals.messages_unacknowledged.to_string()),
+        ];
+        key_value_table(&rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Path;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use serde_json::json;
+
+    // Trả `body` nếu đúng guest/guest, giống broker: sai credentials → 401 kèm reason
+    fn authorized(headers: &HeaderMap, body: Value) -> Response {
+        let expected = format!("Basic {}", BASE64.encode("guest:guest"));
+        match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+            Some(auth) if auth == expected => Json(body).into_response(),
+            _ => (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "not_authorised", "reason": "Login failed"})),
+            )
+                .into_response(),
+        }
+    }
+
+    fn stand_in() -> Router {
+        Router::new()
+            .route(
+                "/api/overview",
+                get(|headers: HeaderMap| async move {
+                    authorized(
+                        &headers,
+                        json!({
+                            "cluster_name": "rabbit@test",
+                            "rabbitmq_version": "3.13.0",
+                            "erlang_version": "26.2",
+                            "queue_totals": {"messages": 7, "messages_ready": 5, "messages_unacknowledged": 2},
+                            "object_totals": {"connections": 1, "channels": 2, "exchanges": 8, "queues": 3, "consumers": 1},
+                            "listeners": [],
+                        }),
+                    )
+                }),
+            )
+            .route(
+                "/api/queues/{vhost}",
+                get(|Path(vhost): Path<String>, headers: HeaderMap| async move {
+                    if vhost != "/" {
+                        return (
+                            StatusCode::NOT_FOUND,
+                            Json(json!({"error": "Object Not Found", "reason": "Not Found"})),
+                        )
+                            .into_response();
+                    }
+                    authorized(
+                        &headers,
+                        json!([
+                            {
+                                "name": "task_queue", "vhost": "/", "durable": true, "auto_delete": false,
+                                "exclusive": false, "state": "running", "messages": 3, "messages_ready": 2,
+                                "messages_unacknowledged": 1, "consumers": 2,
+                                "single_active_consumer_tag": "ctag-1",
+                                "arguments": {"x-single-active-consumer": true}
+                            },
+                            {"name": "fresh_queue", "vhost": "/"}
+                        ]),
+                    )
+                }),
+            )
+            .route(
+                "/api/exchanges/{vhost}",
+                get(|headers: HeaderMap| async move {
+                    authorized(
+                        &headers,
+                        json!([
+                            {"name": "", "vhost": "/", "type": "direct", "durable": true},
+                            {"name": "logs_topic", "vhost": "/", "type": "topic", "durable": true,
+                             "auto_delete": false, "internal": false, "arguments": {}}
+                        ]),
+                    )
+                }),
+            )
+    }
+
+    async fn serve() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stand_in()).await });
+        format!("http://{}", addr)
+    }
+
+    fn client(base_url: &str, password: &str) -> ManagementClient {
+        ManagementClient::new(base_url, "guest", Secret::new(password)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn parses_overview_queues_and_exchanges() {
+        let base_url = serve().await;
+        let client = client(&format!("{}/", base_url), "guest");
+
+        let overview = client.overview().await.unwrap();
+        assert_eq!(overview.cluster_name, "rabbit@test");
+        assert_eq!(overview.queue_totals.messages_ready, 5);
+        assert_eq!(overview.object_totals.exchanges, 8);
+        assert!(overview.to_table().contains("messages unacked  | 2\n"));
+
+        let queues = client.queues(Some("/")).await.unwrap();
+        assert_eq!(queues.len(), 2);
+        assert_eq!(queues[0].state.as_deref(), Some("running"));
+        assert_eq!(queues[0].single_active_consumer_tag.as_deref(), Some("ctag-1"));
+        // Queue mới chưa có stats → các field mặc định
+        assert_eq!((queues[1].state.as_deref(), queues[1].messages), (None, 0));
+        let table = to_table(&queues);
+        assert!(table.contains("2 (active: ctag-1)"));
+        assert!(table.lines().nth(1).unwrap().starts_with("-----"));
+
+        let exchanges = client.exchanges(Some("/")).await.unwrap();
+        assert_eq!(exchanges[1].kind, "topic");
+        assert!(to_table(&exchanges).contains("(default)"));
+    }
+
+    #[tokio::test]
+    async fn maps_auth_and_missing_errors() {
+        let base_url = serve().await;
+
+        let error = client(&base_url, "wrong").overview().await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(error.to_string().contains("Login failed"));
+
+        let error = client(&base_url, "guest").queues(Some("missing")).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("/api/queues/missing"));
+    }
+
+    #[test]
+    fn vhost_is_one_encoded_path_segment() {
+        assert_eq!(scoped("/api/queues", Some("/")), "/api/queues/%2F");
+        assert_eq!(scoped("/api/queues", Some("a b")), "/api/queues/a%20b");
+        assert_eq!(scoped("/api/queues", None), "/api/queues");
+    }
+
+    #[test]
+    fn key_value_table_aligns_keys() {
+        assert_eq!(key_value_table(&[("a", "1".to_string()), ("long", "2".to_string())]), "a    | 1\nlong | 2\n");
+    }
+}
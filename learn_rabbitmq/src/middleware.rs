@@ -0,0 +1,679 @@
+// Consumer middleware pipeline: các phần lặp lại trong simple_consumer, publish_subscribe_subscriber,
+// direct_exchange_subscriber, topic_exchange_subscriber (parse JSON, log, ack/nack) thành layers xếp chồng:
+//
+//   Pipeline::builder()
+//       .layer(LoggingLayer::new("worker"))       // ngoài cùng - chạy đầu tiên
+//       .layer(MetricsLayer::new(metrics))
+//       .layer(CatchPanicLayer)
+//       .layer(TimeoutLayer::new(Duration::from_secs(5)))
+//       .json(|msg: Message, ctx| async move { ... })  // decode JSON + handler (trong cùng)
+//
+// Kết quả cuối cùng được AckPolicy quyết định: ack / nack+requeue / nack (reject).
+
+use crate::event_bus::FailurePolicy;
+use crate::versioning::{UpcastError, Upcasters, Versioned};
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use lapin::{
+    message::Delivery,
+    options::{BasicAckOptions, BasicNackOptions},
+    Consumer, Result as LapinResult,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Delivery + thông tin chung cho mọi layer
+#[derive(Debug)]
+pub struct Context {
+    pub delivery: Delivery,
+    pub received_at: Instant,
+}
+
+impl Context {
+    pub fn new(delivery: Delivery) -> Self {
+        Context {
+            delivery,
+            received_at: Instant::now(),
+        }
+    }
+
+    pub fn routing_key(&self) -> &str {
+        self.delivery.routing_key.as_str()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandlerError {
+    // Body không decode được - chạy lại vô ích
+    Decode(String),
+    Timeout(Duration),
+    Panic(String),
+    // Lỗi bình thường từ handler (có thể thử lại)
+    Failed(String),
+    // Handler chủ động từ chối message (không retry, không requeue)
+    Reject(String),
+    // Sai JSON Schema (src/schema.rs). dead_lettered = bản sao kèm lỗi đã được publish sang DLX
+    Invalid { errors: Vec<String>, dead_lettered: bool },
+    // Circuit breaker đang mở (src/circuit_breaker.rs) - handler không chạy, message trả lại queue
+    CircuitOpen,
+    // Message version mới hơn consumer (src/versioning.rs) - message đúng, consumer cũ:
+    // phải để consumer đã deploy version mới xử lý, không được reject
+    FutureVersion(String),
+    // Sai schema nhưng bản sao chưa được DLX confirm (src/schema.rs) → bản gốc phải quay lại queue
+    DeadLetterFailed(String),
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandlerError::Decode(e) => write!(f, "decode error: {}", e),
+            HandlerError::Timeout(after) => write!(f, "timed out after {:?}", after),
+            HandlerError::Panic(e) => write!(f, "handler panicked: {}", e),
+            HandlerError::Failed(e) => write!(f, "{}", e),
+            HandlerError::Reject(e) => write!(f, "rejected: {}", e),
+            HandlerError::Invalid { errors, .. } => write!(f, "schema validation failed: {}", errors.join("; ")),
+            HandlerError::CircuitOpen => write!(f, "circuit breaker open"),
+            HandlerError::DeadLetterFailed(e) => write!(f, "dead-letter failed: {}", e),
+            HandlerError::FutureVersion(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HandlerError {}
+
+impl From<String> for HandlerError {
+    fn from(e: String) -> Self {
+        HandlerError::Failed(e)
+    }
+}
+
+impl From<&str> for HandlerError {
+    fn from(e: &str) -> Self {
+        HandlerError::Failed(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for HandlerError {
+    fn from(e: std::io::Error) -> Self {
+        HandlerError::Failed(e.to_string())
+    }
+}
+
+pub type HandlerResult = Result<(), HandlerError>;
+
+// Handler đã type-erase: mỗi layer nhận `inner` và trả về handler mới bọc nó
+pub type BoxHandler = Arc<dyn Fn(Arc<Context>) -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+
+pub trait Layer: Send + Sync {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler;
+}
+
+// Layer tự viết bằng closure: from_fn(|ctx, next| async move { ...; next(ctx).await })
+pub fn from_fn<F, Fut>(f: F) -> impl Layer
+where
+    F: Fn(Arc<Context>, BoxHandler) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    FnLayer(Arc::new(f))
+}
+
+struct FnLayer<F>(Arc<F>);
+
+impl<F, Fut> Layer for FnLayer<F>
+where
+    F: Fn(Arc<Context>, BoxHandler) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let f = self.0.clone();
+        Arc::new(move |ctx| f(ctx, inner.clone()).boxed())
+    }
+}
+
+// Hết thời gian → HandlerError::Timeout (future của handler bị drop)
+pub struct TimeoutLayer {
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    pub fn new(timeout: Duration) -> Self {
+        TimeoutLayer { timeout }
+    }
+}
+
+impl Layer for TimeoutLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let timeout = self.timeout;
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            async move {
+                tokio::time::timeout(timeout, inner(ctx))
+                    .await
+                    .unwrap_or(Err(HandlerError::Timeout(timeout)))
+            }
+            .boxed()
+        })
+    }
+}
+
+// Panic trong handler → HandlerError::Panic thay vì làm chết consumer loop
+pub struct CatchPanicLayer;
+
+impl Layer for CatchPanicLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            async move {
+                match AssertUnwindSafe(inner(ctx)).catch_unwind().await {
+                    Ok(result) => result,
+                    Err(panic) => {
+                        let message = panic
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| panic.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "unknown panic".to_string());
+                        Err(HandlerError::Panic(message))
+                    }
+                }
+            }
+            .boxed()
+        })
+    }
+}
+
+pub struct LoggingLayer {
+    name: String,
+}
+
+impl LoggingLayer {
+    pub fn new(name: &str) -> Self {
+        LoggingLayer {
+            name: name.to_string(),
+        }
+    }
+}
+
+impl Layer for LoggingLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let name = self.name.clone();
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            let name = name.clone();
+            async move {
+                let started = Instant::now();
+                let routing_key = ctx.routing_key().to_string();
+                let redelivered = if ctx.delivery.redelivered { " (redelivered)" } else { "" };
+                let result = inner(ctx).await;
+                match &result {
+                    Ok(()) => println!(
+                        "✓ [{}] {}{} handled in {:?}",
+                        name, routing_key, redelivered, started.elapsed()
+                    ),
+                    Err(e) => println!(
+                        "✗ [{}] {}{} failed after {:?}: {}",
+                        name, routing_key, redelivered, started.elapsed(), e
+                    ),
+                }
+                result
+            }
+            .boxed()
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PipelineMetrics {
+    handled: AtomicU64,
+    failed: AtomicU64,
+    decode_errors: AtomicU64,
+    timeouts: AtomicU64,
+    panics: AtomicU64,
+    invalid: AtomicU64,
+    total_latency_us: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PipelineMetricsSnapshot {
+    pub handled: u64,
+    pub failed: u64,
+    pub decode_errors: u64,
+    pub timeouts: u64,
+    pub panics: u64,
+    pub invalid: u64,
+    pub mean_latency_us: u64,
+}
+
+impl PipelineMetrics {
+    pub fn snapshot(&self) -> PipelineMetricsSnapshot {
+        let handled = self.handled.load(Ordering::Relaxed);
+        let failed = self.failed.load(Ordering::Relaxed);
+        let total = handled + failed;
+        PipelineMetricsSnapshot {
+            handled,
+            failed,
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            panics: self.panics.load(Ordering::Relaxed),
+            invalid: self.invalid.load(Ordering::Relaxed),
+            mean_latency_us: self
+                .total_latency_us
+                .load(Ordering::Relaxed)
+                .checked_div(total)
+                .unwrap_or(0),
+        }
+    }
+}
+
+pub struct MetricsLayer {
+    metrics: Arc<PipelineMetrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<PipelineMetrics>) -> Self {
+        MetricsLayer { metrics }
+    }
+}
+
+impl Layer for MetricsLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let metrics = self.metrics.clone();
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            let metrics = metrics.clone();
+            async move {
+                let started = Instant::now();
+                let result = inner(ctx).await;
+                let counter = match &result {
+                    Ok(()) => &metrics.handled,
+                    Err(e) => {
+                        match e {
+                            HandlerError::Decode(_) => metrics.decode_errors.fetch_add(1, Ordering::Relaxed),
+                            HandlerError::Timeout(_) => metrics.timeouts.fetch_add(1, Ordering::Relaxed),
+                            HandlerError::Panic(_) => metrics.panics.fetch_add(1, Ordering::Relaxed),
+                            HandlerError::Invalid { .. } => metrics.invalid.fetch_add(1, Ordering::Relaxed),
+                            _ => 0,
+                        };
+                        &metrics.failed
+                    }
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                metrics
+                    .total_latency_us
+                    .fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+                result
+            }
+            .boxed()
+        })
+    }
+}
+
+// Thử lại ngay trong process với Failed / Timeout (Decode, Panic, Reject không retry)
+pub struct RetryLayer {
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl RetryLayer {
+    // `attempts` = số lần chạy lại tối đa, chờ backoff, 2×backoff, 4×backoff...
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        RetryLayer { attempts, backoff }
+    }
+}
+
+impl Layer for RetryLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let (attempts, backoff) = (self.attempts, self.backoff);
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            async move {
+                let mut attempt = 0;
+                loop {
+                    match inner(ctx.clone()).await {
+                        Err(HandlerError::Failed(_) | HandlerError::Timeout(_)) if attempt < attempts => {
+                            tokio::time::sleep(backoff * 2u32.pow(attempt.min(10))).await;
+                            attempt += 1;
+                        }
+                        result => return result,
+                    }
+                }
+            }
+            .boxed()
+        })
+    }
+}
+
+// Quyết định ack/nack theo loại lỗi
+#[derive(Debug, Clone, Copy)]
+pub struct AckPolicy {
+    pub on_decode_error: FailurePolicy,
+    pub on_timeout: FailurePolicy,
+    pub on_panic: FailurePolicy,
+    pub on_failure: FailurePolicy,
+    // Sai schema mà chưa dead-letter kèm headers (đã dead-letter → luôn ack)
+    pub on_invalid: FailurePolicy,
+    // Version mới hơn consumer. Requeue giữ cả khi redelivered (chờ consumer mới);
+    // đổi thành Reject nếu queue có DLX để gom lại
+    pub on_future_version: FailurePolicy,
+    // false: message đã redelivered mà lại lỗi → Reject thay vì Requeue (tránh lặp vô hạn)
+    pub requeue_redelivered: bool,
+}
+
+impl Default for AckPolicy {
+    fn default() -> Self {
+        AckPolicy {
+            on_decode_error: FailurePolicy::Reject,
+            on_timeout: FailurePolicy::Requeue,
+            on_panic: FailurePolicy::Reject,
+            on_failure: FailurePolicy::Requeue,
+            on_invalid: FailurePolicy::Reject,
+            on_future_version: FailurePolicy::Requeue,
+            requeue_redelivered: false,
+        }
+    }
+}
+
+impl AckPolicy {
+    // None = ack
+    pub fn decide(&self, result: &HandlerResult, redelivered: bool) -> Option<FailurePolicy> {
+        let policy = match result {
+            Ok(()) => return None,
+            Err(HandlerError::Decode(_)) => self.on_decode_error,
+            Err(HandlerError::Timeout(_)) => self.on_timeout,
+            Err(HandlerError::Panic(_)) => self.on_panic,
+            Err(HandlerError::Failed(_)) => self.on_failure,
+            Err(HandlerError::Reject(_)) => FailurePolicy::Reject,
+            Err(HandlerError::Invalid { dead_lettered: true, .. }) => FailurePolicy::Ignore,
+            Err(HandlerError::Invalid { .. }) => self.on_invalid,
+            // Không phải lỗi của message → luôn requeue, kể cả khi đã redelivered
+            Err(HandlerError::CircuitOpen) => return Some(FailurePolicy::Requeue),
+            // Reject lúc này sẽ làm mất message sai schema (chưa có bản sao nào)
+            Err(HandlerError::DeadLetterFailed(_)) => return Some(FailurePolicy::Requeue),
+            Err(HandlerError::FutureVersion(_)) => {
+                return match self.on_future_version {
+                    FailurePolicy::Ignore => None,
+                    policy => Some(policy),
+                };
+            }
+        };
+        match policy {
+            FailurePolicy::Ignore => None,
+            FailurePolicy::Requeue if redelivered && !self.requeue_redelivered => Some(FailurePolicy::Reject),
+            policy => Some(policy),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PipelineBuilder {
+    layers: Vec<Box<dyn Layer>>,
+    ack_policy: AckPolicy,
+}
+
+impl PipelineBuilder {
+    // Layer thêm trước nằm ngoài (chạy trước, thấy kết quả sau cùng)
+    pub fn layer(mut self, layer: impl Layer + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    pub fn with_ack_policy(mut self, ack_policy: AckPolicy) -> Self {
+        self.ack_policy = ack_policy;
+        self
+    }
+
+    // Handler nhận raw Context (tự decode)
+    pub fn handler<F, Fut>(self, handler: F) -> Pipeline
+    where
+        F: Fn(Arc<Context>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let endpoint: BoxHandler = Arc::new(move |ctx| handler(ctx).boxed());
+        let handler = self
+            .layers
+            .iter()
+            .rev()
+            .fold(endpoint, |inner, layer| layer.wrap(inner));
+        Pipeline {
+            handler,
+            ack_policy: self.ack_policy,
+        }
+    }
+
+    // Decode layer: body JSON → T, lỗi → HandlerError::Decode
+    pub fn json<T, F, Fut>(self, handler: F) -> Pipeline
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(T, Arc<Context>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.handler(move |ctx: Arc<Context>| {
+            let handler = handler.clone();
+            async move {
+                let payload = serde_json::from_slice::<T>(&ctx.delivery.data)
+                    .map_err(|e| HandlerError::Decode(e.to_string()))?;
+                handler(payload, ctx).await
+            }
+        })
+    }
+
+    // Như json() nhưng payload version cũ được upcast lên version hiện tại của T (src/versioning.rs).
+    // Version mới hơn consumer → HandlerError::FutureVersion; sai type / upcast lỗi → HandlerError::Decode
+    pub fn versioned<T, F, Fut>(self, upcasters: Upcasters<T>, handler: F) -> Pipeline
+    where
+        T: Versioned + Send + 'static,
+        F: Fn(T, Arc<Context>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let upcasters = Arc::new(upcasters);
+        self.handler(move |ctx: Arc<Context>| {
+            let handler = handler.clone();
+            let upcasters = upcasters.clone();
+            async move {
+                let payload = upcasters.decode_delivery(&ctx.delivery).map_err(|e| match e {
+                    UpcastError::FutureVersion { .. } => HandlerError::FutureVersion(e.to_string()),
+                    e => HandlerError::Decode(e.to_string()),
+                })?;
+                handler(payload, ctx).await
+            }
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Pipeline {
+    handler: BoxHandler,
+    ack_policy: AckPolicy,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    // Chạy qua các layers, KHÔNG ack/nack
+    pub async fn call(&self, delivery: Delivery) -> (Arc<Context>, HandlerResult) {
+        let ctx = Arc::new(Context::new(delivery));
+        let result = (self.handler)(ctx.clone()).await;
+        (ctx, result)
+    }
+
+    // Chạy + ack/nack theo AckPolicy. Trả về quyết định (None = ack)
+    pub async fn handle(&self, delivery: Delivery) -> LapinResult<Option<FailurePolicy>> {
+        let (ctx, result) = self.call(delivery).await;
+        let decision = self.ack_policy.decide(&result, ctx.delivery.redelivered);
+        match decision {
+            None => ctx.delivery.ack(BasicAckOptions::default()).await?,
+            Some(policy) => {
+                ctx.delivery
+                    .nack(BasicNackOptions {
+                        requeue: policy == FailurePolicy::Requeue,
+                        ..Default::default()
+                    })
+                    .await?
+            }
+        }
+        Ok(decision)
+    }
+
+    // Consume tuần tự cho tới khi consumer kết thúc
+    pub async fn run(&self, mut consumer: Consumer) -> LapinResult<()> {
+        while let Some(delivery) = consumer.next().await {
+            self.handle(delivery?).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::atomic::AtomicU32;
+
+    fn delivery(data: &[u8], redelivered: bool) -> Delivery {
+        Delivery {
+            delivery_tag: 1,
+            exchange: "".into(),
+            routing_key: "task_queue".into(),
+            redelivered,
+            properties: Default::default(),
+            data: data.to_vec(),
+            acker: Default::default(),
+        }
+    }
+
+    // Handler trả lần lượt các lỗi trong `errors`, hết thì Ok; đếm số lần được gọi
+    fn scripted(layer: impl Layer + 'static, errors: Vec<HandlerError>) -> (Pipeline, Arc<AtomicU32>) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let pipeline = Pipeline::builder().layer(layer).handler(move |_ctx| {
+            let call = counter.fetch_add(1, Ordering::SeqCst) as usize;
+            let result = errors.get(call).cloned().map_or(Ok(()), Err);
+            async move { result }
+        });
+        (pipeline, calls)
+    }
+
+    #[test]
+    fn redelivered_requeue_becomes_reject() {
+        let policy = AckPolicy::default();
+        let failed: HandlerResult = Err(HandlerError::Failed("db down".into()));
+        assert_eq!(policy.decide(&Ok(()), true), None);
+        assert_eq!(policy.decide(&failed, false), Some(FailurePolicy::Requeue));
+        assert_eq!(policy.decide(&failed, true), Some(FailurePolicy::Reject));
+
+        let requeue_always = AckPolicy { requeue_redelivered: true, ..policy };
+        assert_eq!(requeue_always.decide(&failed, true), Some(FailurePolicy::Requeue));
+    }
+
+    #[test]
+    fn circuit_open_always_requeues() {
+        let policy = AckPolicy {
+            on_failure: FailurePolicy::Ignore,
+            ..Default::default()
+        };
+        let open: HandlerResult = Err(HandlerError::CircuitOpen);
+        assert_eq!(policy.decide(&open, false), Some(FailurePolicy::Requeue));
+        assert_eq!(policy.decide(&open, true), Some(FailurePolicy::Requeue));
+    }
+
+    #[tokio::test]
+    async fn retry_layer_retries_failed_and_timeout_only() {
+        let retry = || RetryLayer::new(3, Duration::from_millis(1));
+
+        let (pipeline, calls) = scripted(
+            retry(),
+            vec![HandlerError::Failed("flaky".into()), HandlerError::Timeout(Duration::from_millis(1))],
+        );
+        assert_eq!(pipeline.call(delivery(b"{}", false)).await.1, Ok(()));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        for error in [HandlerError::Decode("bad json".into()), HandlerError::Reject("no".into())] {
+            let (pipeline, calls) = scripted(retry(), vec![error.clone(); 4]);
+            assert_eq!(pipeline.call(delivery(b"{}", false)).await.1, Err(error));
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+        }
+
+        // Hết số lần thử → trả lỗi cuối cùng
+        let (pipeline, calls) = scripted(retry(), vec![HandlerError::Failed("down".into()); 10]);
+        assert_eq!(pipeline.call(delivery(b"{}", false)).await.1, Err(HandlerError::Failed("down".into())));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn timeout_layer_cancels_slow_handler() {
+        let timeout = Duration::from_millis(20);
+        let pipeline = Pipeline::builder().layer(TimeoutLayer::new(timeout)).handler(|_ctx| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        });
+        assert_eq!(pipeline.call(delivery(b"{}", false)).await.1, Err(HandlerError::Timeout(timeout)));
+    }
+
+    #[tokio::test]
+    async fn catch_panic_layer_turns_panic_into_error() {
+        let pipeline = Pipeline::builder().layer(CatchPanicLayer).handler(|ctx| async move {
+            if ctx.delivery.data.is_empty() {
+                panic!("empty body");
+            }
+            Ok(())
+        });
+        assert_eq!(pipeline.call(delivery(b"", false)).await.1, Err(HandlerError::Panic("empty body".into())));
+        assert_eq!(pipeline.call(delivery(b"{}", false)).await.1, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn metrics_layer_counts_outcomes() {
+        #[derive(Deserialize)]
+        struct Task {
+            fail: bool,
+        }
+
+        let metrics = Arc::new(PipelineMetrics::default());
+        let pipeline = Pipeline::builder()
+            .layer(MetricsLayer::new(metrics.clone()))
+            .json(|task: Task, _ctx| async move {
+                if task.fail { Err("task failed".into()) } else { Ok(()) }
+            });
+        for body in [&br#"{"fail": false}"#[..], br#"{"fail": false}"#, br#"{"fail": true}"#, b"not json"] {
+            let _ = pipeline.call(delivery(body, false)).await;
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.handled, snapshot.failed, snapshot.decode_errors), (2, 2, 1));
+        assert_eq!((snapshot.timeouts, snapshot.panics, snapshot.invalid), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn handle_returns_ack_decision() {
+        let pipeline = Pipeline::builder().json(|task: serde_json::Value, _ctx| async move {
+            match task["action"].as_str() {
+                Some("reject") => Err(HandlerError::Reject("not allowed".into())),
+                Some("fail") => Err("temporary".into()),
+                _ => Ok(()),
+            }
+        });
+        assert_eq!(pipeline.handle(delivery(br#"{"action": "ok"}"#, false)).await.unwrap(), None);
+        assert_eq!(
+            pipeline.handle(delivery(br#"{"action": "fail"}"#, false)).await.unwrap(),
+            Some(FailurePolicy::Requeue)
+        );
+        assert_eq!(
+            pipeline.handle(delivery(br#"{"action": "fail"}"#, true)).await.unwrap(),
+            Some(FailurePolicy::Reject)
+        );
+        assert_eq!(
+            pipeline.handle(delivery(br#"{"action": "reject"}"#, false)).await.unwrap(),
+            Some(FailurePolicy::Reject)
+        );
+        assert_eq!(pipeline.handle(delivery(b"{", false)).await.unwrap(), Some(FailurePolicy::Reject));
+    }
+}
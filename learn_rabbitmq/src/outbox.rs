@@ -0,0 +1,573 @@
+// Transactional outbox (SQLite)
+// Vấn đề: ghi DB rồi `basic_publish` KHÔNG atomic - crash ở giữa → mất event hoặc event "ma".
+// Giải pháp: ghi event vào bảng `outbox` trong CÙNG transaction với business data,
+// relay đọc bảng outbox → publish (có confirm) → đánh dấu đã gửi.
+//   - Relay crash sau publish, trước khi đánh dấu → event được gửi lại (at-least-once),
+//     consumer dùng message_id + dedup store (src/dedup.rs) để bỏ trùng
+//   - Nhiều relay chạy cùng lúc: mỗi relay "thuê" (lease) 1 batch rows, không publish trùng
+//     trừ khi lease hết hạn
+//   - Row publish lỗi được thử lại sau backoff tăng dần (dùng lại `locked_until_ms`),
+//     quá `max_attempts` lần → "parked" (không relay nữa, chờ người xử lý), nên 1 row hỏng
+//     (vd: exchange không tồn tại → 404) không chặn các rows phía sau
+
+use crate::batch::{BatchPublisher, OutgoingMessage, PublishOutcome};
+use crate::dedup::sqlite_error;
+use crate::util::now_millis;
+use lapin::{BasicProperties, Connection, Result as LapinResult};
+use rusqlite::{params, Connection as SqliteConnection, OptionalExtension};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS outbox (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    message_id      TEXT NOT NULL UNIQUE,
+    exchange        TEXT NOT NULL,
+    routing_key     TEXT NOT NULL,
+    content_type    TEXT,
+    payload         BLOB NOT NULL,
+    created_at_ms   INTEGER NOT NULL,
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    last_error      TEXT,
+    locked_by       TEXT,
+    locked_until_ms INTEGER,
+    sent_at_ms      INTEGER,
+    parked_at_ms    INTEGER
+);
+CREATE INDEX IF NOT EXISTS outbox_pending ON outbox (sent_at_ms, id);
+";
+
+pub fn init_schema(conn: &SqliteConnection) -> rusqlite::Result<()> {
+    conn.execute_batch(SCHEMA)
+}
+
+#[derive(Debug, Clone)]
+pub struct NewOutboxEvent {
+    pub message_id: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub content_type: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+impl NewOutboxEvent {
+    pub fn json<T: serde::Serialize>(
+        message_id: &str,
+        exchange: &str,
+        routing_key: &str,
+        event: &T,
+    ) -> serde_json::Result<Self> {
+        Ok(NewOutboxEvent {
+            message_id: message_id.to_string(),
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+            content_type: Some("application/json".to_string()),
+            payload: serde_json::to_vec(event)?,
+        })
+    }
+}
+
+// Ghi event vào outbox. Gọi với transaction của application:
+//   let tx = conn.transaction()?;
+//   tx.execute("INSERT INTO orders ...", ...)?;
+//   outbox::enqueue(&tx, &event)?;
+//   tx.commit()?;
+pub fn enqueue(conn: &SqliteConnection, event: &NewOutboxEvent) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO outbox (message_id, exchange, routing_key, content_type, payload, created_at_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            event.message_id,
+            event.exchange,
+            event.routing_key,
+            event.content_type,
+            event.payload,
+            now_millis(),
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+#[derive(Debug, Clone)]
+struct OutboxRow {
+    id: i64,
+    message_id: String,
+    exchange: String,
+    routing_key: String,
+    content_type: Option<String>,
+    payload: Vec<u8>,
+    attempts: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    pub db_path: PathBuf,
+    // Tên relay (ghi vào `locked_by`), phải khác nhau giữa các relay chạy song song
+    pub relay_id: String,
+    pub batch_size: usize,
+    pub poll_interval: Duration,
+    // Thời gian giữ lease; relay crash → rows được relay khác nhận lại sau khoảng này
+    pub lease: Duration,
+    // Row lỗi được thử lại sau retry_backoff * 2^(attempts-1), tối đa max_backoff
+    pub retry_backoff: Duration,
+    pub max_backoff: Duration,
+    // Lỗi đủ số lần này → parked
+    pub max_attempts: u32,
+}
+
+impl RelayConfig {
+    pub fn new(db_path: impl AsRef<Path>, relay_id: &str) -> Self {
+        RelayConfig {
+            db_path: db_path.as_ref().to_path_buf(),
+            relay_id: relay_id.to_string(),
+            batch_size: 100,
+            poll_interval: Duration::from_millis(500),
+            lease: Duration::from_secs(30),
+            retry_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5 * 60),
+            max_attempts: 10,
+        }
+    }
+
+    // Thời gian chờ trước lần thử tiếp theo, sau `attempts` lần lỗi
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        self.retry_backoff
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RelayStats {
+    pub claimed: usize,
+    pub sent: usize,
+    pub failed: usize,
+    pub parked: usize,
+    pub channel_reopened: bool,
+}
+
+pub struct OutboxRelay {
+    config: RelayConfig,
+    db: SqliteConnection,
+    connection: Arc<Connection>,
+    publisher: BatchPublisher,
+}
+
+impl OutboxRelay {
+    // Relay mở channel riêng (confirm mode) trên `connection`, và mở lại khi channel bị broker đóng
+    pub async fn new(config: RelayConfig, connection: Arc<Connection>) -> LapinResult<Self> {
+        let db = open_db(&config.db_path).map_err(|e| lapin::Error::IOError(e.into()))?;
+        let publisher = BatchPublisher::new(connection.create_channel().await?).await?;
+        Ok(OutboxRelay {
+            config,
+            db,
+            connection,
+            publisher,
+        })
+    }
+
+    // Poll liên tục cho tới khi channel lỗi
+    pub async fn run(&mut self) -> LapinResult<()> {
+        println!(
+            "✓ Outbox relay '{}' polling {} every {:?}",
+            self.config.relay_id,
+            self.config.db_path.display(),
+            self.config.poll_interval
+        );
+        loop {
+            let stats = self.run_once().await?;
+            if stats.claimed > 0 {
+                println!(
+                    "✓ Relay '{}': sent={} failed={} parked={}",
+                    self.config.relay_id, stats.sent, stats.failed, stats.parked
+                );
+            }
+            // Còn đầy batch → có thể còn rows, poll tiếp ngay
+            if stats.claimed < self.config.batch_size {
+                tokio::time::sleep(self.config.poll_interval).await;
+            }
+        }
+    }
+
+    // 1 vòng: lease batch → publish với confirms → đánh dấu sent / ghi lỗi
+    pub async fn run_once(&mut self) -> LapinResult<RelayStats> {
+        let rows = claim_batch(&mut self.db, &self.config).map_err(io_error)?;
+        if rows.is_empty() {
+            return Ok(RelayStats::default());
+        }
+
+        let report = self.publisher.publish_batch(rows.iter().map(outgoing)).await;
+
+        let mut stats = RelayStats {
+            claimed: rows.len(),
+            ..Default::default()
+        };
+        let outcomes: Vec<&PublishOutcome> = report.results.iter().map(|r| &r.outcome).collect();
+        let channel_closed = !self.publisher.channel().status().connected();
+        let mut suspects = Vec::new();
+        for (result, settle) in report.results.iter().zip(settle_batch(&outcomes, channel_closed)) {
+            let row = &rows[result.index];
+            match settle {
+                Settle::Isolate => suspects.push(row),
+                settle => self.apply(row, settle, &result.outcome, &mut stats).map_err(io_error)?,
+            }
+        }
+
+        if channel_closed {
+            self.reopen_channel().await?;
+            stats.channel_reopened = true;
+        }
+
+        // Không biết row nào làm channel bị đóng → publish lại từng row một để chỉ tính lỗi đúng row đó
+        for (i, row) in suspects.iter().enumerate() {
+            let report = self.publisher.publish_batch(std::iter::once(outgoing(row))).await;
+            let outcome = &report.results[0].outcome;
+            let channel_closed = !self.publisher.channel().status().connected();
+            let settle = settle_batch(&[outcome], channel_closed)[0];
+            self.apply(row, settle, outcome, &mut stats).map_err(io_error)?;
+            if channel_closed
+                && let Err(e) = self.reopen_channel().await
+            {
+                // Các suspect còn lại được trả về pending, không tính attempt
+                for row in &suspects[i + 1..] {
+                    unlock(&self.db, row.id).map_err(io_error)?;
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn apply(&self, row: &OutboxRow, settle: Settle, outcome: &PublishOutcome, stats: &mut RelayStats) -> io::Result<()> {
+        match settle {
+            Settle::Sent => {
+                stats.sent += 1;
+                mark_sent(&self.db, row.id)
+            }
+            Settle::Failed => {
+                stats.failed += 1;
+                if mark_failed(&self.db, &self.config, row, &format!("{:?}", outcome))? {
+                    stats.parked += 1;
+                }
+                Ok(())
+            }
+            Settle::Isolate => unlock(&self.db, row.id),
+        }
+    }
+
+    async fn reopen_channel(&mut self) -> LapinResult<()> {
+        // Connection chết → dừng relay, rows chưa gửi sẽ được lease lại sau
+        if !self.connection.status().connected() {
+            return Err(lapin::Error::InvalidChannelState(
+                self.publisher.channel().status().state(),
+            ));
+        }
+        println!("⚠️  Relay '{}': channel closed by broker, reopening", self.config.relay_id);
+        self.publisher = BatchPublisher::new(self.connection.create_channel().await?).await?;
+        Ok(())
+    }
+}
+
+fn outgoing(row: &OutboxRow) -> OutgoingMessage {
+    let mut properties = BasicProperties::default()
+        .with_delivery_mode(2)
+        .with_message_id(row.message_id.clone().into());
+    if let Some(content_type) = &row.content_type {
+        properties = properties.with_content_type(content_type.clone().into());
+    }
+    OutgoingMessage::new(&row.exchange, &row.routing_key, row.payload.clone()).with_properties(properties)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Settle {
+    Sent,
+    // Tính 1 attempt, hẹn thử lại sau backoff
+    Failed,
+    // Chưa biết có lỗi hay không → publish lại riêng
+    Isolate,
+}
+
+// Lỗi channel-level (vd: 404 exchange không tồn tại) đóng channel → mọi message chưa được confirm
+// cũng lỗi theo, kể cả message hợp lệ publish TRƯỚC row hỏng. Khi đó chỉ tính lỗi nếu duy nhất
+// 1 row chưa ack; còn lại thì chưa biết row nào gây ra → Isolate tất cả.
+fn settle_batch(outcomes: &[&PublishOutcome], channel_closed: bool) -> Vec<Settle> {
+    let unacked = outcomes.iter().filter(|o| !matches!(o, PublishOutcome::Acked)).count();
+    outcomes
+        .iter()
+        .map(|outcome| match outcome {
+            PublishOutcome::Acked => Settle::Sent,
+            _ if channel_closed && unacked > 1 => Settle::Isolate,
+            _ => Settle::Failed,
+        })
+        .collect()
+}
+
+fn claim_batch(db: &mut SqliteConnection, config: &RelayConfig) -> io::Result<Vec<OutboxRow>> {
+    let now = now_millis();
+    let locked_until = now + config.lease.as_millis() as i64;
+
+    // IMMEDIATE: lấy write lock ngay → 2 relay không claim cùng rows
+    let tx = db
+        .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+        .map_err(sqlite_error)?;
+    tx.execute(
+        "UPDATE outbox SET locked_by = ?1, locked_until_ms = ?2
+         WHERE id IN (
+             SELECT id FROM outbox
+             WHERE sent_at_ms IS NULL AND parked_at_ms IS NULL
+               AND (locked_until_ms IS NULL OR locked_until_ms < ?3)
+             ORDER BY id LIMIT ?4
+         )",
+        params![config.relay_id, locked_until, now, config.batch_size as i64],
+    )
+    .map_err(sqlite_error)?;
+
+    let rows = {
+        let mut stmt = tx
+            .prepare(
+                "SELECT id, message_id, exchange, routing_key, content_type, payload, attempts FROM outbox
+                 WHERE sent_at_ms IS NULL AND locked_by = ?1 AND locked_until_ms = ?2
+                 ORDER BY id",
+            )
+            .map_err(sqlite_error)?;
+        stmt.query_map(params![config.relay_id, locked_until], |row| {
+            Ok(OutboxRow {
+                id: row.get(0)?,
+                message_id: row.get(1)?,
+                exchange: row.get(2)?,
+                routing_key: row.get(3)?,
+                content_type: row.get(4)?,
+                payload: row.get(5)?,
+                attempts: row.get(6)?,
+            })
+        })
+        .map_err(sqlite_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(sqlite_error)?
+    };
+    tx.commit().map_err(sqlite_error)?;
+
+    Ok(rows)
+}
+
+fn mark_sent(db: &SqliteConnection, id: i64) -> io::Result<()> {
+    db
+        .execute(
+            "UPDATE outbox SET sent_at_ms = ?2, attempts = attempts + 1,
+                 locked_by = NULL, locked_until_ms = NULL, last_error = NULL
+             WHERE id = ?1",
+            params![id, now_millis()],
+        )
+        .map_err(sqlite_error)?;
+    Ok(())
+}
+
+// Ghi lỗi, hẹn lần thử sau bằng `locked_until_ms`. Trả về true nếu row bị parked
+fn mark_failed(db: &SqliteConnection, config: &RelayConfig, row: &OutboxRow, error: &str) -> io::Result<bool> {
+    let now = now_millis();
+    let attempts = row.attempts + 1;
+    let parked_at = (attempts >= config.max_attempts).then_some(now);
+    let retry_at = now + config.backoff(attempts).as_millis() as i64;
+    db
+        .execute(
+            "UPDATE outbox SET attempts = ?2, last_error = ?3,
+                 locked_by = NULL, locked_until_ms = ?4, parked_at_ms = ?5
+             WHERE id = ?1",
+            params![row.id, attempts, error, retry_at, parked_at],
+        )
+        .map_err(sqlite_error)?;
+    if parked_at.is_some() {
+        println!(
+            "✗ Outbox row {} ({}) parked after {} attempts: {}",
+            row.id, row.message_id, attempts, error
+        );
+    }
+    Ok(parked_at.is_some())
+}
+
+// Trả row về pending ngay, không tính attempt
+fn unlock(db: &SqliteConnection, id: i64) -> io::Result<()> {
+    db
+        .execute(
+            "UPDATE outbox SET locked_by = NULL, locked_until_ms = NULL WHERE id = ?1",
+            params![id],
+        )
+        .map_err(sqlite_error)?;
+    Ok(())
+}
+
+pub fn open_db(path: &Path) -> io::Result<SqliteConnection> {
+    let conn = SqliteConnection::open(path).map_err(sqlite_error)?;
+    conn.busy_timeout(Duration::from_secs(5)).map_err(sqlite_error)?;
+    // WAL: application ghi và relay đọc cùng lúc không chặn nhau
+    conn.pragma_update(None, "journal_mode", "WAL")
+        .map_err(sqlite_error)?;
+    init_schema(&conn).map_err(sqlite_error)?;
+    Ok(conn)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutboxCounts {
+    pub pending: i64,
+    pub sent: i64,
+    pub failing: i64,
+    pub parked: i64,
+}
+
+pub fn counts(conn: &SqliteConnection) -> rusqlite::Result<OutboxCounts> {
+    conn.query_row(
+        "SELECT
+             COUNT(*) FILTER (WHERE sent_at_ms IS NULL AND parked_at_ms IS NULL),
+             COUNT(*) FILTER (WHERE sent_at_ms IS NOT NULL),
+             COUNT(*) FILTER (WHERE sent_at_ms IS NULL AND parked_at_ms IS NULL AND last_error IS NOT NULL),
+             COUNT(*) FILTER (WHERE parked_at_ms IS NOT NULL)
+         FROM outbox",
+        [],
+        |row| {
+            Ok(OutboxCounts {
+                pending: row.get(0)?,
+                sent: row.get(1)?,
+                failing: row.get(2)?,
+                parked: row.get(3)?,
+            })
+        },
+    )
+}
+
+// Xóa rows đã gửi cũ hơn `older_than`
+pub fn purge_sent(conn: &SqliteConnection, older_than: Duration) -> rusqlite::Result<usize> {
+    let cutoff = now_millis() - older_than.as_millis() as i64;
+    conn.execute(
+        "DELETE FROM outbox WHERE sent_at_ms IS NOT NULL AND sent_at_ms < ?1",
+        params![cutoff],
+    )
+}
+
+// Đưa rows parked về pending (sau khi đã sửa nguyên nhân, vd: tạo exchange còn thiếu)
+pub fn unpark(conn: &SqliteConnection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE outbox SET parked_at_ms = NULL, attempts = 0, locked_until_ms = NULL
+         WHERE parked_at_ms IS NOT NULL",
+        [],
+    )
+}
+
+// Event đã có trong outbox chưa (vd: để application kiểm tra trước khi enqueue lại)
+pub fn contains(conn: &SqliteConnection, message_id: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT 1 FROM outbox WHERE message_id = ?1",
+        params![message_id],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|found| found.is_some())
+}
+
+fn io_error(e: io::Error) -> lapin::Error {
+    lapin::Error::IOError(e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db(name: &str) -> (SqliteConnection, PathBuf) {
+        let path = std::env::temp_dir().join(format!("learn_rabbitmq_outbox_{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        (open_db(&path).unwrap(), path)
+    }
+
+    fn enqueue_event(db: &SqliteConnection, message_id: &str, exchange: &str) {
+        let event = NewOutboxEvent::json(message_id, exchange, "order.created", &serde_json::json!({"id": 1})).unwrap();
+        enqueue(db, &event).unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let config = RelayConfig::new("unused.db", "relay-1");
+        assert_eq!(config.backoff(1), Duration::from_secs(1));
+        assert_eq!(config.backoff(2), Duration::from_secs(2));
+        assert_eq!(config.backoff(4), Duration::from_secs(8));
+        assert_eq!(config.backoff(20), config.max_backoff);
+        assert_eq!(config.backoff(u32::MAX), config.max_backoff);
+    }
+
+    #[test]
+    fn failed_row_waits_for_backoff_without_blocking_later_rows() {
+        let (mut db, path) = test_db("backoff");
+        let config = RelayConfig::new(&path, "relay-1");
+        enqueue_event(&db, "poison", "missing_exchange");
+        enqueue_event(&db, "good", "logs_topic");
+
+        let rows = claim_batch(&mut db, &config).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(!mark_failed(&db, &config, &rows[0], "NOT_FOUND").unwrap());
+        unlock(&db, rows[1].id).unwrap();
+
+        // Row lỗi chờ backoff, row phía sau được claim ngay
+        let rows = claim_batch(&mut db, &config).unwrap();
+        assert_eq!(rows.iter().map(|r| r.message_id.as_str()).collect::<Vec<_>>(), ["good"]);
+        assert_eq!(rows[0].attempts, 0);
+        mark_sent(&db, rows[0].id).unwrap();
+
+        let counts = counts(&db).unwrap();
+        assert_eq!((counts.pending, counts.sent, counts.failing, counts.parked), (1, 1, 1, 0));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn closed_channel_isolates_unacked_rows() {
+        let closed = PublishOutcome::Failed("channel closed".to_string());
+        let not_found = PublishOutcome::Failed("NOT_FOUND - no exchange 'missing_exchange'".to_string());
+        // Row hợp lệ còn in-flight, rồi row hỏng đóng channel → không row nào bị tính lỗi
+        assert_eq!(
+            settle_batch(&[&PublishOutcome::Acked, &closed, &not_found], true),
+            [Settle::Sent, Settle::Isolate, Settle::Isolate]
+        );
+        // Publish lại riêng từng row: chỉ row hỏng bị tính lỗi
+        assert_eq!(settle_batch(&[&PublishOutcome::Acked], false), [Settle::Sent]);
+        assert_eq!(settle_batch(&[&not_found], true), [Settle::Failed]);
+    }
+
+    #[test]
+    fn open_channel_charges_every_unacked_row() {
+        assert_eq!(
+            settle_batch(&[&PublishOutcome::Nacked, &PublishOutcome::Acked, &PublishOutcome::Nacked], false),
+            [Settle::Failed, Settle::Sent, Settle::Failed]
+        );
+        assert_eq!(
+            settle_batch(&[&PublishOutcome::Acked, &PublishOutcome::Failed("closed".to_string())], true),
+            [Settle::Sent, Settle::Failed]
+        );
+    }
+
+    #[test]
+    fn row_is_parked_after_max_attempts() {
+        let (mut db, path) = test_db("park");
+        let mut config = RelayConfig::new(&path, "relay-1");
+        config.max_attempts = 2;
+        config.retry_backoff = Duration::ZERO;
+        enqueue_event(&db, "poison", "missing_exchange");
+
+        let rows = claim_batch(&mut db, &config).unwrap();
+        assert!(!mark_failed(&db, &config, &rows[0], "NOT_FOUND").unwrap());
+        std::thread::sleep(Duration::from_millis(2));
+        let rows = claim_batch(&mut db, &config).unwrap();
+        assert_eq!(rows[0].attempts, 1);
+        assert!(mark_failed(&db, &config, &rows[0], "NOT_FOUND").unwrap());
+
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(claim_batch(&mut db, &config).unwrap().is_empty());
+        let parked = counts(&db).unwrap();
+        assert_eq!((parked.pending, parked.parked), (0, 1));
+
+        assert_eq!(unpark(&db).unwrap(), 1);
+        let rows = claim_batch(&mut db, &config).unwrap();
+        assert_eq!((rows.len(), rows[0].attempts), (1, 0));
+        let _ = std::fs::remove_file(&path);
+    }
+}
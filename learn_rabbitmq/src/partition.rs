@@ -0,0 +1,341 @@
+// Partitioned ordering: message cùng key (vd order id) luôn vào cùng 1 partition queue → xử lý đúng thứ tự
+// theo key, nhưng các key khác nhau chạy song song trên nhiều worker.
+//
+//   exchange "orders.partitioned" ──▶ orders.partitioned.0 ──▶ worker A (active)
+//                                 ──▶ orders.partitioned.1 ──▶ worker B (active)
+//                                 ──▶ ...
+//
+// 2 cách chọn partition:
+//   - ClientHash: publisher tự hash key → routing key = số partition, exchange direct.
+//     Không cần plugin, đổi số partition → key chuyển partition (chỉ đổi khi không còn message cũ).
+//   - ConsistentHash: plugin rabbitmq_consistent_hash_exchange (exchange type x-consistent-hash),
+//     routing key = key, broker hash; binding key của mỗi queue là weight.
+//
+// Mỗi partition queue là single active consumer (src/queue_args.rs): ConsumerGroup cho MỌI worker subscribe
+// mọi partition, partition được giao có x-priority cao hơn → broker chọn đúng worker làm active
+// (RabbitMQ 3.12+), worker chết → worker khác tự nhận partition. 1 partition không bao giờ có 2 consumer song song.
+//
+// ⚠️  Thứ tự chỉ giữ được nếu handler không requeue: nack+requeue / retry qua DLX có thể đảo thứ tự trong key.
+
+use crate::batch::OutgoingMessage;
+use crate::middleware::Pipeline;
+use crate::queue_args::{self, QueueArguments};
+use lapin::{
+    options::*,
+    types::{AMQPValue, FieldTable, LongString},
+    BasicProperties, Channel, Connection, ExchangeKind, Result as LapinResult,
+};
+use std::sync::Arc;
+
+pub const CONSISTENT_HASH_EXCHANGE_TYPE: &str = "x-consistent-hash";
+pub const PARTITION_KEY_HEADER: &str = "x-partition-key";
+
+// x-priority của consumer trên partition được giao / partition dự phòng
+const OWNER_PRIORITY: i32 = 10;
+const STANDBY_PRIORITY: i32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionStrategy {
+    ClientHash,
+    ConsistentHash,
+}
+
+// FNV-1a 64-bit + finalizer của murmur3: ổn định giữa các process / version Rust (DefaultHasher thì
+// không đảm bảo). Bit thấp của FNV-1a trộn kém → `% partitions` bị lệch nếu thiếu finalizer.
+pub fn hash_key(key: &str) -> u64 {
+    let mut hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+pub fn partition_for(key: &str, partitions: u32) -> u32 {
+    (hash_key(key) % u64::from(partitions.max(1))) as u32
+}
+
+// Thử declare 1 exchange x-consistent-hash. Giống delay::delayed_plugin_available: không có plugin →
+// 503 đóng cả connection → `probe` là connection riêng chỉ dùng để probe
+pub async fn consistent_hash_available(probe: Connection) -> LapinResult<bool> {
+    let channel = probe.create_channel().await?;
+    let exchange = "learn_rabbitmq.consistent_hash_probe";
+    let declared = channel
+        .exchange_declare(
+            exchange,
+            ExchangeKind::Custom(CONSISTENT_HASH_EXCHANGE_TYPE.to_string()),
+            ExchangeDeclareOptions {
+                auto_delete: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await;
+    match declared {
+        Ok(()) => {
+            channel
+                .exchange_delete(exchange, ExchangeDeleteOptions::default())
+                .await?;
+            probe.close(200, "OK").await?;
+            Ok(true)
+        }
+        Err(lapin::Error::ProtocolError(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Partitioner {
+    exchange: String,
+    partitions: u32,
+    strategy: PartitionStrategy,
+}
+
+impl Partitioner {
+    pub fn new(exchange: &str, partitions: u32, strategy: PartitionStrategy) -> Self {
+        Partitioner {
+            exchange: exchange.to_string(),
+            partitions: partitions.max(1),
+            strategy,
+        }
+    }
+
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    pub fn partitions(&self) -> u32 {
+        self.partitions
+    }
+
+    pub fn strategy(&self) -> PartitionStrategy {
+        self.strategy
+    }
+
+    pub fn queue_name(&self, partition: u32) -> String {
+        format!("{}.{}", self.exchange, partition)
+    }
+
+    // ClientHash: partition chắc chắn; ConsistentHash: broker quyết định → None
+    pub fn partition_of(&self, key: &str) -> Option<u32> {
+        match self.strategy {
+            PartitionStrategy::ClientHash => Some(partition_for(key, self.partitions)),
+            PartitionStrategy::ConsistentHash => None,
+        }
+    }
+
+    pub fn routing_key(&self, key: &str) -> String {
+        match self.strategy {
+            PartitionStrategy::ClientHash => partition_for(key, self.partitions).to_string(),
+            PartitionStrategy::ConsistentHash => key.to_string(),
+        }
+    }
+
+    // Exchange + N queue durable, single active consumer
+    pub async fn declare(&self, channel: &Channel) -> LapinResult<()> {
+        let kind = match self.strategy {
+            PartitionStrategy::ClientHash => ExchangeKind::Direct,
+            PartitionStrategy::ConsistentHash => ExchangeKind::Custom(CONSISTENT_HASH_EXCHANGE_TYPE.to_string()),
+        };
+        channel
+            .exchange_declare(
+                &self.exchange,
+                kind,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        for partition in 0..self.partitions {
+            let queue = self.queue_name(partition);
+            channel
+                .queue_declare(
+                    &queue,
+                    QueueDeclareOptions {
+                        durable: true,
+                        ..Default::default()
+                    },
+                    QueueArguments::new().with_single_active_consumer().build(),
+                )
+                .await?;
+            // ConsistentHash: binding key = weight (mọi partition bằng nhau)
+            let binding_key = match self.strategy {
+                PartitionStrategy::ClientHash => partition.to_string(),
+                PartitionStrategy::ConsistentHash => "1".to_string(),
+            };
+            channel
+                .queue_bind(
+                    &queue,
+                    &self.exchange,
+                    &binding_key,
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Key giữ lại trong header để consumer / log biết message thuộc key nào
+    pub fn outgoing(&self, key: &str, payload: Vec<u8>, properties: BasicProperties) -> OutgoingMessage {
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        headers.insert(
+            PARTITION_KEY_HEADER.into(),
+            AMQPValue::LongString(LongString::from(key.to_string())),
+        );
+        OutgoingMessage::new(&self.exchange, &self.routing_key(key), payload)
+            .with_properties(properties.with_headers(headers))
+    }
+}
+
+// Partition được giao cho worker `member` trong nhóm `members` worker (chia vòng tròn)
+pub fn assign_partitions(partitions: u32, member: u32, members: u32) -> Vec<u32> {
+    let members = members.max(1);
+    (0..partitions).filter(|p| p % members == member % members).collect()
+}
+
+pub struct ConsumerGroup {
+    partitioner: Partitioner,
+    member: u32,
+    members: u32,
+    prefetch: u16,
+}
+
+impl ConsumerGroup {
+    // `member` = số thứ tự worker này (0..members)
+    pub fn new(partitioner: Partitioner, member: u32, members: u32) -> Self {
+        ConsumerGroup {
+            partitioner,
+            member,
+            members: members.max(1),
+            prefetch: 10,
+        }
+    }
+
+    pub fn with_prefetch(mut self, prefetch: u16) -> Self {
+        self.prefetch = prefetch.max(1);
+        self
+    }
+
+    pub fn assigned(&self) -> Vec<u32> {
+        assign_partitions(self.partitioner.partitions(), self.member, self.members)
+    }
+
+    pub fn consumer_tag(&self, partition: u32) -> String {
+        format!("{}.member{}", self.partitioner.queue_name(partition), self.member)
+    }
+
+    // Subscribe mọi partition (priority cao cho partition được giao), mỗi partition 1 task chạy pipeline
+    // tuần tự. Trả về khi 1 partition consumer lỗi / kết thúc.
+    pub async fn run(&self, connection: &Connection, pipeline: Pipeline) -> LapinResult<()> {
+        let assigned = self.assigned();
+        let mut tasks = tokio::task::JoinSet::new();
+
+        for partition in 0..self.partitioner.partitions() {
+            // Channel riêng mỗi partition: prefetch tính theo channel, lỗi 1 partition không kéo theo partition khác
+            let channel = connection.create_channel().await?;
+            channel.basic_qos(self.prefetch, BasicQosOptions::default()).await?;
+            let priority = if assigned.contains(&partition) {
+                OWNER_PRIORITY
+            } else {
+                STANDBY_PRIORITY
+            };
+            let consumer = channel
+                .basic_consume(
+                    &self.partitioner.queue_name(partition),
+                    &self.consumer_tag(partition),
+                    BasicConsumeOptions::default(),
+                    queue_args::consumer_priority(priority),
+                )
+                .await?;
+
+            let pipeline = pipeline.clone();
+            tasks.spawn(async move {
+                let _channel = channel;
+                pipeline.run(consumer).await
+            });
+        }
+
+        let result = match tasks.join_next().await {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => Err(lapin::Error::IOError(Arc::new(std::io::Error::other(e)))),
+            None => Ok(()),
+        };
+        tasks.abort_all();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    // Giá trị cố định: đổi hàm hash = key chuyển partition → phải là thay đổi có chủ đích
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(hash_key(""), 0xefd01f60ba992926);
+        assert_eq!(hash_key("order-1"), 0x491b422e3af0d9a5);
+        assert_eq!(hash_key("order-2"), 0xaaca0c2aeae39bab);
+        assert_eq!(partition_for("order-1", 8), 5);
+        assert_eq!(partition_for("order-2", 8), 3);
+        assert_eq!(partition_for("order-1", 0), 0);
+    }
+
+    #[test]
+    fn keys_spread_across_partitions() {
+        for partitions in [2, 3, 8, 16] {
+            let mut counts = vec![0u32; partitions as usize];
+            for i in 0..10_000 {
+                counts[partition_for(&format!("order-{}", i), partitions) as usize] += 1;
+            }
+            let expected = 10_000 / partitions;
+            for count in counts {
+                assert!(count.abs_diff(expected) < expected / 5, "{} partitions: {}", partitions, count);
+            }
+        }
+    }
+
+    #[test]
+    fn every_partition_owned_exactly_once() {
+        for partitions in 0..12 {
+            for members in 1..6 {
+                let mut owners = BTreeMap::new();
+                for member in 0..members {
+                    for partition in assign_partitions(partitions, member, members) {
+                        assert!(owners.insert(partition, member).is_none(), "partition {} owned twice", partition);
+                    }
+                }
+                assert_eq!(owners.keys().copied().collect::<Vec<_>>(), (0..partitions).collect::<Vec<_>>());
+                // Không worker nào nhận nhiều hơn worker khác quá 1 partition
+                let sizes: BTreeSet<usize> = (0..members)
+                    .map(|member| assign_partitions(partitions, member, members).len())
+                    .collect();
+                assert!(sizes.last().unwrap() - sizes.first().unwrap() <= 1);
+            }
+        }
+        assert_eq!(assign_partitions(4, 0, 0), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn outgoing_routes_by_strategy_and_keeps_key() {
+        let client = Partitioner::new("orders.partitioned", 8, PartitionStrategy::ClientHash);
+        let message = client.outgoing("order-1", b"{}".to_vec(), BasicProperties::default());
+        assert_eq!((message.exchange.as_str(), message.routing_key.as_str()), ("orders.partitioned", "5"));
+        let headers = message.properties.headers().clone().unwrap();
+        assert_eq!(
+            headers.inner().get(PARTITION_KEY_HEADER),
+            Some(&AMQPValue::LongString("order-1".into()))
+        );
+
+        let broker = Partitioner::new("orders.partitioned", 8, PartitionStrategy::ConsistentHash);
+        assert_eq!(broker.outgoing("order-1", vec![], BasicProperties::default()).routing_key, "order-1");
+        assert_eq!(broker.partition_of("order-1"), None);
+        assert_eq!(client.queue_name(5), "orders.partitioned.5");
+    }
+}
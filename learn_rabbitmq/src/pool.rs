@@ -0,0 +1,223 @@
+// Connection & Channel pooling
+// Một Connection (TCP) dùng chung, nhiều Channel được mượn/trả đồng thời.
+// Channel bị đóng (vd: do lỗi protocol) sẽ tự động được thay thế.
+
+use lapin::{Channel, Connection, ConnectionProperties, Result as LapinResult};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    // Số channel tối đa được mở cùng lúc trên connection
+    pub size: usize,
+    // Chu kỳ health check: loại bỏ channel đã đóng và mở lại cho đủ `size`
+    pub health_check_interval: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            size: 8,
+            health_check_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+struct PoolInner {
+    conn: Connection,
+    idle: Mutex<Vec<Channel>>,
+    permits: Arc<Semaphore>,
+    config: PoolConfig,
+}
+
+#[derive(Clone)]
+pub struct ChannelPool {
+    inner: Arc<PoolInner>,
+}
+
+impl ChannelPool {
+    pub async fn connect(url: &str, config: PoolConfig) -> LapinResult<Self> {
+        let conn = Connection::connect(url, ConnectionProperties::default()).await?;
+        Self::new(conn, config).await
+    }
+
+    // Tạo pool trên một connection có sẵn và mở trước `size` channels
+    pub async fn new(conn: Connection, config: PoolConfig) -> LapinResult<Self> {
+        let size = config.size.max(1);
+        let mut idle = Vec::with_capacity(size);
+        for _ in 0..size {
+            idle.push(conn.create_channel().await?);
+        }
+
+        Ok(ChannelPool {
+            inner: Arc::new(PoolInner {
+                conn,
+                idle: Mutex::new(idle),
+                permits: Arc::new(Semaphore::new(size)),
+                config: PoolConfig { size, ..config },
+            }),
+        })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.inner.conn
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.config.size
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+
+    // Mượn 1 channel. Chờ nếu tất cả channels đang được dùng.
+    // Channel đã đóng trong pool sẽ bị bỏ qua và thay bằng channel mới.
+    pub async fn get(&self) -> LapinResult<PooledChannel> {
+        let permit = self
+            .inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("pool semaphore is never closed");
+
+        let reused = {
+            let mut idle = self.inner.idle.lock().unwrap();
+            let mut found = None;
+            while let Some(channel) = idle.pop() {
+                if channel.status().connected() {
+                    found = Some(channel);
+                    break;
+                }
+            }
+            found
+        };
+
+        let channel = match reused {
+            Some(channel) => channel,
+            None => self.inner.conn.create_channel().await?,
+        };
+
+        Ok(PooledChannel {
+            channel: Some(channel),
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    // Loại bỏ channels đã đóng khỏi pool và mở channel mới thay thế.
+    // Trả về số channel đã được thay.
+    pub async fn health_check(&self) -> LapinResult<usize> {
+        let removed = {
+            let mut idle = self.inner.idle.lock().unwrap();
+            let before = idle.len();
+            idle.retain(|channel| channel.status().connected());
+            before - idle.len()
+        };
+
+        while self.missing() > 0 {
+            let channel = self.inner.conn.create_channel().await?;
+            // get() chạy song song trong lúc chờ create_channel có thể đã tự mở channel
+            // → kiểm tra lại dưới lock, thừa thì đóng channel vừa mở
+            let surplus = {
+                let mut idle = self.inner.idle.lock().unwrap();
+                if missing_channels(self.size(), idle.len(), self.in_use()) > 0 {
+                    idle.push(channel);
+                    None
+                } else {
+                    Some(channel)
+                }
+            };
+            if let Some(channel) = surplus {
+                let _ = channel.close(200, "pool is full").await;
+                break;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn in_use(&self) -> usize {
+        self.size() - self.inner.permits.available_permits()
+    }
+
+    fn missing(&self) -> usize {
+        missing_channels(self.size(), self.idle_count(), self.in_use())
+    }
+
+    // Chạy health check định kỳ ở background cho tới khi connection đóng
+    pub fn spawn_health_check(&self) -> JoinHandle<()> {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(pool.inner.config.health_check_interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if !pool.connection().status().connected() {
+                    println!("✗ Pool: connection closed, stopping health check");
+                    break;
+                }
+                match pool.health_check().await {
+                    Ok(0) => {}
+                    Ok(replaced) => println!("ℹ️  Pool: replaced {} closed channel(s)", replaced),
+                    Err(e) => println!("✗ Pool: health check failed: {}", e),
+                }
+            }
+        })
+    }
+}
+
+// Số channel cần mở thêm để idle + đang mượn đủ `size`
+fn missing_channels(size: usize, idle: usize, in_use: usize) -> usize {
+    size.saturating_sub(idle + in_use)
+}
+
+// Channel được mượn từ pool - tự trả lại khi drop
+pub struct PooledChannel {
+    channel: Option<Channel>,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledChannel {
+    type Target = Channel;
+
+    fn deref(&self) -> &Channel {
+        self.channel.as_ref().expect("channel is present until drop")
+    }
+}
+
+impl Drop for PooledChannel {
+    fn drop(&mut self) {
+        if let Some(channel) = self.channel.take() {
+            // Channel đã đóng thì bỏ đi, lần `get()` sau sẽ mở channel mới
+            if channel.status().connected() {
+                self.pool.idle.lock().unwrap().push(channel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_counts_idle_and_borrowed_channels() {
+        assert_eq!(missing_channels(8, 8, 0), 0);
+        assert_eq!(missing_channels(8, 5, 0), 3);
+        assert_eq!(missing_channels(8, 3, 4), 1);
+        assert_eq!(missing_channels(8, 0, 8), 0);
+    }
+
+    #[test]
+    fn missing_never_underflows() {
+        // get() mở channel mới trong lúc health check đang chạy → tạm thời nhiều hơn `size`
+        assert_eq!(missing_channels(4, 3, 2), 0);
+        assert_eq!(missing_channels(0, 1, 0), 0);
+    }
+}
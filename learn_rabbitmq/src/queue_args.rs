@@ -0,0 +1,112 @@
+// Arguments cho queue_declare / basic_consume
+//
+// Single active consumer (x-single-active-consumer, queue argument):
+//   nhiều consumer cùng subscribe nhưng broker chỉ giao message cho 1 consumer (active) → thứ tự
+//   được giữ nguyên. Consumer active ngắt kết nối → message chưa ack được requeue, consumer kế tiếp
+//   thành active (failover). Khác round-robin của work queue (Example 3) và khác `exclusive` consumer
+//   (consumer thứ 2 bị từ chối thay vì đứng chờ).
+//
+// Consumer priority (x-priority, argument của basic_consume, mặc định 0):
+//   - queue thường: consumer priority cao nhận message trước, chỉ khi nó hết prefetch mới tới consumer thấp hơn
+//   - single active consumer (RabbitMQ 3.12+): consumer priority cao nhất được chọn làm active;
+//     consumer priority cao hơn join sau → broker chuyển active sang nó khi active cũ ack hết
+//
+// ⚠️  Queue argument cố định lúc tạo: declare lại queue đã có với argument khác → PRECONDITION_FAILED
+//     (channel bị đóng). Xóa queue hoặc dùng tên mới.
+
+use lapin::{
+    options::QueueDeclareOptions,
+    types::{AMQPValue, FieldTable},
+    Channel, Queue, Result as LapinResult,
+};
+
+pub const SINGLE_ACTIVE_CONSUMER: &str = "x-single-active-consumer";
+pub const CONSUMER_PRIORITY: &str = "x-priority";
+
+#[derive(Debug, Clone, Default)]
+pub struct QueueArguments {
+    arguments: FieldTable,
+}
+
+impl QueueArguments {
+    pub fn new() -> Self {
+        QueueArguments::default()
+    }
+
+    pub fn with_single_active_consumer(self) -> Self {
+        self.with(SINGLE_ACTIVE_CONSUMER, AMQPValue::Boolean(true))
+    }
+
+    // Argument bất kỳ (x-message-ttl, x-dead-letter-exchange, x-queue-type...)
+    pub fn with(mut self, key: &str, value: AMQPValue) -> Self {
+        self.arguments.insert(key.into(), value);
+        self
+    }
+
+    pub fn build(self) -> FieldTable {
+        self.arguments
+    }
+}
+
+impl From<QueueArguments> for FieldTable {
+    fn from(arguments: QueueArguments) -> Self {
+        arguments.build()
+    }
+}
+
+pub fn is_single_active_consumer(arguments: &FieldTable) -> bool {
+    matches!(
+        arguments.inner().get(SINGLE_ACTIVE_CONSUMER),
+        Some(AMQPValue::Boolean(true))
+    )
+}
+
+// Arguments cho basic_consume
+pub fn consumer_priority(priority: i32) -> FieldTable {
+    let mut arguments = FieldTable::default();
+    arguments.insert(CONSUMER_PRIORITY.into(), AMQPValue::LongInt(priority));
+    arguments
+}
+
+pub async fn declare_single_active_queue(
+    channel: &Channel,
+    name: &str,
+    options: QueueDeclareOptions,
+) -> LapinResult<Queue> {
+    channel
+        .queue_declare(
+            name,
+            options,
+            QueueArguments::new().with_single_active_consumer().build(),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_active_consumer_argument() {
+        let arguments = QueueArguments::new().with_single_active_consumer().build();
+        assert_eq!(arguments.inner().get(SINGLE_ACTIVE_CONSUMER), Some(&AMQPValue::Boolean(true)));
+        assert!(is_single_active_consumer(&arguments));
+    }
+
+    #[test]
+    fn is_single_active_consumer_needs_true_flag() {
+        assert!(!is_single_active_consumer(&FieldTable::default()));
+        assert!(!is_single_active_consumer(
+            &QueueArguments::new().with(SINGLE_ACTIVE_CONSUMER, AMQPValue::Boolean(false)).build()
+        ));
+        let other: FieldTable = QueueArguments::new().with("x-message-ttl", AMQPValue::LongLongInt(1000)).into();
+        assert!(!is_single_active_consumer(&other));
+    }
+
+    #[test]
+    fn consumer_priority_is_long_int() {
+        let arguments = consumer_priority(10);
+        assert_eq!(arguments.inner().get(CONSUMER_PRIORITY), Some(&AMQPValue::LongInt(10)));
+        assert_eq!(arguments.inner().len(), 1);
+    }
+}
@@ -0,0 +1,179 @@
+// Xem / di chuyển / xóa messages trong queue có sẵn (hello_queue, task_queue...)
+// peek:  basic_get KHÔNG ack, rồi nack(requeue) tất cả → messages quay lại vị trí cũ
+//        (RabbitMQ giữ vị trí ban đầu khi requeue nếu có thể; consumer khác đang chạy có thể làm lệch)
+// move:  lấy từng message, khớp filter → publish sang queue đích (có confirm) rồi mới ack bản gốc
+// purge: đếm (dry-run) hoặc xóa toàn bộ messages
+
+use crate::batch::{BatchPublisher, OutgoingMessage, PublishOutcome};
+use crate::record::RecordedMessage;
+use crate::tail::FieldFilter;
+use lapin::{
+    message::Delivery,
+    options::*,
+    types::FieldTable,
+    Channel, Result as LapinResult,
+};
+use regex::Regex;
+
+// Số messages đang chờ (ready) trong queue - passive declare, không tạo queue mới
+pub async fn message_count(channel: &Channel, queue: &str) -> LapinResult<u32> {
+    let queue = channel
+        .queue_declare(
+            queue,
+            QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    Ok(queue.message_count())
+}
+
+// Lấy tối đa `count` messages mà KHÔNG xóa khỏi queue
+pub async fn peek(channel: &Channel, queue: &str, count: usize) -> LapinResult<Vec<RecordedMessage>> {
+    let mut held: Vec<Delivery> = Vec::new();
+    while held.len() < count {
+        match channel.basic_get(queue, BasicGetOptions { no_ack: false }).await? {
+            Some(message) => held.push(message.delivery),
+            None => break,
+        }
+    }
+
+    let messages = held.iter().map(RecordedMessage::from_delivery).collect();
+    requeue_all(channel, &held).await?;
+    Ok(messages)
+}
+
+// Trả tất cả messages đang giữ về queue (1 lệnh nack multiple)
+async fn requeue_all(channel: &Channel, held: &[Delivery]) -> LapinResult<()> {
+    if let Some(last) = held.iter().map(|d| d.delivery_tag).max() {
+        channel
+            .basic_nack(
+                last,
+                BasicNackOptions {
+                    multiple: true,
+                    requeue: true,
+                },
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MoveFilter {
+    pub routing_key: Option<Regex>,
+    pub fields: Vec<FieldFilter>,
+}
+
+impl MoveFilter {
+    pub fn matches(&self, message: &RecordedMessage) -> bool {
+        if let Some(regex) = &self.routing_key
+            && !regex.is_match(&message.routing_key)
+        {
+            return false;
+        }
+        if self.fields.is_empty() {
+            return true;
+        }
+        message
+            .body_json()
+            .is_some_and(|body| self.fields.iter().all(|f| f.matches(&body)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MoveReport {
+    // Số messages đã xem
+    pub scanned: usize,
+    pub moved: usize,
+    // Khớp filter nhưng publish sang đích thất bại → để lại queue nguồn
+    pub failed: usize,
+}
+
+// source == destination: message vừa publish lại nằm cuối chính queue đó → basic_get lấy lại
+// mãi, vòng lặp không bao giờ hết message (khi không có limit)
+pub fn check_move_target(source: &str, destination: &str) -> LapinResult<()> {
+    if source == destination {
+        return Err(crate::tls::invalid_config(format!(
+            "cannot move messages from '{}' to itself",
+            source
+        )));
+    }
+    Ok(())
+}
+
+// Di chuyển messages khớp `filter` từ `source` sang queue `destination`.
+// `get_channel` lấy/ack messages, `publisher` publish có confirm (2 channel riêng).
+pub async fn move_messages(
+    get_channel: &Channel,
+    publisher: &mut BatchPublisher,
+    source: &str,
+    destination: &str,
+    filter: &MoveFilter,
+    limit: Option<usize>,
+    dry_run: bool,
+) -> LapinResult<MoveReport> {
+    check_move_target(source, destination)?;
+    let mut report = MoveReport::default();
+    let mut kept: Vec<Delivery> = Vec::new();
+
+    while limit.is_none_or(|limit| report.moved < limit) {
+        let Some(message) = get_channel
+            .basic_get(source, BasicGetOptions { no_ack: false })
+            .await?
+        else {
+            break;
+        };
+        let delivery = message.delivery;
+        report.scanned += 1;
+
+        let recorded = RecordedMessage::from_delivery(&delivery);
+        if !filter.matches(&recorded) || dry_run {
+            if dry_run && filter.matches(&recorded) {
+                report.moved += 1;
+            }
+            kept.push(delivery);
+            continue;
+        }
+
+        // Giữ nguyên properties/headers, gửi qua default exchange → routing key = tên queue đích
+        let outgoing = OutgoingMessage::new("", destination, delivery.data.clone())
+            .with_properties(delivery.properties.clone());
+        let published = publisher.publish_batch(std::iter::once(outgoing)).await;
+
+        match published.results.first().map(|r| &r.outcome) {
+            Some(PublishOutcome::Acked) => {
+                // Chỉ ack bản gốc SAU khi đích đã confirm → không mất message
+                delivery.ack(BasicAckOptions::default()).await?;
+                report.moved += 1;
+            }
+            _ => {
+                report.failed += 1;
+                kept.push(delivery);
+            }
+        }
+    }
+
+    // Messages không khớp (hoặc dry-run) quay lại queue nguồn
+    requeue_all(get_channel, &kept).await?;
+    Ok(report)
+}
+
+pub async fn purge(channel: &Channel, queue: &str) -> LapinResult<u32> {
+    channel
+        .queue_purge(queue, QueuePurgeOptions::default())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_a_queue_onto_itself_is_rejected() {
+        assert!(check_move_target("task_queue", "task_queue").is_err());
+        assert!(check_move_target("task_queue", "task_queue.retry").is_ok());
+    }
+}
@@ -0,0 +1,708 @@
+// Message recorder & replayer
+// record: bind 1 queue tạm (exclusive, auto-delete - giống các subscriber) vào exchange,
+//         hoặc đọc 1 queue có sẵn, ghi MỖI delivery thành 1 dòng JSON (NDJSON)
+// replay: đọc file NDJSON và publish lại, có rate limit, rewrite routing key, filter
+
+use crate::batch::{BatchPublisher, BatchReport, OutgoingMessage};
+use crate::util::now_millis;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::StreamExt;
+use lapin::{
+    message::Delivery,
+    options::*,
+    types::{AMQPType, AMQPValue, FieldArray, FieldTable},
+    BasicProperties, Channel, Result as LapinResult,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RecordedProperties {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery_mode: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+// 1 dòng trong file NDJSON
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RecordedMessage {
+    // Thời điểm nhận (ms since epoch)
+    pub recorded_at_ms: i64,
+    pub exchange: String,
+    pub routing_key: String,
+    #[serde(default)]
+    pub redelivered: bool,
+    #[serde(default)]
+    pub properties: RecordedProperties,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub headers: Map<String, Value>,
+    // Kiểu AMQP của các header số không phải mặc định (xem amqp_type_hint), để replay đúng kiểu
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub header_types: Map<String, Value>,
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
+    // UTF-8 giữ nguyên để dễ đọc/grep, binary thì base64
+    pub body: String,
+}
+
+impl RecordedMessage {
+    pub fn from_delivery(delivery: &Delivery) -> Self {
+        let props = &delivery.properties;
+        let text = |v: &Option<lapin::types::ShortString>| v.as_ref().map(|s| s.to_string());
+
+        let (body_encoding, body) = match std::str::from_utf8(&delivery.data) {
+            Ok(text) => (BodyEncoding::Utf8, text.to_string()),
+            Err(_) => (BodyEncoding::Base64, BASE64.encode(&delivery.data)),
+        };
+
+        RecordedMessage {
+            recorded_at_ms: now_millis(),
+            exchange: delivery.exchange.to_string(),
+            routing_key: delivery.routing_key.to_string(),
+            redelivered: delivery.redelivered,
+            properties: RecordedProperties {
+                content_type: text(props.content_type()),
+                content_encoding: text(props.content_encoding()),
+                delivery_mode: *props.delivery_mode(),
+                priority: *props.priority(),
+                correlation_id: text(props.correlation_id()),
+                reply_to: text(props.reply_to()),
+                expiration: text(props.expiration()),
+                message_id: text(props.message_id()),
+                timestamp: *props.timestamp(),
+                kind: text(props.kind()),
+                user_id: text(props.user_id()),
+                app_id: text(props.app_id()),
+            },
+            headers: props
+                .headers()
+                .as_ref()
+                .map(field_table_to_json)
+                .unwrap_or_default(),
+            header_types: props
+                .headers()
+                .as_ref()
+                .map(field_table_type_hints)
+                .unwrap_or_default(),
+            body_encoding,
+            body,
+        }
+    }
+
+    pub fn body_bytes(&self) -> io::Result<Vec<u8>> {
+        match self.body_encoding {
+            BodyEncoding::Utf8 => Ok(self.body.clone().into_bytes()),
+            BodyEncoding::Base64 => BASE64
+                .decode(&self.body)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    // Body dạng JSON (nếu parse được) - dùng cho hiển thị & filter
+    pub fn body_json(&self) -> Option<Value> {
+        match self.body_encoding {
+            BodyEncoding::Utf8 => serde_json::from_str(&self.body).ok(),
+            BodyEncoding::Base64 => None,
+        }
+    }
+
+    pub fn basic_properties(&self) -> BasicProperties {
+        let p = &self.properties;
+        let mut props = BasicProperties::default();
+        if let Some(v) = &p.content_type {
+            props = props.with_content_type(v.clone().into());
+        }
+        if let Some(v) = &p.content_encoding {
+            props = props.with_content_encoding(v.clone().into());
+        }
+        if let Some(v) = p.delivery_mode {
+            props = props.with_delivery_mode(v);
+        }
+        if let Some(v) = p.priority {
+            props = props.with_priority(v);
+        }
+        if let Some(v) = &p.correlation_id {
+            props = props.with_correlation_id(v.clone().into());
+        }
+        if let Some(v) = &p.reply_to {
+            props = props.with_reply_to(v.clone().into());
+        }
+        if let Some(v) = &p.expiration {
+            props = props.with_expiration(v.clone().into());
+        }
+        if let Some(v) = &p.message_id {
+            props = props.with_message_id(v.clone().into());
+        }
+        if let Some(v) = p.timestamp {
+            props = props.with_timestamp(v);
+        }
+        if let Some(v) = &p.kind {
+            props = props.with_type(v.clone().into());
+        }
+        if let Some(v) = &p.user_id {
+            props = props.with_user_id(v.clone().into());
+        }
+        if let Some(v) = &p.app_id {
+            props = props.with_app_id(v.clone().into());
+        }
+        if !self.headers.is_empty() {
+            props = props.with_headers(json_to_typed_field_table(&self.headers, &self.header_types));
+        }
+        props
+    }
+}
+
+// AMQP header values → JSON. Kiểu không có trong JSON được bọc lại để replay đúng kiểu:
+// bytes → {"$bytes": base64}, timestamp → {"$timestamp": n}, decimal → {"$decimal": [scale, value]}
+pub fn amqp_to_json(value: &AMQPValue) -> Value {
+    match value {
+        AMQPValue::Boolean(v) => json!(v),
+        AMQPValue::ShortShortInt(v) => json!(v),
+        AMQPValue::ShortShortUInt(v) => json!(v),
+        AMQPValue::ShortInt(v) => json!(v),
+        AMQPValue::ShortUInt(v) => json!(v),
+        AMQPValue::LongInt(v) => json!(v),
+        AMQPValue::LongUInt(v) => json!(v),
+        AMQPValue::LongLongInt(v) => json!(v),
+        AMQPValue::Float(v) => json!(v),
+        AMQPValue::Double(v) => json!(v),
+        AMQPValue::DecimalValue(v) => json!({ "$decimal": [v.scale, v.value] }),
+        AMQPValue::ShortString(v) => json!(v.as_str()),
+        AMQPValue::LongString(v) => match std::str::from_utf8(v.as_bytes()) {
+            Ok(text) => json!(text),
+            Err(_) => json!({ "$bytes": BASE64.encode(v.as_bytes()) }),
+        },
+        AMQPValue::FieldArray(v) => Value::Array(v.as_slice().iter().map(amqp_to_json).collect()),
+        AMQPValue::Timestamp(v) => json!({ "$timestamp": v }),
+        AMQPValue::FieldTable(v) => Value::Object(field_table_to_json(v)),
+        AMQPValue::ByteArray(v) => json!({ "$bytes": BASE64.encode(v.as_slice()) }),
+        AMQPValue::Void => Value::Null,
+    }
+}
+
+pub fn field_table_to_json(table: &FieldTable) -> Map<String, Value> {
+    table
+        .inner()
+        .iter()
+        .map(|(key, value)| (key.to_string(), amqp_to_json(value)))
+        .collect()
+}
+
+// JSON chỉ có 1 kiểu số → khi replay, số nguyên thành LongLongInt, số thực thành Double.
+// Header kiểu khác (vd: x-retry-count là LongInt, x-priority là ShortShortUInt) được ghi thêm
+// type id của AMQP ("I", "B", "f"...). Table/array lồng nhau → object/array các hint tương ứng.
+pub fn amqp_type_hint(value: &AMQPValue) -> Option<Value> {
+    match value {
+        AMQPValue::ShortShortInt(_)
+        | AMQPValue::ShortShortUInt(_)
+        | AMQPValue::ShortInt(_)
+        | AMQPValue::ShortUInt(_)
+        | AMQPValue::LongInt(_)
+        | AMQPValue::LongUInt(_)
+        | AMQPValue::Float(_) => Some(json!(value.get_type().get_id().to_string())),
+        AMQPValue::FieldTable(table) => {
+            let hints = field_table_type_hints(table);
+            (!hints.is_empty()).then_some(Value::Object(hints))
+        }
+        AMQPValue::FieldArray(items) => {
+            let hints: Vec<Value> = items
+                .as_slice()
+                .iter()
+                .map(|item| amqp_type_hint(item).unwrap_or(Value::Null))
+                .collect();
+            hints.iter().any(|h| !h.is_null()).then_some(Value::Array(hints))
+        }
+        _ => None,
+    }
+}
+
+pub fn field_table_type_hints(table: &FieldTable) -> Map<String, Value> {
+    table
+        .inner()
+        .iter()
+        .filter_map(|(key, value)| amqp_type_hint(value).map(|hint| (key.to_string(), hint)))
+        .collect()
+}
+
+pub fn json_to_amqp(value: &Value) -> AMQPValue {
+    json_to_typed_amqp(value, None)
+}
+
+// Như json_to_amqp, nhưng dùng `hint` (từ amqp_type_hint) để khôi phục kiểu số gốc
+pub fn json_to_typed_amqp(value: &Value, hint: Option<&Value>) -> AMQPValue {
+    match value {
+        Value::Null => AMQPValue::Void,
+        Value::Bool(v) => AMQPValue::Boolean(*v),
+        Value::Number(n) => {
+            let kind = hint
+                .and_then(Value::as_str)
+                .and_then(|id| id.chars().next())
+                .and_then(AMQPType::from_id);
+            if let Some(kind) = kind
+                && let Some(typed) = typed_number(n, kind)
+            {
+                return typed;
+            }
+            match (n.as_i64(), n.as_f64()) {
+                (Some(i), _) => AMQPValue::LongLongInt(i),
+                (None, Some(f)) => AMQPValue::Double(f),
+                _ => AMQPValue::Void,
+            }
+        }
+        Value::String(s) => AMQPValue::LongString(s.clone().into()),
+        Value::Array(items) => {
+            let hints = hint.and_then(Value::as_array);
+            AMQPValue::FieldArray(FieldArray::from(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| json_to_typed_amqp(item, hints.and_then(|h| h.get(i))))
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        Value::Object(map) => {
+            if let Some(Value::String(bytes)) = map.get("$bytes")
+                && let Ok(bytes) = BASE64.decode(bytes)
+            {
+                return AMQPValue::ByteArray(bytes.into());
+            }
+            if let Some(ts) = map.get("$timestamp").and_then(Value::as_u64) {
+                return AMQPValue::Timestamp(ts);
+            }
+            if let Some([scale, value]) = map.get("$decimal").and_then(Value::as_array).map(Vec::as_slice)
+                && let (Some(scale), Some(value)) = (scale.as_u64(), value.as_u64())
+            {
+                return AMQPValue::DecimalValue(lapin::types::DecimalValue {
+                    scale: scale as u8,
+                    value: value as u32,
+                });
+            }
+            match hint.and_then(Value::as_object) {
+                Some(hints) => AMQPValue::FieldTable(json_to_typed_field_table(map, hints)),
+                None => AMQPValue::FieldTable(json_to_field_table(map)),
+            }
+        }
+    }
+}
+
+// Số không vừa kiểu ghi trong hint (file bị sửa tay) → None, dùng kiểu mặc định
+fn typed_number(n: &serde_json::Number, kind: AMQPType) -> Option<AMQPValue> {
+    let int = n.as_i64();
+    Some(match kind {
+        AMQPType::ShortShortInt => AMQPValue::ShortShortInt(int?.try_into().ok()?),
+        AMQPType::ShortShortUInt => AMQPValue::ShortShortUInt(int?.try_into().ok()?),
+        AMQPType::ShortInt => AMQPValue::ShortInt(int?.try_into().ok()?),
+        AMQPType::ShortUInt => AMQPValue::ShortUInt(int?.try_into().ok()?),
+        AMQPType::LongInt => AMQPValue::LongInt(int?.try_into().ok()?),
+        AMQPType::LongUInt => AMQPValue::LongUInt(int?.try_into().ok()?),
+        AMQPType::Float => AMQPValue::Float(n.as_f64()? as f32),
+        _ => return None,
+    })
+}
+
+pub fn json_to_field_table(map: &Map<String, Value>) -> FieldTable {
+    json_to_typed_field_table(map, &Map::new())
+}
+
+pub fn json_to_typed_field_table(map: &Map<String, Value>, hints: &Map<String, Value>) -> FieldTable {
+    let mut table = FieldTable::default();
+    for (key, value) in map {
+        table.insert(key.clone().into(), json_to_typed_amqp(value, hints.get(key)));
+    }
+    table
+}
+
+// Binding keys khi người dùng không truyền -k. "#" chỉ khớp mọi routing key trên topic exchange;
+// direct exchange so khớp nguyên văn → queue tạm bind "#" không nhận được gì.
+// `kind` = type của exchange (vd: lấy từ management API), None = không xác định được.
+pub fn default_binding_keys(exchange: &str, kind: Option<&str>) -> Result<Vec<String>, String> {
+    match kind {
+        Some("topic") => Ok(vec!["#".to_string()]),
+        // fanout bỏ qua routing key; headers exchange: binding không có điều kiện khớp mọi message
+        Some("fanout") | Some("headers") => Ok(vec![String::new()]),
+        Some(kind) => Err(format!(
+            "exchange '{}' is a {} exchange: pass the routing keys to bind with -k",
+            exchange, kind
+        )),
+        None => Err(format!(
+            "could not determine the type of exchange '{}': pass the routing keys to bind with -k",
+            exchange
+        )),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RecordSource {
+    // Queue tạm bind vào exchange với các binding keys - không ảnh hưởng queue thật
+    Exchange {
+        exchange: String,
+        binding_keys: Vec<String>,
+    },
+    // Đọc queue có sẵn KHÔNG ack → khi dừng, messages tự quay lại queue (redelivered=true)
+    Queue { queue: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordOptions {
+    pub source: RecordSource,
+    // Dừng sau N messages (None = không giới hạn)
+    pub max_messages: Option<usize>,
+    // Dừng sau khoảng thời gian này
+    pub duration: Option<Duration>,
+    // Dừng khi không có message mới trong khoảng này (hữu ích khi dump queue)
+    pub idle_timeout: Option<Duration>,
+}
+
+// Tạo queue tạm (exclusive, auto-delete) bind vào `exchange`. Exchange phải tồn tại sẵn.
+pub async fn bind_temporary_queue(
+    channel: &Channel,
+    exchange: &str,
+    binding_keys: &[String],
+) -> LapinResult<String> {
+    channel
+        .exchange_declare(
+            exchange,
+            lapin::ExchangeKind::Direct,
+            ExchangeDeclareOptions {
+                passive: true,  // Chỉ kiểm tra tồn tại, không tạo/đổi type
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    let queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    let queue_name = queue.name().to_string();
+
+    for binding_key in binding_keys {
+        channel
+            .queue_bind(
+                &queue_name,
+                exchange,
+                binding_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+    }
+
+    Ok(queue_name)
+}
+
+// Ghi deliveries vào `out` cho tới khi đủ điều kiện dừng hoặc Ctrl+C. Trả về số messages đã ghi.
+pub async fn record<W: Write>(channel: &Channel, options: &RecordOptions, out: &mut W) -> LapinResult<usize> {
+    let (queue_name, no_ack) = match &options.source {
+        RecordSource::Exchange {
+            exchange,
+            binding_keys,
+        } => (bind_temporary_queue(channel, exchange, binding_keys).await?, true),
+        RecordSource::Queue { queue } => (queue.clone(), false),
+    };
+
+    let mut consumer = channel
+        .basic_consume(
+            &queue_name,
+            "recorder",
+            BasicConsumeOptions {
+                no_ack,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    let started = Instant::now();
+    let mut recorded = 0;
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        if options.max_messages.is_some_and(|max| recorded >= max) {
+            break;
+        }
+        let mut wait = options.idle_timeout.unwrap_or(Duration::from_secs(3600));
+        if let Some(duration) = options.duration {
+            match duration.checked_sub(started.elapsed()) {
+                Some(left) => wait = wait.min(left),
+                None => break,
+            }
+        }
+
+        let delivery = tokio::select! {
+            _ = &mut ctrl_c => break,
+            next = tokio::time::timeout(wait, consumer.next()) => next,
+        };
+        let delivery = match delivery {
+            Ok(Some(delivery)) => delivery?,
+            Ok(None) => break,
+            Err(_) if options.duration.is_some_and(|d| started.elapsed() >= d) => break,
+            Err(_) if options.idle_timeout.is_some() => break,
+            Err(_) => continue,
+        };
+
+        let line = serde_json::to_string(&RecordedMessage::from_delivery(&delivery)).unwrap();
+        writeln!(out, "{}", line).map_err(io_error)?;
+        recorded += 1;
+    }
+
+    out.flush().map_err(io_error)?;
+    Ok(recorded)
+}
+
+pub fn read_recording<R: BufRead>(reader: R) -> io::Result<Vec<RecordedMessage>> {
+    let mut messages = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, e))
+        })?;
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+    // Publish vào exchange này thay vì exchange gốc
+    pub exchange: Option<String>,
+    // Chỉ replay messages có routing key khớp regex
+    pub filter: Option<Regex>,
+    // Đổi routing key: regex → replacement (hỗ trợ $1, $2...)
+    pub rewrite: Option<(Regex, String)>,
+    // Messages/giây, 0 = nhanh nhất có thể
+    pub rate: u64,
+    // Giữ `user_id` gốc. Mặc định bỏ: broker từ chối (PRECONDITION_FAILED) nếu user_id
+    // khác user của connection đang replay
+    pub keep_user_id: bool,
+}
+
+impl ReplayOptions {
+    // Trả về (exchange, routing_key) đích, hoặc None nếu message bị lọc bỏ
+    pub fn target(&self, message: &RecordedMessage) -> Option<(String, String)> {
+        if let Some(filter) = &self.filter
+            && !filter.is_match(&message.routing_key)
+        {
+            return None;
+        }
+        let routing_key = match &self.rewrite {
+            Some((pattern, replacement)) => pattern
+                .replace(&message.routing_key, replacement.as_str())
+                .into_owned(),
+            None => message.routing_key.clone(),
+        };
+        let exchange = self.exchange.clone().unwrap_or_else(|| message.exchange.clone());
+        Some((exchange, routing_key))
+    }
+
+    pub fn properties(&self, message: &RecordedMessage) -> BasicProperties {
+        if self.keep_user_id || message.properties.user_id.is_none() {
+            return message.basic_properties();
+        }
+        let mut message = message.clone();
+        message.properties.user_id = None;
+        message.basic_properties()
+    }
+}
+
+pub async fn replay(
+    publisher: &mut BatchPublisher,
+    messages: &[RecordedMessage],
+    options: &ReplayOptions,
+) -> LapinResult<BatchReport> {
+    let mut outgoing = Vec::new();
+    for message in messages {
+        if let Some((exchange, routing_key)) = options.target(message) {
+            let body = message.body_bytes().map_err(io_error)?;
+            outgoing.push(
+                OutgoingMessage::new(&exchange, &routing_key, body)
+                    .with_properties(options.properties(message)),
+            );
+        }
+    }
+
+    if options.rate == 0 {
+        return Ok(publisher.publish_batch(outgoing).await);
+    }
+
+    // Rate limit: publish từng message theo nhịp
+    let started = Instant::now();
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / options.rate as f64));
+    let mut results = Vec::with_capacity(outgoing.len());
+    for (index, message) in outgoing.into_iter().enumerate() {
+        ticker.tick().await;
+        let mut report = publisher.publish_batch(std::iter::once(message)).await;
+        for mut result in report.results.drain(..) {
+            result.index = index;
+            results.push(result);
+        }
+    }
+
+    Ok(BatchReport {
+        results,
+        elapsed: started.elapsed(),
+    })
+}
+
+fn io_error(e: io::Error) -> lapin::Error {
+    lapin::Error::IOError(e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::types::ShortString;
+
+    fn recorded(properties: BasicProperties, data: &[u8]) -> RecordedMessage {
+        RecordedMessage::from_delivery(&Delivery {
+            delivery_tag: 1,
+            exchange: ShortString::from("logs_topic"),
+            routing_key: ShortString::from("order.created"),
+            redelivered: false,
+            properties,
+            data: data.to_vec(),
+            acker: Default::default(),
+        })
+    }
+
+    // Đi qua NDJSON như file thật
+    fn round_trip(message: &RecordedMessage) -> RecordedMessage {
+        let line = serde_json::to_string(message).unwrap();
+        read_recording(line.as_bytes()).unwrap().remove(0)
+    }
+
+    #[test]
+    fn header_integer_types_survive_replay() {
+        let mut nested = FieldTable::default();
+        nested.insert("attempt".into(), AMQPValue::ShortUInt(3));
+        let mut headers = FieldTable::default();
+        headers.insert("x-retry-count".into(), AMQPValue::LongInt(2));
+        headers.insert("x-priority".into(), AMQPValue::ShortShortUInt(9));
+        headers.insert("x-ratio".into(), AMQPValue::Float(0.5));
+        headers.insert("x-big".into(), AMQPValue::LongLongInt(1 << 40));
+        headers.insert("x-nested".into(), AMQPValue::FieldTable(nested));
+        headers.insert(
+            "x-list".into(),
+            AMQPValue::FieldArray(vec![AMQPValue::LongString("a".into()), AMQPValue::ShortInt(-1)].into()),
+        );
+
+        let message = round_trip(&recorded(BasicProperties::default().with_headers(headers.clone()), b"{}"));
+        assert_eq!(message.headers["x-retry-count"], json!(2));
+        let replayed = message.basic_properties();
+        assert_eq!(replayed.headers().as_ref(), Some(&headers));
+    }
+
+    #[test]
+    fn untyped_headers_default_to_long_long_int() {
+        let mut headers = Map::new();
+        headers.insert("count".to_string(), json!(5));
+        let table = json_to_field_table(&headers);
+        assert_eq!(table.inner().get("count"), Some(&AMQPValue::LongLongInt(5)));
+
+        // Hint sai kiểu (số không vừa u8) → quay về kiểu mặc định
+        let hints = Map::from_iter([("count".to_string(), json!("B"))]);
+        headers.insert("count".to_string(), json!(300));
+        let table = json_to_typed_field_table(&headers, &hints);
+        assert_eq!(table.inner().get("count"), Some(&AMQPValue::LongLongInt(300)));
+    }
+
+    #[test]
+    fn replay_strips_user_id_unless_kept() {
+        let message = recorded(
+            BasicProperties::default()
+                .with_user_id("recorder".into())
+                .with_message_id("m-1".into()),
+            b"{}",
+        );
+        let options = ReplayOptions::default();
+        let properties = options.properties(&message);
+        assert_eq!(properties.user_id(), &None);
+        assert_eq!(properties.message_id().as_ref().map(|id| id.as_str()), Some("m-1"));
+
+        let keep = ReplayOptions {
+            keep_user_id: true,
+            ..Default::default()
+        };
+        assert_eq!(keep.properties(&message).user_id().as_ref().map(|id| id.as_str()), Some("recorder"));
+    }
+
+    #[test]
+    fn binary_body_size_is_payload_size() {
+        let message = round_trip(&recorded(BasicProperties::default(), &[0xff; 10]));
+        assert_eq!(message.body_encoding, BodyEncoding::Base64);
+        assert_ne!(message.body.len(), 10);
+        assert_eq!(message.body_bytes().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn default_binding_keys_follow_exchange_type() {
+        assert_eq!(default_binding_keys("logs_topic", Some("topic")).unwrap(), ["#"]);
+        assert_eq!(default_binding_keys("logs", Some("fanout")).unwrap(), [""]);
+        assert!(default_binding_keys("logs_direct", Some("direct")).is_err());
+        assert!(default_binding_keys("logs_direct", None).is_err());
+    }
+
+    #[test]
+    fn replay_target_filters_and_rewrites() {
+        let message = recorded(BasicProperties::default(), b"{}");
+        let options = ReplayOptions {
+            filter: Some(Regex::new("^order").unwrap()),
+            rewrite: Some((Regex::new(r"^order\.(.*)").unwrap(), "audit.order.$1".to_string())),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.target(&message),
+            Some(("logs_topic".to_string(), "audit.order.created".to_string()))
+        );
+        let skip = ReplayOptions {
+            filter: Some(Regex::new("^user").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(skip.target(&message), None);
+    }
+}
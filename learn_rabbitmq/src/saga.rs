@@ -0,0 +1,793 @@
+// Saga / process manager: state machine theo correlation id, chạy theo event trên topic exchange.
+//
+//   let definition = SagaDefinition::new("order_fulfillment", "new")
+//       .on("new", "order.created", "awaiting_payment", |data, event| vec![SagaCommand::new("logs_topic", "inventory.reserve", ...)])
+//       .on("awaiting_payment", "order.payment.success", "completed", ...)
+//       .on("awaiting_payment", "order.payment.failed", "cancelled", ...)       // compensation: inventory.release
+//       .on_timeout("awaiting_payment", Duration::from_secs(30), "cancelled", ...)
+//       .terminal("completed")
+//       .terminal("cancelled");
+//
+// Event type = routing key. Chưa có instance cho correlation id → chỉ event có transition từ state đầu
+// (`initial`) mới tạo instance. Mỗi transition có thể sửa `data` của instance và trả về commands để publish.
+//
+// Thứ tự trong SagaManager::handle_delivery: tính transition → publish commands (confirm) → lưu state → ack.
+//   - Publish lỗi → nack + requeue, state chưa đổi → lần sau chạy lại
+//   - Crash sau publish, trước khi lưu → event redeliver → commands bị publish LẦN NỮA.
+//     Command có message_id cố định "<saga>:<correlation id>:<số transition>:<state>:<routing key>"
+//     (số transition = độ dài history sau transition → khác nhau khi instance quay lại 1 state, giống nhau
+//     khi chạy lại cùng 1 transition) → consumer dùng IdempotentConsumer (src/dedup.rs) để bỏ bản trùng.
+// 1 correlation id chỉ nên được xử lý bởi 1 manager tại 1 thời điểm (1 consumer, hoặc partition theo
+// correlation id - src/partition.rs), store không khóa instance.
+
+use crate::batch::{BatchPublisher, OutgoingMessage, PublishOutcome};
+use crate::dedup::sqlite_error;
+use crate::util::now_millis;
+use futures::StreamExt;
+use lapin::{
+    message::Delivery,
+    options::*,
+    types::ShortString,
+    BasicProperties, Consumer, Result as LapinResult,
+};
+use rusqlite::{params, Connection as SqliteConnection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Trigger của transition do hết hạn (xuất hiện trong history)
+pub const TIMEOUT_TRIGGER: &str = "timeout";
+
+// Message publish khi chuyển state (payload JSON, correlation_id = correlation id của instance)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SagaCommand {
+    pub exchange: String,
+    pub routing_key: String,
+    pub payload: Value,
+}
+
+impl SagaCommand {
+    pub fn new(exchange: &str, routing_key: &str, payload: Value) -> Self {
+        SagaCommand {
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+            payload,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub from: String,
+    pub to: String,
+    // Event type, hoặc TIMEOUT_TRIGGER
+    pub trigger: String,
+    pub at_ms: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SagaInstance {
+    pub saga: String,
+    pub correlation_id: String,
+    pub state: String,
+    // Dữ liệu tích lũy qua các event (action tự ghi)
+    pub data: Value,
+    // Có giá trị khi state hiện tại có on_timeout
+    pub deadline_ms: Option<i64>,
+    pub history: Vec<HistoryEntry>,
+    pub updated_at_ms: i64,
+}
+
+pub trait SagaStore: Send + Sync {
+    fn load(&self, saga: &str, correlation_id: &str) -> io::Result<Option<SagaInstance>>;
+
+    fn save(&self, instance: &SagaInstance) -> io::Result<()>;
+
+    // Instance có deadline <= now_ms
+    fn expired(&self, saga: &str, now_ms: i64) -> io::Result<Vec<SagaInstance>>;
+}
+
+// Mất khi restart - dùng cho thử nghiệm / saga ngắn
+#[derive(Default)]
+pub struct MemorySagaStore {
+    instances: Mutex<HashMap<(String, String), SagaInstance>>,
+}
+
+impl MemorySagaStore {
+    pub fn new() -> Self {
+        MemorySagaStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SagaStore for MemorySagaStore {
+    fn load(&self, saga: &str, correlation_id: &str) -> io::Result<Option<SagaInstance>> {
+        let key = (saga.to_string(), correlation_id.to_string());
+        Ok(self.instances.lock().unwrap().get(&key).cloned())
+    }
+
+    fn save(&self, instance: &SagaInstance) -> io::Result<()> {
+        let key = (instance.saga.clone(), instance.correlation_id.clone());
+        self.instances.lock().unwrap().insert(key, instance.clone());
+        Ok(())
+    }
+
+    fn expired(&self, saga: &str, now_ms: i64) -> io::Result<Vec<SagaInstance>> {
+        Ok(self
+            .instances
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|i| i.saga == saga && i.deadline_ms.is_some_and(|deadline| deadline <= now_ms))
+            .cloned()
+            .collect())
+    }
+}
+
+// SQLite: giữ state qua restart (timeout vẫn chạy sau khi process khởi động lại)
+pub struct SqliteSagaStore {
+    conn: Mutex<SqliteConnection>,
+}
+
+impl SqliteSagaStore {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let conn = SqliteConnection::open(path).map_err(sqlite_error)?;
+        conn.busy_timeout(Duration::from_secs(5)).map_err(sqlite_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS saga_instances (
+                saga TEXT NOT NULL,
+                correlation_id TEXT NOT NULL,
+                state TEXT NOT NULL,
+                data TEXT NOT NULL,
+                deadline_ms INTEGER,
+                history TEXT NOT NULL,
+                updated_at_ms INTEGER NOT NULL,
+                PRIMARY KEY (saga, correlation_id)
+            );
+            CREATE INDEX IF NOT EXISTS saga_instances_deadline
+                ON saga_instances (saga, deadline_ms) WHERE deadline_ms IS NOT NULL;",
+        )
+        .map_err(sqlite_error)?;
+        Ok(SqliteSagaStore { conn: Mutex::new(conn) })
+    }
+}
+
+const SAGA_COLUMNS: &str = "saga, correlation_id, state, data, deadline_ms, history, updated_at_ms";
+
+fn json_column<T: serde::de::DeserializeOwned>(row: &rusqlite::Row, index: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into()))
+}
+
+fn row_to_instance(row: &rusqlite::Row) -> rusqlite::Result<SagaInstance> {
+    Ok(SagaInstance {
+        saga: row.get(0)?,
+        correlation_id: row.get(1)?,
+        state: row.get(2)?,
+        data: json_column(row, 3)?,
+        deadline_ms: row.get(4)?,
+        history: json_column(row, 5)?,
+        updated_at_ms: row.get(6)?,
+    })
+}
+
+impl SagaStore for SqliteSagaStore {
+    fn load(&self, saga: &str, correlation_id: &str) -> io::Result<Option<SagaInstance>> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM saga_instances WHERE saga = ?1 AND correlation_id = ?2",
+                    SAGA_COLUMNS
+                ),
+                params![saga, correlation_id],
+                row_to_instance,
+            )
+            .optional()
+            .map_err(sqlite_error)
+    }
+
+    fn save(&self, instance: &SagaInstance) -> io::Result<()> {
+        let data = serde_json::to_string(&instance.data)?;
+        let history = serde_json::to_string(&instance.history)?;
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                &format!(
+                    "INSERT OR REPLACE INTO saga_instances ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    SAGA_COLUMNS
+                ),
+                params![
+                    instance.saga,
+                    instance.correlation_id,
+                    instance.state,
+                    data,
+                    instance.deadline_ms,
+                    history,
+                    instance.updated_at_ms
+                ],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn expired(&self, saga: &str, now_ms: i64) -> io::Result<Vec<SagaInstance>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {} FROM saga_instances WHERE saga = ?1 AND deadline_ms <= ?2 ORDER BY deadline_ms",
+                SAGA_COLUMNS
+            ))
+            .map_err(sqlite_error)?;
+        statement
+            .query_map(params![saga, now_ms], row_to_instance)
+            .map_err(sqlite_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(sqlite_error)
+    }
+}
+
+// action(data, event payload) → commands. Timeout: event payload = Value::Null
+type Action = Arc<dyn Fn(&mut Value, &Value) -> Vec<SagaCommand> + Send + Sync>;
+
+struct Transition {
+    to: String,
+    action: Action,
+}
+
+struct Timeout {
+    after: Duration,
+    transition: Transition,
+}
+
+pub struct SagaDefinition {
+    name: String,
+    initial: String,
+    // (state, event type) → transition
+    transitions: HashMap<(String, String), Transition>,
+    timeouts: HashMap<String, Timeout>,
+    terminal: BTreeSet<String>,
+}
+
+impl SagaDefinition {
+    pub fn new(name: &str, initial: &str) -> Self {
+        SagaDefinition {
+            name: name.to_string(),
+            initial: initial.to_string(),
+            transitions: HashMap::new(),
+            timeouts: HashMap::new(),
+            terminal: BTreeSet::new(),
+        }
+    }
+
+    pub fn on<F>(mut self, from: &str, event_type: &str, to: &str, action: F) -> Self
+    where
+        F: Fn(&mut Value, &Value) -> Vec<SagaCommand> + Send + Sync + 'static,
+    {
+        self.transitions.insert(
+            (from.to_string(), event_type.to_string()),
+            Transition {
+                to: to.to_string(),
+                action: Arc::new(action),
+            },
+        );
+        self
+    }
+
+    // Ở `state` quá `after` mà không có event nào chuyển đi → chuyển sang `to` (thường là compensation)
+    pub fn on_timeout<F>(mut self, state: &str, after: Duration, to: &str, action: F) -> Self
+    where
+        F: Fn(&mut Value, &Value) -> Vec<SagaCommand> + Send + Sync + 'static,
+    {
+        self.timeouts.insert(
+            state.to_string(),
+            Timeout {
+                after,
+                transition: Transition {
+                    to: to.to_string(),
+                    action: Arc::new(action),
+                },
+            },
+        );
+        self
+    }
+
+    // Instance ở state này không nhận event nữa (vẫn được giữ trong store)
+    pub fn terminal(mut self, state: &str) -> Self {
+        self.terminal.insert(state.to_string());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Routing keys cần bind vào queue của saga
+    pub fn event_types(&self) -> BTreeSet<String> {
+        self.transitions.keys().map(|(_, event)| event.clone()).collect()
+    }
+
+    pub fn is_terminal(&self, state: &str) -> bool {
+        self.terminal.contains(state)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SagaOutcome {
+    Transitioned {
+        correlation_id: String,
+        from: String,
+        to: String,
+        commands: Vec<OutgoingMessage>,
+    },
+    // Event không làm gì: không có instance / không có transition / instance đã kết thúc
+    Ignored { correlation_id: String, reason: String },
+}
+
+impl fmt::Display for SagaOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SagaOutcome::Transitioned {
+                correlation_id,
+                from,
+                to,
+                commands,
+            } => write!(
+                f,
+                "[{}] {} → {} ({} command(s))",
+                correlation_id,
+                from,
+                to,
+                commands.len()
+            ),
+            SagaOutcome::Ignored { correlation_id, reason } => write!(f, "[{}] ignored: {}", correlation_id, reason),
+        }
+    }
+}
+
+// Lấy correlation id từ (event type, payload, properties)
+type CorrelateFn = Arc<dyn Fn(&str, &Value, &BasicProperties) -> Option<String> + Send + Sync>;
+
+pub struct SagaManager {
+    definition: Arc<SagaDefinition>,
+    store: Arc<dyn SagaStore>,
+    correlate: CorrelateFn,
+}
+
+impl SagaManager {
+    // Mặc định correlation id = property correlation_id
+    pub fn new(definition: SagaDefinition, store: Arc<dyn SagaStore>) -> Self {
+        SagaManager {
+            definition: Arc::new(definition),
+            store,
+            correlate: Arc::new(|_, _, properties| properties.correlation_id().as_ref().map(|id| id.to_string())),
+        }
+    }
+
+    // Vd producer cũ không đặt correlation_id: lấy từ field trong payload
+    pub fn with_correlation<F>(mut self, correlate: F) -> Self
+    where
+        F: Fn(&str, &Value, &BasicProperties) -> Option<String> + Send + Sync + 'static,
+    {
+        self.correlate = Arc::new(correlate);
+        self
+    }
+
+    pub fn definition(&self) -> &SagaDefinition {
+        &self.definition
+    }
+
+    pub fn store(&self) -> Arc<dyn SagaStore> {
+        self.store.clone()
+    }
+
+    // Tính transition cho 1 event, CHƯA lưu. None = không có gì thay đổi
+    fn apply(&self, event_type: &str, correlation_id: &str, payload: &Value, now_ms: i64) -> io::Result<(SagaOutcome, Option<SagaInstance>)> {
+        let definition = &self.definition;
+        let ignored = |reason: String| {
+            Ok((
+                SagaOutcome::Ignored {
+                    correlation_id: correlation_id.to_string(),
+                    reason,
+                },
+                None,
+            ))
+        };
+
+        let mut instance = match self.store.load(&definition.name, correlation_id)? {
+            Some(instance) => instance,
+            None => SagaInstance {
+                saga: definition.name.clone(),
+                correlation_id: correlation_id.to_string(),
+                state: definition.initial.clone(),
+                data: Value::Object(Default::default()),
+                deadline_ms: None,
+                history: Vec::new(),
+                updated_at_ms: now_ms,
+            },
+        };
+        if definition.is_terminal(&instance.state) {
+            return ignored(format!("saga already {}", instance.state));
+        }
+
+        let transition = if event_type == TIMEOUT_TRIGGER {
+            match definition.timeouts.get(&instance.state) {
+                Some(timeout) => &timeout.transition,
+                None => return ignored(format!("no timeout in state '{}'", instance.state)),
+            }
+        } else {
+            match definition
+                .transitions
+                .get(&(instance.state.clone(), event_type.to_string()))
+            {
+                Some(transition) => transition,
+                None => return ignored(format!("no transition for '{}' in state '{}'", event_type, instance.state)),
+            }
+        };
+
+        let from = std::mem::replace(&mut instance.state, transition.to.clone());
+        let commands = (transition.action)(&mut instance.data, payload);
+        instance.deadline_ms = definition
+            .timeouts
+            .get(&instance.state)
+            .filter(|_| !definition.is_terminal(&instance.state))
+            .map(|timeout| now_ms + timeout.after.as_millis() as i64);
+        instance.history.push(HistoryEntry {
+            from: from.clone(),
+            to: instance.state.clone(),
+            trigger: event_type.to_string(),
+            at_ms: now_ms,
+        });
+        instance.updated_at_ms = now_ms;
+
+        let sequence = instance.history.len();
+        let commands = commands
+            .into_iter()
+            .map(|command| self.to_outgoing(correlation_id, sequence, &instance.state, command))
+            .collect();
+        Ok((
+            SagaOutcome::Transitioned {
+                correlation_id: correlation_id.to_string(),
+                from,
+                to: instance.state.clone(),
+                commands,
+            },
+            Some(instance),
+        ))
+    }
+
+    fn to_outgoing(&self, correlation_id: &str, sequence: usize, state: &str, command: SagaCommand) -> OutgoingMessage {
+        let message_id = format!(
+            "{}:{}:{}:{}:{}",
+            self.definition.name, correlation_id, sequence, state, command.routing_key
+        );
+        let properties = BasicProperties::default()
+            .with_content_type(ShortString::from("application/json"))
+            .with_correlation_id(ShortString::from(correlation_id.to_string()))
+            .with_message_id(ShortString::from(message_id))
+            .with_delivery_mode(2);
+        OutgoingMessage::new(
+            &command.exchange,
+            &command.routing_key,
+            serde_json::to_vec(&command.payload).unwrap_or_default(),
+        )
+        .with_properties(properties)
+    }
+
+    // Xử lý event và lưu state NGAY (không publish) - dùng khi tự publish commands hoặc để thử state machine
+    pub fn handle_event(&self, event_type: &str, correlation_id: &str, payload: &Value) -> io::Result<SagaOutcome> {
+        let (outcome, instance) = self.apply(event_type, correlation_id, payload, now_millis())?;
+        if let Some(instance) = instance {
+            self.store.save(&instance)?;
+        }
+        Ok(outcome)
+    }
+
+    // Event → transition → publish commands → lưu state → ack.
+    // Không decode được / không có correlation id → reject (không requeue). Publish / store lỗi → requeue
+    pub async fn handle_delivery(&self, delivery: &Delivery, publisher: &mut BatchPublisher) -> LapinResult<SagaOutcome> {
+        let event_type = delivery.routing_key.as_str();
+        let reject = |reason: String| SagaOutcome::Ignored {
+            correlation_id: String::new(),
+            reason,
+        };
+
+        let payload: Value = match serde_json::from_slice(&delivery.data) {
+            Ok(payload) => payload,
+            Err(e) => {
+                delivery.nack(BasicNackOptions::default()).await?;
+                return Ok(reject(format!("invalid JSON: {}", e)));
+            }
+        };
+        let Some(correlation_id) = (self.correlate)(event_type, &payload, &delivery.properties) else {
+            delivery.nack(BasicNackOptions::default()).await?;
+            return Ok(reject(format!("'{}' has no correlation id", event_type)));
+        };
+
+        match self.commit(event_type, &correlation_id, &payload, publisher).await {
+            Ok(outcome) => {
+                delivery.ack(BasicAckOptions::default()).await?;
+                Ok(outcome)
+            }
+            Err(reason) => {
+                delivery
+                    .nack(BasicNackOptions {
+                        requeue: true,
+                        ..Default::default()
+                    })
+                    .await?;
+                Ok(SagaOutcome::Ignored { correlation_id, reason })
+            }
+        }
+    }
+
+    async fn commit(
+        &self,
+        event_type: &str,
+        correlation_id: &str,
+        payload: &Value,
+        publisher: &mut BatchPublisher,
+    ) -> Result<SagaOutcome, String> {
+        let (outcome, instance) = self
+            .apply(event_type, correlation_id, payload, now_millis())
+            .map_err(|e| format!("saga store: {}", e))?;
+        if let SagaOutcome::Transitioned { commands, .. } = &outcome
+            && !commands.is_empty()
+        {
+            let report = publisher.publish_batch(commands.clone()).await;
+            if let Some(failed) = report.results.iter().find(|r| r.outcome != PublishOutcome::Acked) {
+                return Err(format!("command not published: {:?}", failed.outcome));
+            }
+        }
+        if let Some(instance) = instance {
+            self.store.save(&instance).map_err(|e| format!("saga store: {}", e))?;
+        }
+        Ok(outcome)
+    }
+
+    // Chạy transition timeout cho mọi instance quá hạn (publish compensation commands rồi lưu).
+    // 1 instance lỗi → Ignored (kèm lý do) và chạy tiếp các instance khác; instance lỗi vẫn quá hạn
+    // → thử lại ở lần gọi sau. Err chỉ khi không đọc được danh sách instance quá hạn
+    pub async fn fire_timeouts(&self, publisher: &mut BatchPublisher) -> io::Result<Vec<SagaOutcome>> {
+        let mut outcomes = Vec::new();
+        for instance in self.store.expired(&self.definition.name, now_millis())? {
+            let outcome = match self
+                .commit(TIMEOUT_TRIGGER, &instance.correlation_id, &Value::Null, publisher)
+                .await
+            {
+                Ok(outcome) => outcome,
+                Err(reason) => SagaOutcome::Ignored {
+                    correlation_id: instance.correlation_id,
+                    reason: format!("timeout not fired: {}", reason),
+                },
+            };
+            outcomes.push(outcome);
+        }
+        Ok(outcomes)
+    }
+
+    // Consume events + kiểm tra timeout mỗi `timeout_check` cho tới khi consumer kết thúc
+    pub async fn run<F>(
+        &self,
+        mut consumer: Consumer,
+        publisher: &mut BatchPublisher,
+        timeout_check: Duration,
+        mut on_outcome: F,
+    ) -> LapinResult<()>
+    where
+        F: FnMut(&SagaOutcome),
+    {
+        let mut ticker = tokio::time::interval(timeout_check);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                delivery = consumer.next() => {
+                    let Some(delivery) = delivery else { return Ok(()) };
+                    let outcome = self.handle_delivery(&delivery?, publisher).await?;
+                    on_outcome(&outcome);
+                }
+                _ = ticker.tick() => {
+                    // Không đọc được store → thử lại ở tick sau (lỗi từng instance nằm trong outcomes)
+                    match self.fire_timeouts(publisher).await {
+                        Ok(outcomes) => outcomes.iter().for_each(&mut on_outcome),
+                        Err(e) => println!("✗ saga timeouts: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition() -> SagaDefinition {
+        SagaDefinition::new("payment_retry", "new")
+            .on("new", "order.created", "awaiting_payment", |_, _| {
+                vec![SagaCommand::new("logs_topic", "payment.request", Value::Null)]
+            })
+            .on("awaiting_payment", "order.payment.failed", "awaiting_payment", |_, _| {
+                vec![SagaCommand::new("logs_topic", "payment.request", Value::Null)]
+            })
+            .on("awaiting_payment", "order.payment.success", "completed", |_, _| Vec::new())
+            .on_timeout("awaiting_payment", TIMEOUT, "cancelled", |data, _| {
+                data["cancelled"] = Value::Bool(true);
+                vec![SagaCommand::new("logs_topic", "order.cancel", Value::Null)]
+            })
+            .terminal("completed")
+            .terminal("cancelled")
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn instance(correlation_id: &str, deadline_ms: Option<i64>) -> SagaInstance {
+        SagaInstance {
+            saga: "payment_retry".to_string(),
+            correlation_id: correlation_id.to_string(),
+            state: "awaiting_payment".to_string(),
+            data: serde_json::json!({"attempts": 1}),
+            deadline_ms,
+            history: vec![HistoryEntry {
+                from: "new".to_string(),
+                to: "awaiting_payment".to_string(),
+                trigger: "order.created".to_string(),
+                at_ms: 500,
+            }],
+            updated_at_ms: 500,
+        }
+    }
+
+    // Áp dụng event với thời điểm cố định rồi lưu, như handle_event
+    fn apply_at(manager: &SagaManager, event_type: &str, now_ms: i64) -> (SagaOutcome, Option<SagaInstance>) {
+        let (outcome, instance) = manager.apply(event_type, "300", &Value::Null, now_ms).unwrap();
+        if let Some(instance) = &instance {
+            manager.store().save(instance).unwrap();
+        }
+        (outcome, instance)
+    }
+
+    fn message_ids(outcome: &SagaOutcome) -> Vec<String> {
+        match outcome {
+            SagaOutcome::Transitioned { commands, .. } => commands
+                .iter()
+                .map(|command| command.properties.message_id().as_ref().unwrap().to_string())
+                .collect(),
+            SagaOutcome::Ignored { reason, .. } => panic!("ignored: {}", reason),
+        }
+    }
+
+    // Quay lại cùng state (retry thanh toán) → command mới phải có message_id mới, không thì consumer
+    // dedup bỏ mất lần retry
+    #[test]
+    fn reentering_a_state_gets_a_new_message_id() {
+        let manager = SagaManager::new(definition(), Arc::new(MemorySagaStore::new()));
+        let ids: Vec<Vec<String>> = ["order.created", "order.payment.failed", "order.payment.failed"]
+            .into_iter()
+            .map(|event| message_ids(&manager.handle_event(event, "300", &Value::Null).unwrap()))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                ["payment_retry:300:1:awaiting_payment:payment.request"],
+                ["payment_retry:300:2:awaiting_payment:payment.request"],
+                ["payment_retry:300:3:awaiting_payment:payment.request"],
+            ]
+        );
+    }
+
+    // Crash trước khi lưu state → event redeliver → cùng transition → cùng message_id (consumer bỏ bản trùng)
+    #[test]
+    fn replaying_an_unsaved_transition_keeps_the_message_id() {
+        let manager = SagaManager::new(definition(), Arc::new(MemorySagaStore::new()));
+        manager.handle_event("order.created", "300", &Value::Null).unwrap();
+
+        let (first, _) = manager.apply("order.payment.failed", "300", &Value::Null, now_millis()).unwrap();
+        let (again, _) = manager.apply("order.payment.failed", "300", &Value::Null, now_millis()).unwrap();
+        assert_eq!(message_ids(&first), message_ids(&again));
+    }
+
+    #[test]
+    fn terminal_instances_ignore_events() {
+        let manager = SagaManager::new(definition(), Arc::new(MemorySagaStore::new()));
+        manager.handle_event("order.created", "300", &Value::Null).unwrap();
+        manager.handle_event("order.payment.success", "300", &Value::Null).unwrap();
+        let outcome = manager.handle_event("order.payment.failed", "300", &Value::Null).unwrap();
+        assert!(matches!(outcome, SagaOutcome::Ignored { reason, .. } if reason == "saga already completed"));
+
+        let instance = manager.store().load("payment_retry", "300").unwrap().unwrap();
+        assert_eq!(instance.history.len(), 2);
+    }
+
+    #[test]
+    fn sqlite_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("learn_rabbitmq_saga_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteSagaStore::open(&path).unwrap();
+        store.save(&instance("due", Some(1_000))).unwrap();
+        store.save(&instance("later", Some(5_000))).unwrap();
+        store.save(&instance("no_deadline", None)).unwrap();
+        store.save(&SagaInstance { saga: "other".to_string(), ..instance("other_saga", Some(0)) }).unwrap();
+
+        assert_eq!(store.load("payment_retry", "due").unwrap(), Some(instance("due", Some(1_000))));
+        assert_eq!(store.load("payment_retry", "missing").unwrap(), None);
+        assert_eq!(store.load("other", "due").unwrap(), None);
+
+        // deadline_ms <= now: deadline đúng bằng now cũng hết hạn
+        let ids = |now_ms| -> Vec<String> {
+            store.expired("payment_retry", now_ms).unwrap().into_iter().map(|i| i.correlation_id).collect()
+        };
+        assert!(ids(999).is_empty());
+        assert_eq!(ids(1_000), ["due"]);
+        assert_eq!(ids(i64::MAX), ["due", "later"]);
+
+        // save ghi đè instance cũ
+        store.save(&instance("due", None)).unwrap();
+        assert_eq!(ids(1_000), Vec::<String>::new());
+        assert_eq!(store.load("payment_retry", "due").unwrap().unwrap().deadline_ms, None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn timeout_deadline_is_set_on_entry_and_cleared_in_terminal_states() {
+        let manager = SagaManager::new(definition(), Arc::new(MemorySagaStore::new()));
+        let (_, instance) = apply_at(&manager, "order.created", 1_000);
+        assert_eq!(instance.unwrap().deadline_ms, Some(1_000 + TIMEOUT.as_millis() as i64));
+
+        // Vào lại state → deadline tính lại từ lần vào mới nhất
+        let (_, instance) = apply_at(&manager, "order.payment.failed", 2_000);
+        assert_eq!(instance.unwrap().deadline_ms, Some(2_000 + TIMEOUT.as_millis() as i64));
+        assert_eq!(manager.store().expired("payment_retry", 32_000).unwrap().len(), 1);
+
+        let (_, instance) = apply_at(&manager, "order.payment.success", 3_000);
+        assert_eq!(instance.unwrap().deadline_ms, None);
+        assert!(manager.store().expired("payment_retry", i64::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn timeout_takes_the_compensation_transition() {
+        let manager = SagaManager::new(definition(), Arc::new(MemorySagaStore::new()));
+        apply_at(&manager, "order.created", 1_000);
+
+        let (outcome, instance) = apply_at(&manager, TIMEOUT_TRIGGER, 31_000);
+        let SagaOutcome::Transitioned { from, to, .. } = &outcome else {
+            panic!("expected a transition, got {}", outcome);
+        };
+        assert_eq!((from.as_str(), to.as_str()), ("awaiting_payment", "cancelled"));
+        assert_eq!(message_ids(&outcome), ["payment_retry:300:2:cancelled:order.cancel"]);
+
+        let instance = instance.unwrap();
+        assert_eq!(instance.data["cancelled"], Value::Bool(true));
+        assert_eq!(instance.deadline_ms, None);
+        assert_eq!(instance.history.last().unwrap().trigger, TIMEOUT_TRIGGER);
+    }
+
+    #[test]
+    fn events_without_a_transition_are_ignored() {
+        let manager = SagaManager::new(definition(), Arc::new(MemorySagaStore::new()));
+        let outcome = manager.handle_event("order.payment.success", "300", &Value::Null).unwrap();
+        assert!(matches!(
+            &outcome,
+            SagaOutcome::Ignored { reason, .. } if reason == "no transition for 'order.payment.success' in state 'new'"
+        ));
+        let (outcome, _) = manager.apply(TIMEOUT_TRIGGER, "300", &Value::Null, 0).unwrap();
+        assert!(matches!(&outcome, SagaOutcome::Ignored { reason, .. } if reason == "no timeout in state 'new'"));
+        // Không có transition → không tạo instance
+        assert_eq!(manager.store().load("payment_retry", "300").unwrap(), None);
+    }
+}
@@ -0,0 +1,216 @@
+// Cron scheduler: publish các message cấu hình sẵn theo lịch (xem schedule.example.json)
+//   { "jobs": [ { "name": "heartbeat", "cron": "*/30 * * * * *", "exchange": "logs_topic",
+//                 "routing_key": "system.heartbeat", "body": { "status": "ok" } } ] }
+// Cron 5 trường (phút giờ ngày tháng thứ) như crontab, hoặc 6-7 trường (thêm giây ở đầu, năm ở cuối).
+// Giờ theo UTC. Lần chạy bị lỡ khi process tắt KHÔNG được chạy bù.
+
+use crate::batch::{BatchPublisher, BatchReport, OutgoingMessage};
+use crate::record::json_to_field_table;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use lapin::{
+    types::{AMQPValue, ShortString},
+    BasicProperties,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const SCHEDULED_AT_HEADER: &str = "x-scheduled-at";
+pub const JOB_HEADER: &str = "x-scheduled-job";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub name: String,
+    pub cron: String,
+    #[serde(default)]
+    pub exchange: String,
+    pub routing_key: String,
+    // String → publish nguyên văn, giá trị khác → JSON
+    pub body: Value,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub headers: Map<String, Value>,
+    #[serde(default)]
+    pub persistent: bool,
+}
+
+impl ScheduledJob {
+    pub fn to_outgoing(&self, scheduled_at: DateTime<Utc>) -> OutgoingMessage {
+        let (payload, content_type) = match &self.body {
+            Value::String(text) => (text.clone().into_bytes(), "text/plain"),
+            other => (serde_json::to_vec(other).unwrap_or_default(), "application/json"),
+        };
+
+        let mut headers = json_to_field_table(&self.headers);
+        headers.insert(JOB_HEADER.into(), AMQPValue::LongString(self.name.clone().into()));
+        headers.insert(
+            SCHEDULED_AT_HEADER.into(),
+            AMQPValue::LongString(scheduled_at.to_rfc3339().into()),
+        );
+        let mut properties = BasicProperties::default()
+            .with_content_type(ShortString::from(content_type))
+            .with_timestamp(scheduled_at.timestamp().max(0) as u64)
+            .with_headers(headers);
+        if self.persistent {
+            properties = properties.with_delivery_mode(2);
+        }
+
+        OutgoingMessage::new(&self.exchange, &self.routing_key, payload).with_properties(properties)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleFile {
+    pub jobs: Vec<ScheduledJob>,
+}
+
+// 5 trường crontab → thêm giây = 0
+pub fn parse_cron(expression: &str) -> Result<Schedule, String> {
+    let fields = expression.split_whitespace().count();
+    let expression = match fields {
+        5 => format!("0 {}", expression),
+        6 | 7 => expression.to_string(),
+        _ => return Err(format!("'{}': expected 5, 6 or 7 fields, got {}", expression, fields)),
+    };
+    Schedule::from_str(&expression).map_err(|e| format!("invalid cron: {}", e))
+}
+
+pub struct Scheduler {
+    jobs: Vec<(ScheduledJob, Schedule)>,
+}
+
+impl Scheduler {
+    pub fn new(jobs: Vec<ScheduledJob>) -> Result<Self, String> {
+        let jobs = jobs
+            .into_iter()
+            .map(|job| {
+                let schedule = parse_cron(&job.cron).map_err(|e| format!("job '{}': {}", job.name, e))?;
+                Ok((job, schedule))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Scheduler { jobs })
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+        let content = std::fs::read_to_string(path)?;
+        let file: ScheduleFile = serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+        Scheduler::new(file.jobs).map_err(invalid)
+    }
+
+    pub fn jobs(&self) -> impl Iterator<Item = &ScheduledJob> {
+        self.jobs.iter().map(|(job, _)| job)
+    }
+
+    // `count` lần chạy kế tiếp của mỗi job sau `after` (dùng cho --dry-run)
+    pub fn upcoming(&self, after: DateTime<Utc>, count: usize) -> Vec<(&ScheduledJob, DateTime<Utc>)> {
+        let mut runs: Vec<(&ScheduledJob, DateTime<Utc>)> = self
+            .jobs
+            .iter()
+            .flat_map(|(job, schedule)| schedule.after(&after).take(count).map(move |at| (job, at)))
+            .collect();
+        runs.sort_by_key(|(_, at)| *at);
+        runs
+    }
+
+    // Thời điểm sớm nhất sau `after` + các job chạy lúc đó
+    pub fn next_due(&self, after: DateTime<Utc>) -> Option<(DateTime<Utc>, Vec<&ScheduledJob>)> {
+        let next: Vec<(&ScheduledJob, DateTime<Utc>)> = self
+            .jobs
+            .iter()
+            .filter_map(|(job, schedule)| schedule.after(&after).next().map(|at| (job, at)))
+            .collect();
+        let at = next.iter().map(|(_, at)| *at).min()?;
+        let jobs = next.into_iter().filter(|(_, t)| *t == at).map(|(job, _)| job).collect();
+        Some((at, jobs))
+    }
+
+    // Chạy tới khi channel đóng: ngủ tới lần kế tiếp, publish các job tới hạn (qua confirm), gọi `on_publish` với kết quả
+    pub async fn run<F>(&self, publisher: &mut BatchPublisher, mut on_publish: F) -> lapin::Result<()>
+    where
+        F: FnMut(DateTime<Utc>, &[&ScheduledJob], &BatchReport),
+    {
+        let mut after = Utc::now();
+        while let Some((at, jobs)) = self.next_due(after) {
+            let wait = (at - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            let messages: Vec<OutgoingMessage> = jobs.iter().map(|job| job.to_outgoing(at)).collect();
+            let report = publisher.publish_batch(messages).await;
+            on_publish(at, &jobs, &report);
+            // Channel chết → dừng (caller tự kết nối lại), thay vì báo lỗi ở mọi lần chạy sau
+            let status = publisher.channel().status();
+            if report.failed() > 0 && !status.connected() {
+                return Err(lapin::Error::InvalidChannelState(status.state()));
+            }
+            after = at;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn next_runs(expression: &str, after: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        parse_cron(expression).unwrap().after(&after).take(count).collect()
+    }
+
+    #[test]
+    fn five_fields_run_at_second_zero() {
+        let after = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 30).unwrap();
+        assert_eq!(
+            next_runs("*/15 * * * *", after, 2),
+            [
+                Utc.with_ymd_and_hms(2024, 1, 1, 10, 15, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 1, 1, 10, 30, 0).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn six_and_seven_fields_keep_seconds_and_year() {
+        let after = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        assert_eq!(
+            next_runs("*/30 * * * * *", after, 2),
+            [
+                Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 30).unwrap(),
+                Utc.with_ymd_and_hms(2024, 1, 1, 10, 1, 0).unwrap(),
+            ]
+        );
+        assert_eq!(
+            next_runs("0 0 12 1 1 * 2030", after, 2),
+            [Utc.with_ymd_and_hms(2030, 1, 1, 12, 0, 0).unwrap()]
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_field_count_and_bad_values() {
+        assert!(parse_cron("* * * *").unwrap_err().contains("expected 5, 6 or 7 fields, got 4"));
+        assert!(parse_cron("* * * * * * * *").unwrap_err().contains("got 8"));
+        assert!(parse_cron("61 * * * *").unwrap_err().starts_with("invalid cron"));
+
+        let job = ScheduledJob {
+            name: "broken".to_string(),
+            cron: "not a cron".to_string(),
+            exchange: String::new(),
+            routing_key: "q".to_string(),
+            body: Value::Null,
+            headers: Map::new(),
+            persistent: false,
+        };
+        assert!(Scheduler::new(vec![job]).err().unwrap().starts_with("job 'broken'"));
+    }
+
+    #[test]
+    fn example_schedule_parses() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("schedule.example.json");
+        let scheduler = Scheduler::from_file(&path).unwrap();
+        assert!(scheduler.jobs().count() > 0);
+        assert!(scheduler.next_due(Utc::now()).is_some());
+    }
+}
@@ -0,0 +1,434 @@
+// JSON Schema validation cho payload khi publish và consume
+// Registry = thư mục schemas, mỗi message type 1 thư mục con, mỗi version 1 file:
+//   schemas/message/v1.json
+//   schemas/order.created/v1.json, schemas/order.created/v2.json
+// Message type lấy từ property `type` (BasicProperties::with_type), version từ header `x-message-version`
+// (không có header → version 1).
+// Consumer: message sai schema được publish sang dead-letter exchange kèm header
+// `x-validation-errors` (RabbitMQ tự dead-letter khi nack thì KHÔNG thêm header được).
+
+use crate::batch::{BatchPublisher, OutgoingMessage, PublishOutcome};
+use crate::middleware::{BoxHandler, HandlerError, Layer};
+use crate::record::amqp_to_json;
+use futures::FutureExt;
+use jsonschema::Validator;
+use lapin::{
+    message::Delivery,
+    types::{AMQPValue, FieldArray, FieldTable, LongString, ShortString},
+    BasicProperties, Result as LapinResult,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const VERSION_HEADER: &str = "x-message-version";
+pub const ERRORS_HEADER: &str = "x-validation-errors";
+pub const ORIGINAL_EXCHANGE_HEADER: &str = "x-original-exchange";
+pub const ORIGINAL_ROUTING_KEY_HEADER: &str = "x-original-routing-key";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaViolation {
+    // Không có property `type` (chỉ lỗi khi registry strict)
+    MissingType,
+    // Không có schema cho type/version này (chỉ lỗi khi registry strict)
+    UnknownSchema { message_type: String, version: u32 },
+    NotJson(String),
+    // Mỗi lỗi dạng "/path: message"
+    Invalid { message_type: String, version: u32, errors: Vec<String> },
+}
+
+impl SchemaViolation {
+    pub fn errors(&self) -> Vec<String> {
+        match self {
+            SchemaViolation::Invalid { errors, .. } => errors.clone(),
+            other => vec![other.to_string()],
+        }
+    }
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaViolation::MissingType => write!(f, "message has no `type` property"),
+            SchemaViolation::UnknownSchema { message_type, version } => {
+                write!(f, "no schema for '{}' v{}", message_type, version)
+            }
+            SchemaViolation::NotJson(e) => write!(f, "payload is not JSON: {}", e),
+            SchemaViolation::Invalid { message_type, version, errors } => {
+                write!(f, "'{}' v{} failed validation: {}", message_type, version, errors.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaViolation {}
+
+#[derive(Default)]
+pub struct SchemaRegistry {
+    validators: BTreeMap<(String, u32), Validator>,
+    // true: message không có type / không có schema bị từ chối
+    strict: bool,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Đọc mọi `<dir>/<type>/v<version>.json`
+    pub fn load_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut registry = SchemaRegistry::new();
+        for type_dir in std::fs::read_dir(dir)? {
+            let type_dir = type_dir?.path();
+            if !type_dir.is_dir() {
+                continue;
+            }
+            let message_type = type_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+            for file in std::fs::read_dir(&type_dir)? {
+                let path = file?.path();
+                let Some(version) = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix('v'))
+                    .and_then(|name| name.strip_suffix(".json"))
+                    .and_then(|version| version.parse().ok())
+                else {
+                    continue;
+                };
+
+                let content = std::fs::read_to_string(&path)?;
+                let schema: Value = serde_json::from_str(&content).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+                })?;
+                registry
+                    .register(&message_type, version, &schema)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn register(&mut self, message_type: &str, version: u32, schema: &Value) -> Result<(), String> {
+        let validator = jsonschema::validator_for(schema).map_err(|e| e.to_string())?;
+        self.validators.insert((message_type.to_string(), version), validator);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn schemas(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.validators.keys().map(|(message_type, version)| (message_type.as_str(), *version))
+    }
+
+    pub fn latest_version(&self, message_type: &str) -> Option<u32> {
+        self.schemas()
+            .filter(|(t, _)| *t == message_type)
+            .map(|(_, version)| version)
+            .max()
+    }
+
+    pub fn validate(&self, message_type: &str, version: u32, payload: &Value) -> Result<(), SchemaViolation> {
+        let Some(validator) = self.validators.get(&(message_type.to_string(), version)) else {
+            return if self.strict {
+                Err(SchemaViolation::UnknownSchema {
+                    message_type: message_type.to_string(),
+                    version,
+                })
+            } else {
+                Ok(())
+            };
+        };
+
+        let errors: Vec<String> = validator
+            .iter_errors(payload)
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                format!("{}: {}", if path.is_empty() { "/" } else { &path }, e)
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SchemaViolation::Invalid {
+                message_type: message_type.to_string(),
+                version,
+                errors,
+            })
+        }
+    }
+
+    // Dùng type/version trong properties (publish lẫn consume)
+    pub fn validate_message(&self, properties: &BasicProperties, payload: &[u8]) -> Result<(), SchemaViolation> {
+        let Some(message_type) = properties.kind() else {
+            return if self.strict {
+                Err(SchemaViolation::MissingType)
+            } else {
+                Ok(())
+            };
+        };
+        let message_type = message_type.as_str();
+        let version = message_version(properties).unwrap_or(1);
+
+        // Không có schema và không strict → khỏi parse JSON
+        if !self.strict && !self.validators.contains_key(&(message_type.to_string(), version)) {
+            return Ok(());
+        }
+        let payload: Value =
+            serde_json::from_slice(payload).map_err(|e| SchemaViolation::NotJson(e.to_string()))?;
+        self.validate(message_type, version, &payload)
+    }
+
+    pub fn validate_delivery(&self, delivery: &Delivery) -> Result<(), SchemaViolation> {
+        self.validate_message(&delivery.properties, &delivery.data)
+    }
+}
+
+// Header `x-message-version` (số hoặc string số)
+pub fn message_version(properties: &BasicProperties) -> Option<u32> {
+    let value = properties.headers().as_ref()?.inner().get(VERSION_HEADER)?;
+    match amqp_to_json(value) {
+        Value::Number(n) => n.as_u64().and_then(|v| u32::try_from(v).ok()),
+        Value::String(s) => s.trim_start_matches('v').parse().ok(),
+        _ => None,
+    }
+}
+
+// Properties với `type` + header version, giữ các headers sẵn có
+pub fn with_message_type(properties: BasicProperties, message_type: &str, version: u32) -> BasicProperties {
+    let mut headers = properties.headers().clone().unwrap_or_default();
+    headers.insert(VERSION_HEADER.into(), AMQPValue::LongUInt(version));
+    properties
+        .with_type(ShortString::from(message_type.to_string()))
+        .with_headers(headers)
+}
+
+// Bản sao message sai schema, gửi sang `exchange` (routing key giữ nguyên) kèm lỗi trong headers
+pub fn dead_letter_message(exchange: &str, delivery: &Delivery, violation: &SchemaViolation) -> OutgoingMessage {
+    let mut headers: FieldTable = delivery.properties.headers().clone().unwrap_or_default();
+    let errors: Vec<AMQPValue> = violation
+        .errors()
+        .into_iter()
+        .map(|e| AMQPValue::LongString(LongString::from(e)))
+        .collect();
+    headers.insert(ERRORS_HEADER.into(), AMQPValue::FieldArray(FieldArray::from(errors)));
+    headers.insert(
+        ORIGINAL_EXCHANGE_HEADER.into(),
+        AMQPValue::LongString(LongString::from(delivery.exchange.to_string())),
+    );
+    headers.insert(
+        ORIGINAL_ROUTING_KEY_HEADER.into(),
+        AMQPValue::LongString(LongString::from(delivery.routing_key.to_string())),
+    );
+
+    OutgoingMessage::new(exchange, delivery.routing_key.as_str(), delivery.data.clone())
+        .with_properties(delivery.properties.clone().with_headers(headers))
+}
+
+// Publish bản sao qua `publisher` (confirm mode, nên bật mandatory). Chỉ Ok khi broker ack và
+// message route được tới queue - lúc đó mới ack bản gốc được; nếu không, bản gốc phải requeue.
+pub async fn dead_letter(
+    publisher: &mut BatchPublisher,
+    exchange: &str,
+    delivery: &Delivery,
+    violation: &SchemaViolation,
+) -> LapinResult<()> {
+    let message = dead_letter_message(exchange, delivery, violation);
+    let report = publisher.publish_batch(std::iter::once(message)).await;
+    match report.results.first().map(|r| &r.outcome) {
+        Some(PublishOutcome::Acked) => Ok(()),
+        outcome => Err(lapin::Error::IOError(Arc::new(io::Error::other(format!(
+            "not confirmed: {:?}",
+            outcome
+        ))))),
+    }
+}
+
+// Middleware layer (src/middleware.rs): validate trước handler.
+// Sai schema → HandlerError::Invalid, handler không chạy.
+pub struct SchemaLayer {
+    registry: Arc<SchemaRegistry>,
+    dead_letter: Option<(Arc<tokio::sync::Mutex<BatchPublisher>>, String)>,
+}
+
+impl SchemaLayer {
+    pub fn new(registry: Arc<SchemaRegistry>) -> Self {
+        SchemaLayer {
+            registry,
+            dead_letter: None,
+        }
+    }
+
+    // Publish bản sao kèm `x-validation-errors` sang `exchange` qua `publisher` (bật mandatory),
+    // ack bản gốc khi bản sao đã được confirm. Publisher không được có schema registry
+    // (bản sao vẫn sai schema).
+    pub fn with_dead_letter(mut self, publisher: BatchPublisher, exchange: &str) -> Self {
+        let publisher = publisher.with_mandatory(true);
+        self.dead_letter = Some((Arc::new(tokio::sync::Mutex::new(publisher)), exchange.to_string()));
+        self
+    }
+}
+
+impl Layer for SchemaLayer {
+    fn wrap(&self, inner: BoxHandler) -> BoxHandler {
+        let registry = self.registry.clone();
+        let dead_letter_target = self.dead_letter.clone();
+        Arc::new(move |ctx| {
+            let inner = inner.clone();
+            let registry = registry.clone();
+            let dead_letter_target = dead_letter_target.clone();
+            async move {
+                let Err(violation) = registry.validate_delivery(&ctx.delivery) else {
+                    return inner(ctx).await;
+                };
+
+                let dead_lettered = match &dead_letter_target {
+                    Some((publisher, exchange)) => {
+                        // Không dead-letter được → requeue bản gốc, thử lại sau
+                        let mut publisher = publisher.lock().await;
+                        dead_letter(&mut publisher, exchange, &ctx.delivery, &violation)
+                            .await
+                            .map_err(|e| HandlerError::DeadLetterFailed(format!("'{}': {}", exchange, e)))?;
+                        true
+                    }
+                    None => false,
+                };
+                Err(HandlerError::Invalid {
+                    errors: violation.errors(),
+                    dead_lettered,
+                })
+            }
+            .boxed()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::FailurePolicy;
+    use crate::middleware::AckPolicy;
+
+    #[test]
+    fn dead_letter_copy_carries_errors_and_origin() {
+        let delivery = Delivery {
+            delivery_tag: 1,
+            exchange: ShortString::from("orders"),
+            routing_key: ShortString::from("order.created"),
+            redelivered: false,
+            properties: with_message_type(BasicProperties::default(), "order.created", 2),
+            data: br#"{"id":"x"}"#.to_vec(),
+            acker: Default::default(),
+        };
+        let violation = SchemaViolation::Invalid {
+            message_type: "order.created".to_string(),
+            version: 2,
+            errors: vec!["/id: \"x\" is not of type \"integer\"".to_string()],
+        };
+
+        let message = dead_letter_message("validation_dlx", &delivery, &violation);
+        assert_eq!((message.exchange.as_str(), message.routing_key.as_str()), ("validation_dlx", "order.created"));
+        assert_eq!(message.payload, delivery.data);
+        let headers = message.properties.headers().clone().unwrap();
+        let header = |name: &str| amqp_to_json(headers.inner().get(name).unwrap());
+        assert_eq!(header(ERRORS_HEADER), serde_json::json!(violation.errors()));
+        assert_eq!(header(ORIGINAL_EXCHANGE_HEADER), "orders");
+        assert_eq!(header(ORIGINAL_ROUTING_KEY_HEADER), "order.created");
+        assert_eq!(message_version(&message.properties), Some(2));
+    }
+
+    fn registry() -> SchemaRegistry {
+        SchemaRegistry::load_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/schemas")).unwrap()
+    }
+
+    fn message(message_type: &str, version: u32) -> BasicProperties {
+        with_message_type(BasicProperties::default(), message_type, version)
+    }
+
+    #[test]
+    fn load_dir_reads_every_version() {
+        let registry = registry();
+        assert_eq!(registry.schemas().collect::<Vec<_>>(), [("message", 1), ("message", 2), ("message", 3)]);
+        assert_eq!(registry.latest_version("message"), Some(3));
+        assert_eq!(registry.latest_version("order.created"), None);
+    }
+
+    #[test]
+    fn valid_payload_passes() {
+        let registry = registry();
+        assert_eq!(registry.validate_message(&message("message", 1), br#"{"id": 1, "content": "hi"}"#), Ok(()));
+        // Không có header version → v1
+        let untagged = BasicProperties::default().with_type(ShortString::from("message"));
+        assert_eq!(registry.validate_message(&untagged, br#"{"id": 1, "content": "hi"}"#), Ok(()));
+    }
+
+    #[test]
+    fn invalid_payload_lists_every_error() {
+        let registry = registry();
+        let violation = registry
+            .validate_message(&message("message", 2), br#"{"id": -1, "content": "hi", "priority": 12}"#)
+            .unwrap_err();
+        let SchemaViolation::Invalid { message_type, version, errors } = &violation else {
+            panic!("expected Invalid, got {:?}", violation);
+        };
+        assert_eq!((message_type.as_str(), *version), ("message", 2));
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|e| e.starts_with("/id: ")));
+        assert!(errors.iter().any(|e| e.starts_with("/priority: ")));
+        assert_eq!(violation.errors(), *errors);
+
+        assert!(matches!(
+            registry.validate_message(&message("message", 1), b"not json"),
+            Err(SchemaViolation::NotJson(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_type_depends_on_strict_mode() {
+        let payload = br#"{"anything": true}"#;
+        let lenient = registry();
+        assert_eq!(lenient.validate_message(&message("order.created", 1), payload), Ok(()));
+        assert_eq!(lenient.validate_message(&message("message", 9), payload), Ok(()));
+        assert_eq!(lenient.validate_message(&BasicProperties::default(), payload), Ok(()));
+
+        let strict = registry().with_strict(true);
+        assert_eq!(
+            strict.validate_message(&message("order.created", 1), payload),
+            Err(SchemaViolation::UnknownSchema {
+                message_type: "order.created".to_string(),
+                version: 1
+            })
+        );
+        assert_eq!(
+            strict.validate_message(&BasicProperties::default(), payload),
+            Err(SchemaViolation::MissingType)
+        );
+    }
+
+    #[test]
+    fn unconfirmed_dead_letter_requeues_even_when_redelivered() {
+        let policy = AckPolicy::default();
+        let failed = Err(HandlerError::DeadLetterFailed("not confirmed".to_string()));
+        assert_eq!(policy.decide(&failed, true), Some(FailurePolicy::Requeue));
+
+        let dead_lettered = Err(HandlerError::Invalid {
+            errors: vec![],
+            dead_lettered: true,
+        });
+        assert_eq!(policy.decide(&dead_lettered, false), None);
+    }
+}
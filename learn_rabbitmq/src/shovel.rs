@@ -0,0 +1,454 @@
+// Shovel: chuyển message từ queue nguồn (connection A) sang exchange đích (connection B) - dùng khi
+// chuyển vhost (vd bỏ vhost `/sos` cứng trong config) hoặc chuyển cluster mà không cần plugin rabbitmq_shovel.
+//
+//   source broker/vhost                            destination broker/vhost
+//   queue "orders" ──basic_consume(prefetch)──▶ shovel ──publish(confirm, mandatory)──▶ exchange "orders"
+//                  ◀──────── ack SAU KHI đích confirm ────────┘
+//
+// Ack-after-confirm: message chỉ rời queue nguồn khi broker đích đã nhận → shovel chết / mất kết nối ở bất kỳ
+// đâu thì message chưa ack vẫn nằm trong queue nguồn → chạy lại là tiếp tục đúng chỗ (resumable).
+// Đổi lại là at-least-once: đích đã confirm nhưng ack nguồn chưa tới → message được chuyển 2 lần.
+// message_id giữ nguyên → bên nhận dedup được (src/dedup.rs).
+//
+// Kết quả publish ở đích → xử lý message nguồn:
+//   Acked → ack    Nacked / Failed / Invalid → nack + requeue (thử lại)
+//   Returned (không queue nào ở đích nhận) → `unroutable` policy, mặc định reject → DLX của queue nguồn
+//
+// Routing key: giữ nguyên, hoặc cố định (with_routing_key), hoặc đổi theo regex (with_rewrite, rule đầu tiên khớp).
+// Header thêm vào: x-shovel-source (queue nguồn), x-shovel-routing-key (routing key gốc nếu bị đổi).
+//
+// ⚠️  Thứ tự: giữ nguyên trong 1 batch; message bị requeue sẽ đến sau các message đi sau nó.
+
+use crate::batch::{BatchPublisher, OutgoingMessage, PublishOutcome};
+use crate::config::{RabbitMQConfig, Secret};
+use crate::event_bus::FailurePolicy;
+use crate::tls::{self, TlsOptions};
+use futures::{FutureExt, StreamExt};
+use lapin::{
+    message::Delivery,
+    options::*,
+    protocol::AMQPErrorKind,
+    types::{AMQPValue, FieldTable, LongString},
+    Connection, ConnectionProperties, ExchangeKind, Result as LapinResult,
+};
+use regex::Regex;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const SOURCE_HEADER: &str = "x-shovel-source";
+pub const ORIGINAL_ROUTING_KEY_HEADER: &str = "x-shovel-routing-key";
+
+// 1 đầu của shovel: URL (có credentials) + TLS
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    url: Secret,
+    tls: TlsOptions,
+    label: String,
+}
+
+impl Endpoint {
+    pub fn from_config(config: &RabbitMQConfig) -> LapinResult<Self> {
+        Ok(Endpoint {
+            url: config.connection_url()?,
+            tls: config.tls.clone(),
+            label: config.redacted_url(),
+        })
+    }
+
+    // URL đã redact, dùng để log
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub async fn connect(&self) -> LapinResult<Connection> {
+        tls::connect(self.url.expose(), ConnectionProperties::default(), &self.tls).await
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShovelStats {
+    pub shovelled: u64,
+    pub unroutable: u64,
+    // Nack/lỗi ở đích → trả về queue nguồn
+    pub requeued: u64,
+    pub reconnects: u64,
+}
+
+// Vì sao 1 phiên chạy kết thúc
+enum SessionEnd {
+    Idle,
+    Limit,
+    Cancelled,
+}
+
+pub struct Shovel {
+    source: Endpoint,
+    queue: String,
+    destination: Endpoint,
+    exchange: String,
+    routing_key: Option<String>,
+    rewrites: Vec<(Regex, String)>,
+    prefetch: u16,
+    batch_size: usize,
+    unroutable: FailurePolicy,
+    idle_exit: Option<Duration>,
+    max_messages: Option<u64>,
+    reconnect_delay: Duration,
+}
+
+impl Shovel {
+    // exchange "" = default exchange ở đích (routing key = tên queue đích)
+    pub fn new(source: Endpoint, queue: &str, destination: Endpoint, exchange: &str) -> Self {
+        Shovel {
+            source,
+            queue: queue.to_string(),
+            destination,
+            exchange: exchange.to_string(),
+            routing_key: None,
+            rewrites: Vec::new(),
+            prefetch: 100,
+            batch_size: 50,
+            unroutable: FailurePolicy::Reject,
+            idle_exit: None,
+            max_messages: None,
+            reconnect_delay: Duration::from_secs(5),
+        }
+    }
+
+    // Mọi message dùng routing key này (bỏ qua rewrite)
+    pub fn with_routing_key(mut self, routing_key: &str) -> Self {
+        self.routing_key = Some(routing_key.to_string());
+        self
+    }
+
+    // `pattern` khớp routing key gốc → thay bằng `replacement` ($1, ${name}...). vd "^order\.(.*)$" → "orders.$1"
+    pub fn with_rewrite(mut self, pattern: &str, replacement: &str) -> Result<Self, regex::Error> {
+        self.rewrites.push((Regex::new(pattern)?, replacement.to_string()));
+        Ok(self)
+    }
+
+    // prefetch ở nguồn và số message publish + chờ confirm mỗi lần (batch ≤ prefetch)
+    pub fn with_prefetch(mut self, prefetch: u16, batch_size: usize) -> Self {
+        self.prefetch = prefetch.max(1);
+        self.batch_size = batch_size.clamp(1, usize::from(self.prefetch));
+        self
+    }
+
+    // Message không route được ở đích: Reject (DLX nguồn, mặc định) / Requeue / Ignore (ack, bỏ)
+    pub fn with_unroutable_policy(mut self, policy: FailurePolicy) -> Self {
+        self.unroutable = policy;
+        self
+    }
+
+    // Dừng khi queue nguồn không có message mới trong `idle` (chuyển xong 1 queue rồi thoát)
+    pub fn with_idle_exit(mut self, idle: Duration) -> Self {
+        self.idle_exit = Some(idle);
+        self
+    }
+
+    // Dừng sau khi chuyển đủ n message
+    pub fn with_max_messages(mut self, max: u64) -> Self {
+        self.max_messages = Some(max);
+        self
+    }
+
+    pub fn with_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    pub fn routing_key_for(&self, original: &str) -> String {
+        if let Some(routing_key) = &self.routing_key {
+            return routing_key.clone();
+        }
+        self.rewrites
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(original))
+            .map(|(pattern, replacement)| pattern.replace(original, replacement.as_str()).into_owned())
+            .unwrap_or_else(|| original.to_string())
+    }
+
+    pub fn outgoing(&self, delivery: &Delivery) -> OutgoingMessage {
+        let original = delivery.routing_key.as_str();
+        let routing_key = self.routing_key_for(original);
+        let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+        headers.insert(
+            SOURCE_HEADER.into(),
+            AMQPValue::LongString(LongString::from(self.queue.clone())),
+        );
+        if routing_key != original {
+            headers.insert(
+                ORIGINAL_ROUTING_KEY_HEADER.into(),
+                AMQPValue::LongString(LongString::from(original.to_string())),
+            );
+        }
+        OutgoingMessage::new(&self.exchange, &routing_key, delivery.data.clone())
+            .with_properties(delivery.properties.clone().with_headers(headers))
+    }
+
+    // Chạy tới khi idle_exit / max_messages / consumer bị broker cancel (queue nguồn bị xóa).
+    // Mất kết nối 1 trong 2 đầu → chờ reconnect_delay rồi chạy lại từ message chưa ack.
+    // Queue / exchange không tồn tại, không có quyền → dừng luôn.
+    pub async fn run(&self) -> LapinResult<ShovelStats> {
+        let mut stats = ShovelStats::default();
+        loop {
+            match self.run_session(&mut stats).await {
+                Ok(end) => {
+                    match end {
+                        SessionEnd::Idle => println!("ℹ️  '{}' idle, stopping", self.queue),
+                        SessionEnd::Limit => println!("ℹ️  Reached {} message(s), stopping", stats.shovelled),
+                        SessionEnd::Cancelled => println!("⚠️  Consumer on '{}' was cancelled", self.queue),
+                    }
+                    return Ok(stats);
+                }
+                // Soft error (channel): queue / exchange không tồn tại, không có quyền... → chạy lại cũng vậy.
+                // Hard error (connection, vd CONNECTION_FORCED khi broker restart) → reconnect
+                Err(lapin::Error::ProtocolError(e)) if matches!(e.kind(), AMQPErrorKind::Soft(_)) => {
+                    return Err(lapin::Error::ProtocolError(e));
+                }
+                Err(e) => {
+                    stats.reconnects += 1;
+                    println!(
+                        "⚠️  Shovel connection lost ({}), reconnecting in {:?}...",
+                        e, self.reconnect_delay
+                    );
+                    tokio::time::sleep(self.reconnect_delay).await;
+                }
+            }
+        }
+    }
+
+    async fn run_session(&self, stats: &mut ShovelStats) -> LapinResult<SessionEnd> {
+        let source = self.source.connect().await?;
+        let destination = match self.destination.connect().await {
+            Ok(destination) => destination,
+            Err(e) => {
+                let _ = source.close(200, "OK").await;
+                return Err(e);
+            }
+        };
+        // Đóng cả 2 connection kể cả khi lỗi: message đã prefetch nhưng chưa chuyển → về lại queue nguồn
+        let result = self.transfer(&source, &destination, stats).await;
+        let _ = source.close(200, "OK").await;
+        let _ = destination.close(200, "OK").await;
+        result
+    }
+
+    async fn transfer(&self, source: &Connection, destination: &Connection, stats: &mut ShovelStats) -> LapinResult<SessionEnd> {
+        let channel = source.create_channel().await?;
+        channel.basic_qos(self.prefetch, BasicQosOptions::default()).await?;
+        let queue = channel
+            .queue_declare(
+                &self.queue,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        let mut publisher = BatchPublisher::new(destination.create_channel().await?)
+            .await?
+            .with_mandatory(true);
+        // Exchange đích phải có sẵn (passive) - shovel không tự tạo topology
+        if !self.exchange.is_empty() {
+            publisher
+                .channel()
+                .exchange_declare(
+                    &self.exchange,
+                    ExchangeKind::Direct,
+                    ExchangeDeclareOptions {
+                        passive: true,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+        }
+
+        println!(
+            "🚚 Shovel {} '{}' ({} waiting) → {} '{}'",
+            self.source.label(),
+            self.queue,
+            queue.message_count(),
+            self.destination.label(),
+            self.exchange
+        );
+
+        let mut consumer = channel
+            .basic_consume(
+                &self.queue,
+                "shovel",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        let end = loop {
+            let remaining = self
+                .max_messages
+                .map(|max| max.saturating_sub(stats.shovelled) as usize);
+            if remaining == Some(0) {
+                break SessionEnd::Limit;
+            }
+
+            let next = match self.idle_exit {
+                Some(idle) => match tokio::time::timeout(idle, consumer.next()).await {
+                    Ok(next) => next,
+                    Err(_) => break SessionEnd::Idle,
+                },
+                None => consumer.next().await,
+            };
+            let Some(first) = next else {
+                break SessionEnd::Cancelled;
+            };
+
+            // Gom thêm message đã prefetch sẵn, không chờ
+            let limit = remaining.map_or(self.batch_size, |r| r.min(self.batch_size));
+            let mut batch = vec![first?];
+            while batch.len() < limit {
+                match consumer.next().now_or_never() {
+                    Some(Some(delivery)) => batch.push(delivery?),
+                    _ => break,
+                }
+            }
+
+            // Collect trước: closure giữ qua await làm future không Send → không tokio::spawn được run()
+            let outgoing: Vec<OutgoingMessage> = batch.iter().map(|delivery| self.outgoing(delivery)).collect();
+            let report = publisher.publish_batch(outgoing).await;
+            let failed = report.failed();
+            for (delivery, result) in batch.iter().zip(report.results) {
+                self.settle(delivery, &result.outcome, stats).await?;
+            }
+            if failed > 0 && !publisher.channel().status().connected() {
+                return Err(lapin::Error::IOError(Arc::new(std::io::Error::other(
+                    "destination channel closed",
+                ))));
+            }
+        };
+        Ok(end)
+    }
+
+    async fn settle(&self, delivery: &Delivery, outcome: &PublishOutcome, stats: &mut ShovelStats) -> LapinResult<()> {
+        let policy = match outcome {
+            PublishOutcome::Acked => {
+                stats.shovelled += 1;
+                return delivery.ack(BasicAckOptions::default()).await;
+            }
+            PublishOutcome::Returned { reply_text, .. } => {
+                stats.unroutable += 1;
+                println!(
+                    "⚠️  Unroutable at destination ({}): routing key '{}'",
+                    reply_text,
+                    self.routing_key_for(delivery.routing_key.as_str())
+                );
+                self.unroutable
+            }
+            // Invalid: không có schema registry → không xảy ra, nhưng đừng để mất message
+            PublishOutcome::Nacked | PublishOutcome::Failed(_) | PublishOutcome::Invalid(_) => {
+                stats.requeued += 1;
+                FailurePolicy::Requeue
+            }
+        };
+        match policy {
+            FailurePolicy::Ignore => delivery.ack(BasicAckOptions::default()).await,
+            FailurePolicy::Reject | FailurePolicy::Requeue => {
+                delivery
+                    .nack(BasicNackOptions {
+                        requeue: policy == FailurePolicy::Requeue,
+                        ..Default::default()
+                    })
+                    .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::BasicProperties;
+
+    fn shovel() -> Shovel {
+        let endpoint = Endpoint::from_config(&RabbitMQConfig {
+            url: "amqp://localhost:5672/%2f".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        Shovel::new(endpoint.clone(), "orders", endpoint, "orders.v2")
+    }
+
+    fn delivery(routing_key: &str, properties: BasicProperties) -> Delivery {
+        Delivery {
+            delivery_tag: 1,
+            exchange: "orders".into(),
+            routing_key: routing_key.into(),
+            redelivered: false,
+            properties,
+            data: b"{}".to_vec(),
+            acker: Default::default(),
+        }
+    }
+
+    fn header(message: &OutgoingMessage, name: &str) -> Option<String> {
+        match message.properties.headers().as_ref()?.inner().get(name)? {
+            AMQPValue::LongString(value) => Some(value.to_string()),
+            other => panic!("{}: {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn routing_key_is_kept_when_nothing_matches() {
+        let rewritten = shovel().with_rewrite(r"^order\.(.*)$", "orders.$1").unwrap();
+        assert_eq!(rewritten.routing_key_for("payment.success"), "payment.success");
+        assert_eq!(shovel().routing_key_for("order.created"), "order.created");
+    }
+
+    #[test]
+    fn first_matching_rewrite_wins_and_expands_groups() {
+        let rewritten = shovel()
+            .with_rewrite(r"^order\.(?<event>.*)$", "orders.${event}")
+            .unwrap()
+            .with_rewrite(r"^order\.created$", "never")
+            .unwrap()
+            .with_rewrite(r"^(\w+)\.(\w+)$", "$2.$1")
+            .unwrap();
+        assert_eq!(rewritten.routing_key_for("order.created"), "orders.created");
+        assert_eq!(rewritten.routing_key_for("payment.success"), "success.payment");
+        assert!(shovel().with_rewrite("(", "x").is_err());
+    }
+
+    #[test]
+    fn fixed_routing_key_overrides_rewrites() {
+        let shovel = shovel()
+            .with_rewrite(r"^order\.(.*)$", "orders.$1")
+            .unwrap()
+            .with_routing_key("archive");
+        assert_eq!(shovel.routing_key_for("order.created"), "archive");
+    }
+
+    #[test]
+    fn outgoing_adds_shovel_headers_and_keeps_properties() {
+        let mut headers = FieldTable::default();
+        headers.insert("x-tenant".into(), AMQPValue::LongString("acme".into()));
+        let properties = BasicProperties::default()
+            .with_message_id("m-1".into())
+            .with_headers(headers);
+
+        let renamed = shovel().with_rewrite(r"^order\.(.*)$", "orders.$1").unwrap();
+        let message = renamed.outgoing(&delivery("order.created", properties.clone()));
+        assert_eq!((message.exchange.as_str(), message.routing_key.as_str()), ("orders.v2", "orders.created"));
+        assert_eq!(message.payload, b"{}");
+        assert_eq!(message.properties.message_id().as_ref().unwrap().as_str(), "m-1");
+        assert_eq!(header(&message, SOURCE_HEADER).as_deref(), Some("orders"));
+        assert_eq!(header(&message, ORIGINAL_ROUTING_KEY_HEADER).as_deref(), Some("order.created"));
+        assert_eq!(header(&message, "x-tenant").as_deref(), Some("acme"));
+
+        // Routing key không đổi → không có x-shovel-routing-key
+        let message = shovel().outgoing(&delivery("order.created", BasicProperties::default()));
+        assert_eq!(message.routing_key, "order.created");
+        assert_eq!(header(&message, SOURCE_HEADER).as_deref(), Some("orders"));
+        assert_eq!(header(&message, ORIGINAL_ROUTING_KEY_HEADER), None);
+    }
+}
@@ -0,0 +1,358 @@
+// tail: xem LIVE traffic trên 1 exchange bất kỳ
+// Tạo queue tạm (exclusive, auto-delete, no_ack) → KHÔNG đụng tới queue thật, không ack hộ ai.
+// Lọc theo: binding pattern (phía broker), regex routing key, field filter kiểu jq trên JSON body.
+
+use crate::record::{bind_temporary_queue, RecordedMessage};
+use futures::StreamExt;
+use lapin::{options::BasicConsumeOptions, types::FieldTable, Channel, Result as LapinResult};
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum FilterOp {
+    // `.field` - field tồn tại và không phải null/false
+    Exists,
+    Eq(Value),
+    Ne(Value),
+    Gt(f64),
+    Ge(f64),
+    Lt(f64),
+    Le(f64),
+    // `.field ~ regex` - so khớp string
+    Matches(Regex),
+}
+
+// Filter trên JSON body, vd: `.id > 100`, `.user.name == "alice"`, `.items[0].sku ~ ^A`
+#[derive(Debug, Clone)]
+pub struct FieldFilter {
+    pub path: Vec<PathSegment>,
+    pub op: FilterOp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterParseError(pub String);
+
+impl fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid filter: {}", self.0)
+    }
+}
+
+impl std::error::Error for FilterParseError {}
+
+pub fn parse_path(path: &str) -> Result<Vec<PathSegment>, FilterParseError> {
+    let path = path.trim();
+    let rest = path
+        .strip_prefix('.')
+        .ok_or_else(|| FilterParseError(format!("path '{}' must start with '.'", path)))?;
+
+    let mut segments = Vec::new();
+    for part in rest.split('.').filter(|p| !p.is_empty()) {
+        // "items[0][1]" → Key("items"), Index(0), Index(1)
+        let (key, mut indexes) = match part.find('[') {
+            Some(i) => (&part[..i], &part[i..]),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key.to_string()));
+        }
+        while let Some(inner) = indexes.strip_prefix('[') {
+            let end = inner
+                .find(']')
+                .ok_or_else(|| FilterParseError(format!("unclosed '[' in '{}'", path)))?;
+            let index = inner[..end]
+                .parse()
+                .map_err(|_| FilterParseError(format!("bad index '{}' in '{}'", &inner[..end], path)))?;
+            segments.push(PathSegment::Index(index));
+            indexes = &inner[end + 1..];
+        }
+        if !indexes.is_empty() {
+            return Err(FilterParseError(format!("unexpected '{}' in '{}'", indexes, path)));
+        }
+    }
+    Ok(segments)
+}
+
+pub fn select<'a>(value: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |current, segment| match segment {
+        PathSegment::Key(key) => current.get(key),
+        PathSegment::Index(index) => current.get(index),
+    })
+}
+
+impl FromStr for FieldFilter {
+    type Err = FilterParseError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        // Toán tử 2 ký tự phải được thử trước
+        const OPERATORS: [&str; 7] = ["==", "!=", ">=", "<=", ">", "<", "~"];
+
+        let found = OPERATORS
+            .iter()
+            .filter_map(|op| expr.find(op).map(|at| (at, *op)))
+            .min_by_key(|(at, op)| (*at, std::cmp::Reverse(op.len())));
+
+        let Some((at, op)) = found else {
+            return Ok(FieldFilter {
+                path: parse_path(expr)?,
+                op: FilterOp::Exists,
+            });
+        };
+
+        let path = parse_path(&expr[..at])?;
+        let raw = expr[at + op.len()..].trim();
+        // Giá trị là JSON (số, true, "string"...) hoặc string trần
+        let value = serde_json::from_str::<Value>(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+        let number = || {
+            value
+                .as_f64()
+                .ok_or_else(|| FilterParseError(format!("'{}' needs a number, got '{}'", op, raw)))
+        };
+
+        let op = match op {
+            "==" => FilterOp::Eq(value.clone()),
+            "!=" => FilterOp::Ne(value.clone()),
+            ">" => FilterOp::Gt(number()?),
+            ">=" => FilterOp::Ge(number()?),
+            "<" => FilterOp::Lt(number()?),
+            "<=" => FilterOp::Le(number()?),
+            _ => FilterOp::Matches(
+                Regex::new(value.as_str().unwrap_or(raw)).map_err(|e| FilterParseError(e.to_string()))?,
+            ),
+        };
+
+        Ok(FieldFilter { path, op })
+    }
+}
+
+impl FieldFilter {
+    pub fn matches(&self, body: &Value) -> bool {
+        let selected = select(body, &self.path);
+        let number = || selected.and_then(Value::as_f64);
+
+        match &self.op {
+            FilterOp::Exists => !matches!(selected, None | Some(Value::Null) | Some(Value::Bool(false))),
+            FilterOp::Eq(expected) => selected.is_some_and(|v| loose_eq(v, expected)),
+            FilterOp::Ne(expected) => !selected.is_some_and(|v| loose_eq(v, expected)),
+            FilterOp::Gt(n) => number().is_some_and(|v| v > *n),
+            FilterOp::Ge(n) => number().is_some_and(|v| v >= *n),
+            FilterOp::Lt(n) => number().is_some_and(|v| v < *n),
+            FilterOp::Le(n) => number().is_some_and(|v| v <= *n),
+            FilterOp::Matches(regex) => match selected {
+                Some(Value::String(s)) => regex.is_match(s),
+                Some(Value::Null) | None => false,
+                Some(other) => regex.is_match(&other.to_string()),
+            },
+        }
+    }
+}
+
+// 100 == 100.0, và so sánh số với string số ("42" == 42) cho tiện gõ trên CLI
+fn loose_eq(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::String(a), Value::Number(b)) | (Value::Number(b), Value::String(a)) => {
+            a.parse::<f64>().ok() == b.as_f64()
+        }
+        _ => actual == expected,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TailOptions {
+    pub exchange: String,
+    // Binding patterns cho queue tạm (vd: "order.#"), không được rỗng. Fanout bỏ qua pattern.
+    pub patterns: Vec<String>,
+    // Lọc phía client theo routing key
+    pub routing_key: Option<Regex>,
+    // Tất cả filters phải khớp (AND). Body không phải JSON → không khớp nếu có filter.
+    pub filters: Vec<FieldFilter>,
+    pub show_properties: bool,
+    // In body JSON trên 1 dòng
+    pub compact: bool,
+    pub max_messages: Option<usize>,
+}
+
+impl TailOptions {
+    pub fn accepts(&self, message: &RecordedMessage) -> bool {
+        if let Some(regex) = &self.routing_key
+            && !regex.is_match(&message.routing_key)
+        {
+            return false;
+        }
+        if self.filters.is_empty() {
+            return true;
+        }
+        match message.body_json() {
+            Some(body) => self.filters.iter().all(|f| f.matches(&body)),
+            None => false,
+        }
+    }
+}
+
+pub fn format_message(message: &RecordedMessage, options: &TailOptions) -> String {
+    let mut out = format!(
+        "── {} [{}] {}{}\n",
+        format_time(message.recorded_at_ms),
+        message.exchange,
+        message.routing_key,
+        if message.redelivered { " (redelivered)" } else { "" },
+    );
+
+    if options.show_properties {
+        if let Value::Object(props) = serde_json::to_value(&message.properties).unwrap_or_default() {
+            for (key, value) in props {
+                out.push_str(&format!("   {:<16} {}\n", key, value));
+            }
+        }
+        for (key, value) in &message.headers {
+            out.push_str(&format!("   header {:<9} {}\n", key, value));
+        }
+    }
+
+    let body = match message.body_json() {
+        Some(json) if options.compact => json.to_string(),
+        Some(json) => serde_json::to_string_pretty(&json).unwrap_or_default(),
+        None => message.body.clone(),
+    };
+    for line in body.lines() {
+        out.push_str("   ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+// HH:MM:SS.mmm (UTC)
+fn format_time(ms: i64) -> String {
+    let day_ms = ms.rem_euclid(86_400_000);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        day_ms / 3_600_000,
+        day_ms / 60_000 % 60,
+        day_ms / 1000 % 60,
+        day_ms % 1000
+    )
+}
+
+// Chạy tới khi Ctrl+C hoặc đủ `max_messages`. Trả về (số nhận được, số đã hiển thị).
+// `options.patterns` phải có ít nhất 1 pattern: "#" chỉ khớp mọi thứ trên topic exchange,
+// xem record::default_binding_keys để chọn mặc định theo type của exchange
+pub async fn tail(channel: &Channel, options: &TailOptions) -> LapinResult<(usize, usize)> {
+    if options.patterns.is_empty() {
+        return Err(crate::tls::invalid_config(format!(
+            "no binding pattern for exchange '{}'",
+            options.exchange
+        )));
+    }
+    let queue_name = bind_temporary_queue(channel, &options.exchange, &options.patterns).await?;
+
+    let mut consumer = channel
+        .basic_consume(
+            &queue_name,
+            "tail",
+            BasicConsumeOptions {
+                no_ack: true,  // Queue tạm của riêng tail - không cần ack
+                exclusive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    let (mut received, mut shown) = (0, 0);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    while options.max_messages.is_none_or(|max| shown < max) {
+        let delivery = tokio::select! {
+            _ = &mut ctrl_c => break,
+            next = consumer.next() => match next {
+                Some(delivery) => delivery?,
+                None => break,
+            },
+        };
+        received += 1;
+
+        let message = RecordedMessage::from_delivery(&delivery);
+        if options.accepts(&message) {
+            shown += 1;
+            print!("{}", format_message(&message, options));
+        }
+    }
+
+    Ok((received, shown))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filter(expr: &str) -> FieldFilter {
+        expr.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_paths_with_indexes() {
+        assert_eq!(
+            parse_path(".items[0][1].sku").unwrap(),
+            [
+                PathSegment::Key("items".to_string()),
+                PathSegment::Index(0),
+                PathSegment::Index(1),
+                PathSegment::Key("sku".to_string()),
+            ]
+        );
+        assert_eq!(parse_path(".").unwrap(), []);
+        assert!(parse_path("id").is_err());
+        assert!(parse_path(".items[x]").is_err());
+        assert!(parse_path(".items[0").is_err());
+        assert!(parse_path(".items[0]x").is_err());
+    }
+
+    #[test]
+    fn parses_operators() {
+        assert!(matches!(filter(".id").op, FilterOp::Exists));
+        assert!(matches!(filter(".id >= 300").op, FilterOp::Ge(n) if n == 300.0));
+        assert!(matches!(filter(".id > 300").op, FilterOp::Gt(n) if n == 300.0));
+        assert!(matches!(filter(".id <= 1").op, FilterOp::Le(_)));
+        assert!(matches!(filter(".id != 1").op, FilterOp::Ne(_)));
+        assert!(matches!(filter(r#".user.name == "alice""#).op, FilterOp::Eq(Value::String(ref s)) if s == "alice"));
+        assert!(matches!(filter(".content ~ ^Payment").op, FilterOp::Matches(_)));
+        assert_eq!(filter(".user.name == alice").path.len(), 2);
+
+        assert!(".id > abc".parse::<FieldFilter>().is_err());
+        assert!(".content ~ (".parse::<FieldFilter>().is_err());
+        assert!("id == 1".parse::<FieldFilter>().is_err());
+    }
+
+    #[test]
+    fn filters_match_json_bodies() {
+        let body = json!({
+            "id": 300,
+            "content": "Payment received",
+            "user": {"name": "alice"},
+            "items": [{"sku": "A-1"}],
+            "flag": false,
+        });
+        assert!(filter(".id >= 300").matches(&body));
+        assert!(!filter(".id > 300").matches(&body));
+        assert!(filter(".id == 300.0").matches(&body));
+        assert!(filter(r#".id == "300""#).matches(&body));
+        assert!(filter(".user.name == alice").matches(&body));
+        assert!(filter(".items[0].sku ~ ^A").matches(&body));
+        assert!(filter(".content ~ ^Payment").matches(&body));
+        assert!(!filter(".flag").matches(&body));
+        assert!(!filter(".missing").matches(&body));
+        assert!(filter(".missing != 1").matches(&body));
+    }
+}
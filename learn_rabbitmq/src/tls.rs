@@ -0,0 +1,431 @@
+// TLS (amqps://) cho connection
+// - CA bundle riêng (vd: CA tự ký của cluster nội bộ)
+// - Client cert/key cho mutual TLS, kết hợp SASL EXTERNAL (đăng nhập bằng cert, không cần password)
+// - Bật/tắt hostname verification, override server name (SNI)
+// Kèm theo `run_tls_proxy`: stand-in terminate TLS trước 1 broker plain-text để thử nghiệm local.
+
+use lapin::{
+    auth::SASLMechanism,
+    tcp::{RustlsConnector, TcpStream},
+    uri::{AMQPScheme, AMQPUri},
+    Connection, ConnectionProperties, Error as LapinError, Result as LapinResult,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    client::WebPkiServerVerifier,
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    server::WebPkiClientVerifier,
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream as TokioTcpStream};
+use tokio_rustls::TlsAcceptor;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsOptions {
+    // PEM bundle các CA tin cậy. None = dùng CA của hệ điều hành
+    pub ca_file: Option<PathBuf>,
+    // PEM client certificate (chain) + private key cho mutual TLS
+    pub client_cert_file: Option<PathBuf>,
+    pub client_key_file: Option<PathBuf>,
+    // Đăng nhập bằng SASL EXTERNAL (username lấy từ client cert)
+    pub external_auth: bool,
+    // false = chấp nhận cert hợp lệ (đúng CA) nhưng không khớp hostname
+    pub verify_hostname: bool,
+    // Tên dùng cho SNI & hostname verification thay cho host trong URL
+    pub server_name: Option<String>,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        TlsOptions {
+            ca_file: None,
+            client_cert_file: None,
+            client_key_file: None,
+            external_auth: false,
+            verify_hostname: true,
+            server_name: None,
+        }
+    }
+}
+
+// Kết nối tới `url` (amqp:// hoặc amqps://) với các TLS options
+pub async fn connect(
+    url: &str,
+    properties: ConnectionProperties,
+    options: &TlsOptions,
+) -> LapinResult<Connection> {
+    let mut uri: AMQPUri = url.parse().map_err(invalid_config)?;
+
+    if options.external_auth {
+        if options.client_cert_file.is_none() {
+            return Err(invalid_config("SASL EXTERNAL requires a client certificate"));
+        }
+        uri.query.auth_mechanism = Some(SASLMechanism::External);
+    }
+
+    if uri.scheme != AMQPScheme::AMQPS {
+        if options.client_cert_file.is_some() || options.ca_file.is_some() {
+            return Err(invalid_config("TLS options require an amqps:// URL"));
+        }
+        return Connection::connect_uri(uri, properties).await;
+    }
+
+    let connector = RustlsConnector::from(client_config(options)?);
+    let server_name = options
+        .server_name
+        .clone()
+        .unwrap_or_else(|| uri.authority.host.clone());
+
+    // HandshakeError lớn nhưng là kiểu lapin quy định cho connector
+    #[allow(clippy::result_large_err)]
+    let connect_tls = move |uri: &AMQPUri| {
+        let address = format!("{}:{}", uri.authority.host, uri.authority.port);
+        let stream = TcpStream::connect(address)?.into_rustls(&connector, &server_name)?;
+        stream.set_nonblocking(true)?;
+        Ok(stream)
+    };
+    Connection::connector(uri, Box::new(connect_tls), properties).await
+}
+
+pub fn client_config(options: &TlsOptions) -> LapinResult<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let roots = Arc::new(root_store(options.ca_file.as_deref())?);
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_config)?;
+
+    let builder = if options.verify_hostname {
+        builder.with_root_certificates(roots)
+    } else {
+        let inner = WebPkiServerVerifier::builder_with_provider(roots, provider)
+            .build()
+            .map_err(invalid_config)?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipHostnameVerification { inner }))
+    };
+
+    match (&options.client_cert_file, &options.client_key_file) {
+        (Some(cert_file), Some(key_file)) => builder
+            .with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)
+            .map_err(invalid_config),
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(invalid_config(
+            "client certificate and key must be configured together",
+        )),
+    }
+}
+
+fn root_store(ca_file: Option<&Path>) -> LapinResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(ca_file) => {
+            for cert in load_certs(ca_file)? {
+                roots.add(cert).map_err(invalid_config)?;
+            }
+        }
+        None => {
+            let native = rustls_native_certs::load_native_certs();
+            roots.add_parsable_certificates(native.certs);
+        }
+    }
+    Ok(roots)
+}
+
+pub fn load_certs(path: &Path) -> LapinResult<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_config(format!("{}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(invalid_config(format!("{}: no certificates found", path.display())));
+    }
+    Ok(certs)
+}
+
+pub fn load_key(path: &Path) -> LapinResult<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| invalid_config(format!("{}: {}", path.display(), e)))
+}
+
+pub(crate) fn invalid_config(error: impl ToString) -> LapinError {
+    LapinError::IOError(Arc::new(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        error.to_string(),
+    )))
+}
+
+// Kiểm tra chain như bình thường nhưng bỏ qua lỗi hostname không khớp
+#[derive(Debug)]
+struct SkipHostnameVerification {
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl ServerCertVerifier for SkipHostnameVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsProxyConfig {
+    pub listen: String,
+    // Broker plain-text phía sau, vd: "localhost:5672"
+    pub upstream: String,
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    // Nếu có: bắt buộc client gửi cert ký bởi CA này (mutual TLS)
+    pub client_ca_file: Option<PathBuf>,
+}
+
+pub fn server_config(config: &TlsProxyConfig) -> LapinResult<ServerConfig> {
+    let provider: Arc<CryptoProvider> = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_config)?;
+
+    let builder = match &config.client_ca_file {
+        Some(ca_file) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(root_store(Some(ca_file))?),
+                provider,
+            )
+            .build()
+            .map_err(invalid_config)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    builder
+        .with_single_cert(load_certs(&config.cert_file)?, load_key(&config.key_file)?)
+        .map_err(invalid_config)
+}
+
+// TLS-terminating stand-in: nhận amqps:// và chuyển tiếp plain-text tới broker.
+// ⚠️  Chỉ dùng để thử nghiệm TLS local - SASL EXTERNAL vẫn cần broker thật có cấu hình TLS.
+pub async fn run_tls_proxy(config: TlsProxyConfig) -> LapinResult<()> {
+    let listener = TcpListener::bind(&config.listen)
+        .await
+        .map_err(|e| LapinError::IOError(Arc::new(e)))?;
+    println!("✓ TLS proxy listening on {} → {}", config.listen, config.upstream);
+    serve_tls_proxy(listener, config).await
+}
+
+// Như run_tls_proxy nhưng với listener có sẵn (vd cổng 0 do OS chọn). `config.listen` bị bỏ qua
+pub async fn serve_tls_proxy(listener: TcpListener, config: TlsProxyConfig) -> LapinResult<()> {
+    let acceptor = TlsAcceptor::from(Arc::new(server_config(&config)?));
+
+    loop {
+        let (client, peer) = listener
+            .accept()
+            .await
+            .map_err(|e| LapinError::IOError(Arc::new(e)))?;
+        let acceptor = acceptor.clone();
+        let upstream = config.upstream.clone();
+
+        tokio::spawn(async move {
+            let mut tls = match acceptor.accept(client).await {
+                Ok(tls) => tls,
+                Err(e) => {
+                    println!("✗ TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let mut broker = match TokioTcpStream::connect(&upstream).await {
+                Ok(broker) => broker,
+                Err(e) => {
+                    println!("✗ Cannot reach upstream {}: {}", upstream, e);
+                    return;
+                }
+            };
+
+            println!("✓ {} connected (TLS terminated)", peer);
+            let _ = tokio::io::copy_bidirectional(&mut tls, &mut broker).await;
+            println!("ℹ️  {} disconnected", peer);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    fn proxy_config(client_ca: bool) -> TlsProxyConfig {
+        TlsProxyConfig {
+            listen: "127.0.0.1:0".to_string(),
+            upstream: String::new(),
+            cert_file: fixture("server.pem"),
+            key_file: fixture("server.key"),
+            client_ca_file: client_ca.then(|| fixture("ca.pem")),
+        }
+    }
+
+    // Upstream giả: echo lại mọi byte → đi qua proxy được nghĩa là TLS đã thông
+    async fn start_proxy(client_ca: bool) -> String {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = upstream.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = socket.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = TlsProxyConfig {
+            upstream: upstream_addr,
+            ..proxy_config(client_ca)
+        };
+        tokio::spawn(serve_tls_proxy(listener, config));
+        addr
+    }
+
+    async fn ping(addr: &str, options: &TlsOptions, server_name: &str) -> io::Result<Vec<u8>> {
+        let connector = TlsConnector::from(Arc::new(client_config(options).map_err(io::Error::other)?));
+        let tcp = TokioTcpStream::connect(addr).await?;
+        let name = ServerName::try_from(server_name.to_string()).map_err(io::Error::other)?;
+        let mut tls = connector.connect(name, tcp).await?;
+        tls.write_all(b"ping").await?;
+        let mut reply = vec![0u8; 4];
+        tls.read_exact(&mut reply).await?;
+        Ok(reply)
+    }
+
+    fn trusting(ca: &str) -> TlsOptions {
+        TlsOptions {
+            ca_file: Some(fixture(ca)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn connects_when_ca_is_trusted() {
+        let addr = start_proxy(false).await;
+        assert_eq!(ping(&addr, &trusting("ca.pem"), "localhost").await.unwrap(), b"ping");
+    }
+
+    #[tokio::test]
+    async fn rejects_server_signed_by_other_ca() {
+        let addr = start_proxy(false).await;
+        assert!(ping(&addr, &trusting("other-ca.pem"), "localhost").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn hostname_verification_can_be_disabled() {
+        let addr = start_proxy(false).await;
+        assert!(ping(&addr, &trusting("ca.pem"), "broker.internal").await.is_err());
+
+        let options = TlsOptions {
+            verify_hostname: false,
+            ..trusting("ca.pem")
+        };
+        assert_eq!(ping(&addr, &options, "broker.internal").await.unwrap(), b"ping");
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_requires_client_certificate() {
+        let addr = start_proxy(true).await;
+        assert!(ping(&addr, &trusting("ca.pem"), "localhost").await.is_err());
+
+        let options = TlsOptions {
+            client_cert_file: Some(fixture("client.pem")),
+            client_key_file: Some(fixture("client.key")),
+            ..trusting("ca.pem")
+        };
+        assert_eq!(ping(&addr, &options, "localhost").await.unwrap(), b"ping");
+    }
+
+    #[test]
+    fn options_default_from_empty_json() {
+        let options: TlsOptions = serde_json::from_str("{}").unwrap();
+        assert!(options.verify_hostname);
+        assert!(options.ca_file.is_none() && !options.external_auth);
+
+        let options: TlsOptions =
+            serde_json::from_str(r#"{"ca_file": "ca.pem", "verify_hostname": false, "server_name": "rabbit"}"#).unwrap();
+        assert_eq!(options.ca_file, Some(PathBuf::from("ca.pem")));
+        assert!(!options.verify_hostname);
+        assert_eq!(options.server_name.as_deref(), Some("rabbit"));
+    }
+
+    #[test]
+    fn client_cert_without_key_is_rejected() {
+        let options = TlsOptions {
+            client_cert_file: Some(fixture("client.pem")),
+            ..trusting("ca.pem")
+        };
+        assert!(client_config(&options).is_err());
+        assert!(client_config(&trusting("missing.pem")).is_err());
+    }
+
+    #[tokio::test]
+    async fn connect_validates_options_before_dialing() {
+        let external = TlsOptions {
+            external_auth: true,
+            ..Default::default()
+        };
+        let error = connect("amqps://localhost:1", ConnectionProperties::default(), &external)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("SASL EXTERNAL"));
+
+        let error = connect("amqp://localhost:1", ConnectionProperties::default(), &trusting("ca.pem"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("amqps://"));
+    }
+}
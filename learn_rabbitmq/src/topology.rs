@@ -0,0 +1,433 @@
+// Vẽ topology (exchange → queue, có label binding key) dạng Graphviz DOT hoặc Mermaid
+// Nguồn:
+//   - definitions file: export từ Management UI / `GET /api/definitions`, hoặc viết tay
+//     (xem topology.example.json - topology của các examples trong main.rs)
+//   - management API của broker đang chạy (src/management.rs)
+// Thay cho các sơ đồ ASCII vẽ tay trong EXCHANGE_EXPLAINED.md / ROUTING_EXAMPLES.md.
+
+use crate::management::{BindingInfo, ExchangeInfo, ManagementClient, QueueInfo};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+// Cùng format với definitions export (các key khác như users, policies... bị bỏ qua)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Topology {
+    pub exchanges: Vec<ExchangeInfo>,
+    pub queues: Vec<QueueInfo>,
+    pub bindings: Vec<BindingInfo>,
+}
+
+impl Topology {
+    pub fn from_definitions_file(path: &Path) -> io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    // vhost = None → mọi vhost
+    pub async fn fetch(client: &ManagementClient, vhost: Option<&str>) -> io::Result<Self> {
+        Ok(Topology {
+            exchanges: client.exchanges(vhost).await?,
+            queues: client.queues(vhost).await?,
+            bindings: client.bindings(vhost).await?,
+        })
+    }
+
+    // Definitions export của 1 vhost không có field `vhost` → luôn giữ lại
+    pub fn retain_vhost(&mut self, vhost: &str) {
+        let keep = |v: &str| v.is_empty() || v == vhost;
+        self.exchanges.retain(|e| keep(&e.vhost));
+        self.queues.retain(|q| keep(&q.vhost));
+        self.bindings.retain(|b| keep(&b.vhost));
+    }
+
+    // Bỏ default exchange, các exchange `amq.*` có sẵn, và binding ngầm định từ default exchange
+    pub fn without_builtins(mut self) -> Self {
+        let builtin = |name: &str| name.is_empty() || name.starts_with("amq.");
+        self.exchanges.retain(|e| !builtin(&e.name));
+        self.bindings.retain(|b| {
+            let to_builtin = b.destination_type == "exchange" && builtin(&b.destination);
+            !builtin(&b.source) && !to_builtin
+        });
+        self
+    }
+
+    fn vhosts(&self) -> BTreeSet<&str> {
+        self.exchanges
+            .iter()
+            .map(|e| e.vhost.as_str())
+            .chain(self.queues.iter().map(|q| q.vhost.as_str()))
+            .chain(self.bindings.iter().map(|b| b.vhost.as_str()))
+            .collect()
+    }
+
+    // Gom nodes/edges, tự thêm node cho binding trỏ tới exchange/queue không có trong danh sách
+    fn graph(&self) -> Graph {
+        let mut graph = Graph::default();
+        for exchange in &self.exchanges {
+            graph.node(&exchange.vhost, NodeKind::Exchange, &exchange.name, &exchange.kind);
+        }
+        for queue in &self.queues {
+            graph.node(&queue.vhost, NodeKind::Queue, &queue.name, "");
+        }
+        for binding in &self.bindings {
+            let from = graph.node(&binding.vhost, NodeKind::Exchange, &binding.source, "");
+            let to_kind = match binding.destination_type.as_str() {
+                "exchange" => NodeKind::Exchange,
+                _ => NodeKind::Queue,
+            };
+            let to = graph.node(&binding.vhost, to_kind, &binding.destination, "");
+            let exchange_type = self
+                .exchanges
+                .iter()
+                .find(|e| e.vhost == binding.vhost && e.name == binding.source)
+                .map(|e| e.kind.as_str())
+                .unwrap_or("");
+            graph.edges.push((from, to, binding_label(binding, exchange_type)));
+        }
+        graph
+    }
+
+    pub fn to_dot(&self) -> String {
+        let graph = self.graph();
+        let multi_vhost = self.vhosts().len() > 1;
+
+        let mut out = String::from("digraph topology {\n");
+        out.push_str("    rankdir=LR;\n");
+        out.push_str("    node [fontname=\"Helvetica\"];\n");
+        out.push_str("    edge [fontname=\"Helvetica\", fontsize=10];\n");
+
+        for (index, (vhost, ids)) in graph.by_vhost().into_iter().enumerate() {
+            let indent = if multi_vhost { "        " } else { "    " };
+            if multi_vhost {
+                out.push_str(&format!("    subgraph cluster_{} {{\n", index));
+                out.push_str(&format!("        label=\"vhost {}\";\n", dot_escape(vhost)));
+            }
+            for id in ids {
+                let node = &graph.nodes[id];
+                let attributes = match node.kind {
+                    NodeKind::Exchange => format!(
+                        "shape=box, style=\"rounded,filled\", fillcolor=\"#fde2b8\", label=\"{}\"",
+                        dot_escape(&node.label())
+                    ),
+                    NodeKind::Queue => format!(
+                        "shape=cylinder, style=filled, fillcolor=\"#cfe3f7\", label=\"{}\"",
+                        dot_escape(&node.name)
+                    ),
+                };
+                out.push_str(&format!("{}{} [{}];\n", indent, node.id(id), attributes));
+            }
+            if multi_vhost {
+                out.push_str("    }\n");
+            }
+        }
+
+        for (from, to, label) in &graph.edges {
+            let from_id = graph.nodes[*from].id(*from);
+            let to_id = graph.nodes[*to].id(*to);
+            match label {
+                Some(label) => out.push_str(&format!(
+                    "    {} -> {} [label=\"{}\"];\n",
+                    from_id,
+                    to_id,
+                    dot_escape(label)
+                )),
+                None => out.push_str(&format!("    {} -> {};\n", from_id, to_id)),
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let graph = self.graph();
+        let multi_vhost = self.vhosts().len() > 1;
+
+        let mut out = String::from("flowchart LR\n");
+        for (index, (vhost, ids)) in graph.by_vhost().into_iter().enumerate() {
+            let indent = if multi_vhost { "        " } else { "    " };
+            if multi_vhost {
+                out.push_str(&format!(
+                    "    subgraph vhost_{}[\"vhost {}\"]\n",
+                    index,
+                    mermaid_escape(vhost)
+                ));
+            }
+            for id in ids {
+                let node = &graph.nodes[id];
+                // {{...}} = hexagon cho exchange, [(...)] = cylinder cho queue
+                let shape = match node.kind {
+                    NodeKind::Exchange => format!("{{{{\"{}\"}}}}", mermaid_escape(&node.label())),
+                    NodeKind::Queue => format!("[(\"{}\")]", mermaid_escape(&node.name)),
+                };
+                out.push_str(&format!("{}{}{}\n", indent, node.id(id), shape));
+            }
+            if multi_vhost {
+                out.push_str("    end\n");
+            }
+        }
+
+        for (from, to, label) in &graph.edges {
+            let from_id = graph.nodes[*from].id(*from);
+            let to_id = graph.nodes[*to].id(*to);
+            match label {
+                Some(label) => out.push_str(&format!(
+                    "    {} -->|\"{}\"| {}\n",
+                    from_id,
+                    mermaid_escape(label),
+                    to_id
+                )),
+                None => out.push_str(&format!("    {} --> {}\n", from_id, to_id)),
+            }
+        }
+
+        out.push_str("    classDef exchange fill:#fde2b8,stroke:#b7791f\n");
+        out.push_str("    classDef queue fill:#cfe3f7,stroke:#2b6cb0\n");
+        for (kind, class) in [(NodeKind::Exchange, "exchange"), (NodeKind::Queue, "queue")] {
+            let ids: Vec<String> = graph
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| node.kind == kind)
+                .map(|(id, node)| node.id(id))
+                .collect();
+            if !ids.is_empty() {
+                out.push_str(&format!("    class {} {}\n", ids.join(","), class));
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum NodeKind {
+    Exchange,
+    Queue,
+}
+
+#[derive(Debug)]
+struct Node {
+    vhost: String,
+    kind: NodeKind,
+    name: String,
+    exchange_type: String,
+}
+
+impl Node {
+    // Id chỉ gồm [a-z0-9_] - tên exchange/queue có thể chứa ký tự bất kỳ
+    fn id(&self, index: usize) -> String {
+        match self.kind {
+            NodeKind::Exchange => format!("x{}", index),
+            NodeKind::Queue => format!("q{}", index),
+        }
+    }
+
+    fn label(&self) -> String {
+        let name = if self.name.is_empty() { "(default)" } else { &self.name };
+        if self.exchange_type.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}\n{}", name, self.exchange_type)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    index: BTreeMap<(String, NodeKind, String), usize>,
+    edges: Vec<(usize, usize, Option<String>)>,
+}
+
+impl Graph {
+    fn node(&mut self, vhost: &str, kind: NodeKind, name: &str, exchange_type: &str) -> usize {
+        let key = (vhost.to_string(), kind, name.to_string());
+        if let Some(&id) = self.index.get(&key) {
+            if !exchange_type.is_empty() {
+                self.nodes[id].exchange_type = exchange_type.to_string();
+            }
+            return id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            vhost: vhost.to_string(),
+            kind,
+            name: name.to_string(),
+            exchange_type: exchange_type.to_string(),
+        });
+        self.index.insert(key, id);
+        id
+    }
+
+    // Exchanges trước rồi tới queues, nhóm theo vhost
+    fn by_vhost(&self) -> BTreeMap<&str, Vec<usize>> {
+        let mut groups: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (id, node) in self.nodes.iter().enumerate() {
+            groups.entry(node.vhost.as_str()).or_default().push(id);
+        }
+        for ids in groups.values_mut() {
+            ids.sort_by_key(|id| self.nodes[*id].kind);
+        }
+        groups
+    }
+}
+
+// Fanout bỏ qua routing key → không label; headers exchange → label là các arguments
+fn binding_label(binding: &BindingInfo, exchange_type: &str) -> Option<String> {
+    match exchange_type {
+        "fanout" => None,
+        "headers" => Some(format_arguments(&binding.arguments)),
+        _ if binding.routing_key.is_empty() && !binding.arguments.is_empty() => {
+            Some(format_arguments(&binding.arguments))
+        }
+        _ if binding.routing_key.is_empty() => None,
+        _ => Some(binding.routing_key.clone()),
+    }
+}
+
+fn format_arguments(arguments: &Map<String, Value>) -> String {
+    arguments
+        .iter()
+        .map(|(key, value)| match value {
+            Value::String(s) => format!("{}={}", key, s),
+            other => format!("{}={}", key, other),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn dot_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Mermaid dùng HTML entities trong label có dấu nháy
+fn mermaid_escape(value: &str) -> String {
+    value
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('\n', "<br/>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // logs (topic) → queue errors, logs → exchange audit (fanout) → queue archive
+    fn topology() -> Topology {
+        serde_json::from_value(json!({
+            "exchanges": [
+                {"name": "logs", "vhost": "/", "type": "topic"},
+                {"name": "audit", "vhost": "/", "type": "fanout"}
+            ],
+            "queues": [{"name": "errors", "vhost": "/"}, {"name": "archive", "vhost": "/"}],
+            "bindings": [
+                {"source": "logs", "vhost": "/", "destination": "errors", "destination_type": "queue", "routing_key": "*.error"},
+                {"source": "logs", "vhost": "/", "destination": "audit", "destination_type": "exchange", "routing_key": "#"},
+                {"source": "audit", "vhost": "/", "destination": "archive", "destination_type": "queue", "routing_key": "ignored"}
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn binding(routing_key: &str, arguments: Value) -> BindingInfo {
+        BindingInfo {
+            routing_key: routing_key.to_string(),
+            arguments: serde_json::from_value(arguments).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dot_draws_queue_and_exchange_bindings() {
+        let dot = topology().to_dot();
+        assert!(dot.starts_with("digraph topology {\n"));
+        assert!(dot.contains("    x0 [shape=box, style=\"rounded,filled\", fillcolor=\"#fde2b8\", label=\"logs\\ntopic\"];\n"));
+        assert!(dot.contains("    q2 [shape=cylinder, style=filled, fillcolor=\"#cfe3f7\", label=\"errors\"];\n"));
+        assert!(dot.contains("    x0 -> q2 [label=\"*.error\"];\n"));
+        assert!(dot.contains("    x0 -> x1 [label=\"#\"];\n"));
+        // Fanout → không label
+        assert!(dot.contains("    x1 -> q3;\n"));
+        assert!(!dot.contains("subgraph"));
+    }
+
+    #[test]
+    fn mermaid_draws_queue_and_exchange_bindings() {
+        let mermaid = topology().to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("    x1{{\"audit<br/>fanout\"}}\n"));
+        assert!(mermaid.contains("    q3[(\"archive\")]\n"));
+        assert!(mermaid.contains("    x0 -->|\"*.error\"| q2\n"));
+        assert!(mermaid.contains("    x0 -->|\"#\"| x1\n"));
+        assert!(mermaid.contains("    x1 --> q3\n"));
+        assert!(mermaid.contains("    class x0,x1 exchange\n"));
+        assert!(mermaid.contains("    class q2,q3 queue\n"));
+    }
+
+    #[test]
+    fn escapes_quotes_and_brackets() {
+        assert_eq!(dot_escape("say \"hi\"\\\nbye"), "say \\\"hi\\\"\\\\\\nbye");
+        assert_eq!(mermaid_escape("<\"q\">\nx"), "#lt;#quot;q#quot;#gt;<br/>x");
+    }
+
+    #[test]
+    fn binding_label_depends_on_exchange_type() {
+        assert_eq!(binding_label(&binding("ignored", json!({})), "fanout"), None);
+        assert_eq!(
+            binding_label(&binding("", json!({"x-match": "all", "format": "pdf"})), "headers"),
+            Some("format=pdf, x-match=all".to_string())
+        );
+        assert_eq!(binding_label(&binding("a.b", json!({})), "topic"), Some("a.b".to_string()));
+        assert_eq!(binding_label(&binding("", json!({})), "direct"), None);
+        assert_eq!(binding_label(&binding("", json!({"x-priority": 5})), ""), Some("x-priority=5".to_string()));
+    }
+
+    #[test]
+    fn without_builtins_drops_default_and_amq_exchanges() {
+        let mut topology = topology();
+        topology.exchanges.push(ExchangeInfo { name: "amq.topic".to_string(), ..Default::default() });
+        topology.exchanges.push(ExchangeInfo::default());
+        topology.bindings.push(BindingInfo {
+            destination: "errors".to_string(),
+            destination_type: "queue".to_string(),
+            routing_key: "errors".to_string(),
+            ..Default::default()
+        });
+        topology.bindings.push(BindingInfo {
+            source: "logs".to_string(),
+            destination: "amq.direct".to_string(),
+            destination_type: "exchange".to_string(),
+            ..Default::default()
+        });
+
+        let topology = topology.without_builtins();
+        let exchanges: Vec<&str> = topology.exchanges.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(exchanges, ["logs", "audit"]);
+        assert_eq!(topology.bindings.len(), 3);
+        assert_eq!(topology.queues.len(), 2);
+    }
+
+    #[test]
+    fn retain_vhost_keeps_matching_and_unscoped_entries() {
+        let mut topology = topology();
+        topology.queues.push(QueueInfo { name: "other".to_string(), vhost: "staging".to_string(), ..Default::default() });
+        topology.queues.push(QueueInfo { name: "unscoped".to_string(), ..Default::default() });
+        assert_eq!(topology.vhosts().len(), 3);
+
+        topology.retain_vhost("/");
+        let queues: Vec<&str> = topology.queues.iter().map(|q| q.name.as_str()).collect();
+        assert_eq!(queues, ["errors", "archive", "unscoped"]);
+        assert_eq!(topology.exchanges.len(), 2);
+        assert_eq!(topology.bindings.len(), 3);
+    }
+}
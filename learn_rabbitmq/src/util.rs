@@ -0,0 +1,10 @@
+// Tiện ích nhỏ dùng chung giữa các module
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Unix time tính bằng millisecond - dùng cho timestamp lưu trong SQLite (dedup, outbox, saga) và record
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,298 @@
+// Versioned message types + upcasting
+// Producer gắn `type` + header `x-message-version` (giống src/schema.rs) cho mỗi message.
+// Consumer chỉ biết struct version MỚI NHẤT; payload cũ đi qua chuỗi upcaster v1 → v2 → ... → hiện tại
+// (mỗi upcaster biến JSON version N thành JSON version N+1) rồi mới decode thành struct.
+//
+//   let upcasters = Upcasters::<MessageV3>::new()
+//       .with_upcaster(1, |mut v| { v.as_object_mut().ok_or("not an object")?.insert(...); Ok(v) })
+//       .with_upcaster(2, |mut v| { ...rename "content" → "body"...; Ok(v) });
+//   let msg: MessageV3 = upcasters.decode_delivery(&delivery)?;
+//
+// Message KHÔNG có header version được coi là v1 (các producer cũ trong main.rs).
+
+use crate::batch::OutgoingMessage;
+use crate::record::RecordedMessage;
+use crate::schema::{self, message_version};
+use lapin::{message::Delivery, BasicProperties};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+// Struct = version hiện tại của 1 message type
+pub trait Versioned: Serialize + DeserializeOwned {
+    const TYPE: &'static str;
+    const VERSION: u32;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpcastError {
+    // Property `type` là type khác (gửi nhầm queue/routing key)
+    WrongType { expected: String, found: String },
+    // Producer đã lên version mới hơn consumer → deploy consumer trước
+    FutureVersion { message_type: String, version: u32, current: u32 },
+    MissingUpcaster { message_type: String, from: u32 },
+    Upcaster { from: u32, error: String },
+    Decode(String),
+}
+
+impl fmt::Display for UpcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpcastError::WrongType { expected, found } => {
+                write!(f, "expected message type '{}', got '{}'", expected, found)
+            }
+            UpcastError::FutureVersion { message_type, version, current } => write!(
+                f,
+                "'{}' v{} is newer than this consumer (v{})",
+                message_type, version, current
+            ),
+            UpcastError::MissingUpcaster { message_type, from } => {
+                write!(f, "no upcaster for '{}' v{} → v{}", message_type, from, from + 1)
+            }
+            UpcastError::Upcaster { from, error } => write!(f, "upcast v{} → v{}: {}", from, from + 1, error),
+            UpcastError::Decode(e) => write!(f, "decode: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UpcastError {}
+
+type UpcastFn = Arc<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+pub struct Upcasters<T> {
+    // from version → upcaster (from → from + 1)
+    steps: BTreeMap<u32, UpcastFn>,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Upcasters<T> {
+    fn clone(&self) -> Self {
+        Upcasters {
+            steps: self.steps.clone(),
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<T: Versioned> Default for Upcasters<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Versioned> Upcasters<T> {
+    pub fn new() -> Self {
+        Upcasters {
+            steps: BTreeMap::new(),
+            _message: PhantomData,
+        }
+    }
+
+    // `upcaster` biến payload `from` thành payload `from + 1`
+    pub fn with_upcaster<F>(mut self, from: u32, upcaster: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.steps.insert(from, Arc::new(upcaster));
+        self
+    }
+
+    // Các version đọc được: từ version thấp nhất có chuỗi upcaster liền tới hiện tại
+    pub fn supported_versions(&self) -> Vec<u32> {
+        let mut oldest = T::VERSION;
+        while oldest > 1 && self.steps.contains_key(&(oldest - 1)) {
+            oldest -= 1;
+        }
+        (oldest..=T::VERSION).collect()
+    }
+
+    pub fn upcast(&self, version: u32, mut payload: Value) -> Result<Value, UpcastError> {
+        if version > T::VERSION {
+            return Err(UpcastError::FutureVersion {
+                message_type: T::TYPE.to_string(),
+                version,
+                current: T::VERSION,
+            });
+        }
+        for from in version..T::VERSION {
+            let step = self.steps.get(&from).ok_or_else(|| UpcastError::MissingUpcaster {
+                message_type: T::TYPE.to_string(),
+                from,
+            })?;
+            payload = step(payload).map_err(|error| UpcastError::Upcaster { from, error })?;
+        }
+        Ok(payload)
+    }
+
+    pub fn decode(&self, version: u32, payload: &[u8]) -> Result<T, UpcastError> {
+        let value: Value = serde_json::from_slice(payload).map_err(|e| UpcastError::Decode(e.to_string()))?;
+        let value = self.upcast(version, value)?;
+        serde_json::from_value(value).map_err(|e| UpcastError::Decode(e.to_string()))
+    }
+
+    // Type/version lấy từ properties; không có `type` → tin là T, không có version → v1
+    pub fn decode_message(&self, properties: &BasicProperties, payload: &[u8]) -> Result<T, UpcastError> {
+        if let Some(found) = properties.kind()
+            && found.as_str() != T::TYPE
+        {
+            return Err(UpcastError::WrongType {
+                expected: T::TYPE.to_string(),
+                found: found.to_string(),
+            });
+        }
+        self.decode(message_version(properties).unwrap_or(1), payload)
+    }
+
+    pub fn decode_delivery(&self, delivery: &Delivery) -> Result<T, UpcastError> {
+        self.decode_message(&delivery.properties, &delivery.data)
+    }
+
+    // Message trong file NDJSON của `record` - kiểm tra recording cũ vẫn decode được
+    pub fn decode_recorded(&self, message: &RecordedMessage) -> Result<T, UpcastError> {
+        let payload = message.body_bytes().map_err(|e| UpcastError::Decode(e.to_string()))?;
+        self.decode_message(&message.basic_properties(), &payload)
+    }
+}
+
+// Properties gắn type + version hiện tại của T
+pub fn properties_for<T: Versioned>(properties: BasicProperties) -> BasicProperties {
+    schema::with_message_type(properties, T::TYPE, T::VERSION)
+}
+
+pub fn outgoing<T: Versioned>(exchange: &str, routing_key: &str, message: &T) -> serde_json::Result<OutgoingMessage> {
+    let payload = serde_json::to_vec(message)?;
+    let properties = properties_for::<T>(BasicProperties::default());
+    Ok(OutgoingMessage::new(exchange, routing_key, payload).with_properties(properties))
+}
+
+// Message của các examples (main.rs Example 15), schema từng version ở schemas/message/v1-3.json:
+//   v1: { id, content }                 (simple_producer, không có header version)
+//   v2: { id, content, priority }
+//   v3: { id, body, priority }          (hiện tại - content đổi tên thành body)
+#[derive(Debug, Clone, PartialEq, Serialize, serde::Deserialize)]
+pub struct MessageV3 {
+    pub id: u32,
+    pub body: String,
+    pub priority: u8,
+}
+
+impl Versioned for MessageV3 {
+    const TYPE: &'static str = "message";
+    const VERSION: u32 = 3;
+}
+
+pub fn message_upcasters() -> Upcasters<MessageV3> {
+    Upcasters::new()
+        .with_upcaster(1, |mut v| {
+            let object = v.as_object_mut().ok_or("payload is not an object")?;
+            object.insert("priority".to_string(), serde_json::json!(0));
+            Ok(v)
+        })
+        .with_upcaster(2, |mut v| {
+            let object = v.as_object_mut().ok_or("payload is not an object")?;
+            let content = object.remove("content").ok_or("missing 'content'")?;
+            object.insert("body".to_string(), content);
+            Ok(v)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::FailurePolicy;
+    use crate::middleware::Pipeline;
+    use crate::record::read_recording;
+    use crate::schema::SchemaRegistry;
+    use std::path::PathBuf;
+
+    fn fixture(path: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+    }
+
+    fn recordings() -> Vec<RecordedMessage> {
+        let file = std::fs::File::open(fixture("messages.example.ndjson")).unwrap();
+        read_recording(std::io::BufReader::new(file)).unwrap()
+    }
+
+    // Recording cũ (cargo run -- record ...) phải vẫn decode được sau mỗi lần đổi version
+    #[test]
+    fn old_recordings_decode_to_current_version() {
+        let decoded: Vec<MessageV3> = recordings()
+            .iter()
+            .map(|message| message_upcasters().decode_recorded(message).unwrap())
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                MessageV3 { id: 1, body: "Hello from simple_producer (v1, no headers)".to_string(), priority: 0 },
+                MessageV3 { id: 2, body: "Tagged v1".to_string(), priority: 0 },
+                MessageV3 { id: 3, body: "Urgent v2".to_string(), priority: 9 },
+                MessageV3 { id: 4, body: "Current v3".to_string(), priority: 5 },
+            ]
+        );
+    }
+
+    // Mỗi fixture khớp schema version của nó, và upcast xong khớp schema hiện tại
+    #[test]
+    fn upcasted_fixtures_match_current_schema() {
+        let registry = SchemaRegistry::load_dir(fixture("schemas")).unwrap();
+        let upcasters = message_upcasters();
+        let mut versions = Vec::new();
+        for message in recordings() {
+            let version = message_version(&message.basic_properties()).unwrap_or(1);
+            let payload = message.body_json().unwrap();
+            registry.validate(MessageV3::TYPE, version, &payload).unwrap();
+
+            let upcasted = upcasters.upcast(version, payload).unwrap();
+            registry.validate(MessageV3::TYPE, MessageV3::VERSION, &upcasted).unwrap();
+            versions.push(version);
+        }
+        assert_eq!(versions, [1, 1, 2, 3]);
+        assert_eq!(upcasters.supported_versions(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_future_version_and_wrong_type() {
+        let upcasters = message_upcasters();
+        let future = schema::with_message_type(BasicProperties::default(), MessageV3::TYPE, 4);
+        assert!(matches!(
+            upcasters.decode_message(&future, br#"{"id":1}"#),
+            Err(UpcastError::FutureVersion { version: 4, current: 3, .. })
+        ));
+
+        let other = schema::with_message_type(BasicProperties::default(), "order.created", 1);
+        assert!(matches!(
+            upcasters.decode_message(&other, br#"{"id":1}"#),
+            Err(UpcastError::WrongType { .. })
+        ));
+        assert!(matches!(
+            upcasters.upcast(2, serde_json::json!({"id": 1})),
+            Err(UpcastError::Upcaster { from: 2, .. })
+        ));
+    }
+
+    // Version mới hơn consumer → requeue (kể cả redelivered) cho consumer mới; v1 vẫn tới handler
+    #[tokio::test]
+    async fn pipeline_requeues_future_version() {
+        let pipeline = Pipeline::builder()
+            .versioned(message_upcasters(), |_message: MessageV3, _ctx| async { Ok(()) });
+        let delivery = |properties: BasicProperties, redelivered: bool| Delivery {
+            delivery_tag: 1,
+            exchange: "".into(),
+            routing_key: "messages".into(),
+            redelivered,
+            properties,
+            data: br#"{"id":1,"content":"hi"}"#.to_vec(),
+            acker: Default::default(),
+        };
+
+        let future = schema::with_message_type(BasicProperties::default(), MessageV3::TYPE, 4);
+        assert_eq!(pipeline.handle(delivery(future.clone(), false)).await.unwrap(), Some(FailurePolicy::Requeue));
+        assert_eq!(pipeline.handle(delivery(future, true)).await.unwrap(), Some(FailurePolicy::Requeue));
+        assert_eq!(pipeline.handle(delivery(BasicProperties::default(), false)).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,917 @@
+// Cầu nối HTTP ⇄ RabbitMQ cho hệ thống không nói AMQP (webhook của SaaS, script curl, serverless...)
+//
+// HTTP → AMQP (HttpBridge):
+//   POST /exchanges/{name}/publish?routing_key=order.created&persistent=true   body = payload
+//     Content-Type / X-Message-Id / X-Correlation-Id → content_type / message_id / correlation_id
+//     Publish qua BatchPublisher (confirm + mandatory) → trả lời SAU khi broker confirm:
+//       200 {"routed":true}                          broker ack
+//       200 {"routed":false,"reply_code":312,...}    không queue nào nhận (basic.return)
+//       403 exchange không nằm trong allowlist   404 exchange không tồn tại
+//       502 lỗi channel/connection   503 broker nack   422 sai JSON Schema
+//     Exchange mặc định (routing theo tên queue) → tên "amq.default"
+//   GET /health → 200 / 503 theo trạng thái connection
+//
+// AMQP → HTTP (WebhookSink): consume queue, POST từng message tới URL cố định
+//   2xx → ack
+//   408 / 425 / 429 / 5xx / lỗi mạng → thử lại (backoff lũy thừa), hết lượt → exhausted policy (mặc định reject → DLX)
+//   4xx khác → reject ngay (gửi lại cũng vậy)
+//   Header: X-AMQP-Exchange / X-AMQP-Routing-Key / X-AMQP-Redelivered / X-AMQP-Message-Id / X-AMQP-Correlation-Id,
+//   Idempotency-Key = message_id (at-least-once: receiver tự bỏ qua bản lặp)
+//
+// Chữ ký webhook dùng lại KeyRing của src/envelope.rs (keys/ + `keygen`):
+//   X-Webhook-Signature = base64(sign("<X-Webhook-Timestamp>." + body))
+//   + X-Webhook-Signature-Alg / X-Webhook-Key-Id. Timestamp nằm trong phần ký → receiver chặn replay
+//   bằng cách từ chối timestamp lệch quá `tolerance`.
+//
+// Router chỉ nói chuyện với BridgeBackend (AmqpBackend = broker thật) → test được HTTP mà không cần broker.
+//
+// StandInReceiver: receiver giả trên localhost (in request, kiểm tra chữ ký, cố tình trả 503 N lần đầu)
+// để thử sink mà không cần dịch vụ thật.
+
+use crate::batch::{BatchPublisher, OutgoingMessage, PublishOutcome};
+use crate::envelope::{Algorithm, EnvelopeError, KeyRing};
+use crate::event_bus::FailurePolicy;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, Method, StatusCode, Uri},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use lapin::{
+    message::Delivery,
+    options::{BasicAckOptions, BasicNackOptions},
+    types::ShortString,
+    BasicProperties, Connection, Consumer, Result as LapinResult,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SIGNATURE_ALG_HEADER: &str = "x-webhook-signature-alg";
+pub const SIGNATURE_KEY_HEADER: &str = "x-webhook-key-id";
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+// Tên dùng trong URL cho exchange mặc định ("")
+pub const DEFAULT_EXCHANGE_ALIAS: &str = "amq.default";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookError {
+    // Receiver trả status không phải 2xx
+    Status { status: u16, body: String },
+    // Không kết nối được / timeout
+    Transport(String),
+    Signature(EnvelopeError),
+    // Timestamp lệch quá tolerance (replay hoặc lệch đồng hồ)
+    Stale { age_secs: i64 },
+}
+
+impl WebhookError {
+    // Gửi lại có thể thành công không
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            WebhookError::Status { status, .. } => matches!(status, 408 | 425 | 429) || *status >= 500,
+            WebhookError::Transport(_) => true,
+            WebhookError::Signature(_) | WebhookError::Stale { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::Status { status, body } if body.is_empty() => write!(f, "HTTP {}", status),
+            WebhookError::Status { status, body } => write!(f, "HTTP {}: {}", status, body),
+            WebhookError::Transport(e) => write!(f, "request failed: {}", e),
+            WebhookError::Signature(e) => write!(f, "bad webhook signature: {}", e),
+            WebhookError::Stale { age_secs } => write!(f, "webhook timestamp is {}s off", age_secs),
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+impl From<EnvelopeError> for WebhookError {
+    fn from(e: EnvelopeError) -> Self {
+        WebhookError::Signature(e)
+    }
+}
+
+// ===== Chữ ký =====
+
+fn signed_bytes(timestamp: &str, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(timestamp.len() + 1 + body.len());
+    data.extend_from_slice(timestamp.as_bytes());
+    data.push(b'.');
+    data.extend_from_slice(body);
+    data
+}
+
+#[derive(Clone)]
+pub struct WebhookSigner {
+    keys: Arc<KeyRing>,
+    algorithm: Algorithm,
+    key_id: String,
+}
+
+impl WebhookSigner {
+    // Chỉ HmacSha256 / Ed25519 (algorithm.is_signature())
+    pub fn new(keys: Arc<KeyRing>, algorithm: Algorithm, key_id: &str) -> Result<Self, EnvelopeError> {
+        if !algorithm.is_signature() {
+            return Err(EnvelopeError::UnsupportedAlgorithm(algorithm.name().to_string()));
+        }
+        // Báo thiếu key ngay lúc tạo thay vì ở message đầu tiên
+        keys.sign(algorithm, key_id, b"")?;
+        Ok(WebhookSigner {
+            keys,
+            algorithm,
+            key_id: key_id.to_string(),
+        })
+    }
+
+    // Các header cần gắn vào request
+    pub fn headers(&self, timestamp: i64, body: &[u8]) -> Result<Vec<(&'static str, String)>, EnvelopeError> {
+        let timestamp = timestamp.to_string();
+        let signature = self
+            .keys
+            .sign(self.algorithm, &self.key_id, &signed_bytes(&timestamp, body))?;
+        Ok(vec![
+            (SIGNATURE_ALG_HEADER, self.algorithm.name().to_string()),
+            (SIGNATURE_KEY_HEADER, self.key_id.clone()),
+            (TIMESTAMP_HEADER, timestamp),
+            (SIGNATURE_HEADER, BASE64.encode(signature)),
+        ])
+    }
+}
+
+// Phía receiver: kiểm tra chữ ký + timestamp. Trả về key id đã ký
+pub fn verify_webhook(keys: &KeyRing, headers: &HeaderMap, body: &[u8], tolerance: Duration) -> Result<String, WebhookError> {
+    let header = |name: &'static str| -> Result<&str, EnvelopeError> {
+        match headers.get(name) {
+            Some(value) => value.to_str().map_err(|_| EnvelopeError::MalformedHeader(name)),
+            None => Err(EnvelopeError::Unsigned),
+        }
+    };
+    let algorithm = header(SIGNATURE_ALG_HEADER)?;
+    let algorithm = Algorithm::from_name(algorithm)
+        .filter(|alg| alg.is_signature())
+        .ok_or_else(|| EnvelopeError::UnsupportedAlgorithm(algorithm.to_string()))?;
+    let key_id = header(SIGNATURE_KEY_HEADER)?;
+    let timestamp = header(TIMESTAMP_HEADER)?;
+    let signature = BASE64
+        .decode(header(SIGNATURE_HEADER)?)
+        .map_err(|_| EnvelopeError::MalformedHeader(SIGNATURE_HEADER))?;
+
+    // Kiểm tra chữ ký trước: timestamp chỉ đáng tin khi chữ ký đúng
+    keys.verify(algorithm, key_id, &signed_bytes(timestamp, body), &signature)?;
+
+    let sent_at: i64 = timestamp
+        .parse()
+        .map_err(|_| EnvelopeError::MalformedHeader(TIMESTAMP_HEADER))?;
+    let age_secs = now_secs() - sent_at;
+    if age_secs.unsigned_abs() > tolerance.as_secs() {
+        return Err(WebhookError::Stale { age_secs });
+    }
+    Ok(key_id.to_string())
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+// ===== HTTP → AMQP =====
+
+// Nơi HttpBridge publish tới: publish trả về kết quả SAU confirm, health cho GET /health
+pub trait BridgeBackend: Send + Sync {
+    fn publish(&self, message: OutgoingMessage) -> BoxFuture<'_, PublishOutcome>;
+    // (connected, blocked)
+    fn health(&self) -> (bool, bool);
+}
+
+// Broker thật: 1 publisher confirm + mandatory dùng chung, mutex giữ thứ tự delivery tag (xem batch.rs)
+pub struct AmqpBackend {
+    connection: Arc<Connection>,
+    publisher: tokio::sync::Mutex<BatchPublisher>,
+}
+
+impl AmqpBackend {
+    pub async fn new(connection: Arc<Connection>) -> LapinResult<Self> {
+        let publisher = Self::publisher(&connection).await?;
+        Ok(AmqpBackend {
+            connection,
+            publisher: tokio::sync::Mutex::new(publisher),
+        })
+    }
+
+    async fn publisher(connection: &Connection) -> LapinResult<BatchPublisher> {
+        Ok(BatchPublisher::new(connection.create_channel().await?)
+            .await?
+            .with_mandatory(true))
+    }
+}
+
+impl BridgeBackend for AmqpBackend {
+    fn publish(&self, message: OutgoingMessage) -> BoxFuture<'_, PublishOutcome> {
+        async move {
+            let mut publisher = self.publisher.lock().await;
+            let report = publisher.publish_sequential(vec![message]).await;
+            let outcome = report
+                .results
+                .into_iter()
+                .next()
+                .map(|r| r.outcome)
+                .unwrap_or_else(|| PublishOutcome::Failed("no publish result".to_string()));
+
+            // Exchange không tồn tại → broker đóng channel (404 NOT_FOUND) → mở channel mới cho request sau
+            if matches!(outcome, PublishOutcome::Failed(_)) && !publisher.channel().status().connected() {
+                match Self::publisher(&self.connection).await {
+                    Ok(fresh) => *publisher = fresh,
+                    Err(reopen) => println!("✗ Webhook bridge: cannot reopen publisher channel: {}", reopen),
+                }
+            }
+            outcome
+        }
+        .boxed()
+    }
+
+    fn health(&self) -> (bool, bool) {
+        let status = self.connection.status();
+        (status.connected(), status.blocked())
+    }
+}
+
+struct BridgeState {
+    backend: Arc<dyn BridgeBackend>,
+    allowed_exchanges: Option<BTreeSet<String>>,
+}
+
+pub struct HttpBridge {
+    backend: Arc<dyn BridgeBackend>,
+    allowed_exchanges: Option<BTreeSet<String>>,
+}
+
+impl HttpBridge {
+    pub async fn new(connection: Arc<Connection>) -> LapinResult<Self> {
+        Ok(HttpBridge::with_backend(Arc::new(AmqpBackend::new(connection).await?)))
+    }
+
+    pub fn with_backend(backend: Arc<dyn BridgeBackend>) -> Self {
+        HttpBridge {
+            backend,
+            allowed_exchanges: None,
+        }
+    }
+
+    // Chỉ cho publish vào các exchange này (mặc định: mọi exchange user AMQP được phép)
+    pub fn with_allowed_exchanges<I, S>(mut self, exchanges: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_exchanges = Some(exchanges.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn router(self) -> Router {
+        let state = Arc::new(BridgeState {
+            backend: self.backend,
+            allowed_exchanges: self.allowed_exchanges,
+        });
+        Router::new()
+            .route("/exchanges/{name}/publish", post(publish))
+            .route("/health", get(health))
+            .with_state(state)
+    }
+
+    pub async fn serve(self, listener: tokio::net::TcpListener) -> io::Result<()> {
+        axum::serve(listener, self.router()).await
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PublishQuery {
+    #[serde(default)]
+    routing_key: String,
+    #[serde(default)]
+    persistent: bool,
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<ShortString> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| ShortString::from(value.to_string()))
+}
+
+async fn publish(
+    State(state): State<Arc<BridgeState>>,
+    Path(name): Path<String>,
+    Query(query): Query<PublishQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<Value>) {
+    let exchange = if name == DEFAULT_EXCHANGE_ALIAS { "" } else { name.as_str() };
+    if let Some(allowed) = &state.allowed_exchanges
+        && !allowed.contains(&name)
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": format!("exchange '{}' is not allowed", name) })),
+        );
+    }
+
+    let mut properties = BasicProperties::default();
+    if let Some(content_type) = header_value(&headers, "content-type") {
+        properties = properties.with_content_type(content_type);
+    }
+    if let Some(message_id) = header_value(&headers, "x-message-id") {
+        properties = properties.with_message_id(message_id);
+    }
+    if let Some(correlation_id) = header_value(&headers, "x-correlation-id") {
+        properties = properties.with_correlation_id(correlation_id);
+    }
+    if query.persistent {
+        properties = properties.with_delivery_mode(2);
+    }
+    let message = OutgoingMessage::new(exchange, &query.routing_key, body.to_vec()).with_properties(properties);
+
+    match state.backend.publish(message).await {
+        PublishOutcome::Acked => (StatusCode::OK, Json(json!({ "routed": true }))),
+        PublishOutcome::Returned { reply_code, reply_text } => (
+            StatusCode::OK,
+            Json(json!({ "routed": false, "reply_code": reply_code, "reply_text": reply_text })),
+        ),
+        PublishOutcome::Nacked => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "broker rejected the message" })),
+        ),
+        PublishOutcome::Invalid(errors) => (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "errors": errors }))),
+        PublishOutcome::Failed(e) => {
+            let status = if e.contains("NOT_FOUND") {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            (status, Json(json!({ "error": e })))
+        }
+    }
+}
+
+async fn health(State(state): State<Arc<BridgeState>>) -> (StatusCode, Json<Value>) {
+    let (connected, blocked) = state.backend.health();
+    let code = if connected {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(json!({ "connected": connected, "blocked": blocked })))
+}
+
+// ===== AMQP → HTTP =====
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    pub delivered: u64,
+    pub retries: u64,
+    // Reject ngay (4xx) hoặc hết lượt thử
+    pub failed: u64,
+}
+
+pub struct WebhookSink {
+    http: reqwest::Client,
+    url: String,
+    signer: Option<WebhookSigner>,
+    max_attempts: u32,
+    backoff: Duration,
+    timeout: Duration,
+    exhausted: FailurePolicy,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> io::Result<Self> {
+        let http = reqwest::Client::builder().build().map_err(io::Error::other)?;
+        Ok(WebhookSink {
+            http,
+            url: url.to_string(),
+            signer: None,
+            max_attempts: 5,
+            backoff: Duration::from_millis(500),
+            timeout: Duration::from_secs(10),
+            exhausted: FailurePolicy::Reject,
+        })
+    }
+
+    pub fn with_signer(mut self, signer: WebhookSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    // Tổng số lần gửi (tính cả lần đầu); chờ backoff, 2×backoff, 4×backoff... giữa các lần
+    pub fn with_retries(mut self, max_attempts: u32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    // Timeout mỗi request
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Làm gì khi hết lượt thử với lỗi tạm thời: Reject (DLX, mặc định) / Requeue / Ignore (ack, bỏ)
+    pub fn with_exhausted_policy(mut self, policy: FailurePolicy) -> Self {
+        self.exhausted = policy;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // Gửi 1 message, có retry. Trả về status 2xx và số lần đã thử lại
+    pub async fn forward(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        redelivered: bool,
+        properties: &BasicProperties,
+        body: &[u8],
+    ) -> (Result<u16, WebhookError>, u32) {
+        let mut retries = 0;
+        loop {
+            let result = self.send(exchange, routing_key, redelivered, properties, body).await;
+            match result {
+                Err(e) if e.is_retryable() && retries + 1 < self.max_attempts => {
+                    let delay = self.backoff.saturating_mul(1 << retries.min(16));
+                    println!(
+                        "⚠️  Webhook {} ({}), retry {}/{} in {:?}",
+                        self.url,
+                        e,
+                        retries + 1,
+                        self.max_attempts - 1,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    retries += 1;
+                }
+                result => return (result, retries),
+            }
+        }
+    }
+
+    pub async fn forward_delivery(&self, delivery: &Delivery) -> (Result<u16, WebhookError>, u32) {
+        self.forward(
+            delivery.exchange.as_str(),
+            delivery.routing_key.as_str(),
+            delivery.redelivered,
+            &delivery.properties,
+            &delivery.data,
+        )
+        .await
+    }
+
+    async fn send(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        redelivered: bool,
+        properties: &BasicProperties,
+        body: &[u8],
+    ) -> Result<u16, WebhookError> {
+        let content_type = properties
+            .content_type()
+            .as_ref()
+            .map(|value| value.as_str())
+            .unwrap_or("application/octet-stream");
+        let mut request = self
+            .http
+            .post(&self.url)
+            .timeout(self.timeout)
+            .header("content-type", content_type)
+            .header("x-amqp-exchange", exchange)
+            .header("x-amqp-routing-key", routing_key)
+            .header("x-amqp-redelivered", redelivered.to_string());
+        if let Some(message_id) = properties.message_id() {
+            request = request
+                .header("x-amqp-message-id", message_id.as_str())
+                .header(IDEMPOTENCY_KEY_HEADER, message_id.as_str());
+        }
+        if let Some(correlation_id) = properties.correlation_id() {
+            request = request.header("x-amqp-correlation-id", correlation_id.as_str());
+        }
+        // Ký lại mỗi lần gửi → timestamp mới, không bị receiver coi là replay khi retry lâu
+        if let Some(signer) = &self.signer {
+            for (name, value) in signer.headers(now_secs(), body)? {
+                request = request.header(name, value);
+            }
+        }
+
+        let response = request
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| WebhookError::Transport(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(status.as_u16());
+        }
+        let mut body = response.text().await.unwrap_or_default();
+        body.truncate(200);
+        Err(WebhookError::Status {
+            status: status.as_u16(),
+            body,
+        })
+    }
+
+    // Consume tới khi consumer đóng. Mỗi message: forward → ack / nack (lỗi vĩnh viễn → reject)
+    pub async fn run(&self, mut consumer: Consumer) -> LapinResult<SinkStats> {
+        let mut stats = SinkStats::default();
+        while let Some(delivery) = consumer.next().await {
+            let delivery = delivery?;
+            let (result, retries) = self.forward_delivery(&delivery).await;
+            stats.retries += u64::from(retries);
+
+            let e = match result {
+                Ok(status) => {
+                    stats.delivered += 1;
+                    println!("✓ [{}] {} → HTTP {}", delivery.routing_key, delivery.delivery_tag, status);
+                    delivery.ack(BasicAckOptions::default()).await?;
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            stats.failed += 1;
+            let policy = if e.is_retryable() {
+                self.exhausted
+            } else {
+                FailurePolicy::Reject
+            };
+            match policy {
+                FailurePolicy::Ignore => {
+                    println!("✗ [{}] {}: {} (dropped)", delivery.routing_key, delivery.delivery_tag, e);
+                    delivery.ack(BasicAckOptions::default()).await?;
+                }
+                FailurePolicy::Reject | FailurePolicy::Requeue => {
+                    let requeue = policy == FailurePolicy::Requeue;
+                    println!(
+                        "✗ [{}] {}: {} ({})",
+                        delivery.routing_key,
+                        delivery.delivery_tag,
+                        e,
+                        if requeue { "requeued" } else { "rejected" }
+                    );
+                    delivery
+                        .nack(BasicNackOptions {
+                            requeue,
+                            ..Default::default()
+                        })
+                        .await?;
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+// ===== Receiver giả để thử sink =====
+
+#[derive(Default)]
+struct StandInState {
+    keys: Option<Arc<KeyRing>>,
+    tolerance: Duration,
+    fail_first: u64,
+    received: AtomicU64,
+    accepted: AtomicU64,
+}
+
+#[derive(Default)]
+pub struct StandInReceiver {
+    state: StandInState,
+}
+
+impl StandInReceiver {
+    pub fn new() -> Self {
+        StandInReceiver::default()
+    }
+
+    // Chữ ký sai / thiếu → 401
+    pub fn with_verification(mut self, keys: Arc<KeyRing>, tolerance: Duration) -> Self {
+        self.state.keys = Some(keys);
+        self.state.tolerance = tolerance;
+        self
+    }
+
+    // N request đầu trả 503 → thấy sink retry
+    pub fn with_fail_first(mut self, count: u64) -> Self {
+        self.state.fail_first = count;
+        self
+    }
+
+    // Nhận mọi method / path. Trả về router + bộ đếm (received, accepted) để kiểm tra
+    pub fn router(self) -> (Router, StandInCounters) {
+        let state = Arc::new(self.state);
+        let counters = StandInCounters { state: state.clone() };
+        (Router::new().fallback(receive).with_state(state), counters)
+    }
+
+    pub async fn serve(self, listener: tokio::net::TcpListener) -> io::Result<()> {
+        let (router, _) = self.router();
+        axum::serve(listener, router).await
+    }
+}
+
+#[derive(Clone)]
+pub struct StandInCounters {
+    state: Arc<StandInState>,
+}
+
+impl StandInCounters {
+    pub fn received(&self) -> u64 {
+        self.state.received.load(Ordering::Relaxed)
+    }
+
+    pub fn accepted(&self) -> u64 {
+        self.state.accepted.load(Ordering::Relaxed)
+    }
+}
+
+async fn receive(
+    State(state): State<Arc<StandInState>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, &'static str) {
+    let count = state.received.fetch_add(1, Ordering::Relaxed) + 1;
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+    println!(
+        "ℹ️  #{} {} {} routing_key={} message_id={} redelivered={} ({} bytes)",
+        count,
+        method,
+        uri,
+        header("x-amqp-routing-key"),
+        header("x-amqp-message-id"),
+        header("x-amqp-redelivered"),
+        body.len()
+    );
+
+    if let Some(keys) = &state.keys {
+        match verify_webhook(keys, &headers, &body, state.tolerance) {
+            Ok(key_id) => println!("   ✓ signature ok (key '{}')", key_id),
+            Err(e) => {
+                println!("   ✗ {}", e);
+                return (StatusCode::UNAUTHORIZED, "bad signature");
+            }
+        }
+    }
+    if count <= state.fail_first {
+        println!("   ⚠️  failing on purpose ({}/{})", count, state.fail_first);
+        return (StatusCode::SERVICE_UNAVAILABLE, "try again later");
+    }
+    println!("   {}", String::from_utf8_lossy(&body));
+    state.accepted.fetch_add(1, Ordering::Relaxed);
+    (StatusCode::OK, "ok")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{}", addr)
+    }
+
+    fn keys() -> Arc<KeyRing> {
+        let mut keys = KeyRing::new();
+        keys.insert("hooks", Algorithm::HmacSha256, &[7; 32]).unwrap();
+        Arc::new(keys)
+    }
+
+    fn sink(url: &str) -> WebhookSink {
+        WebhookSink::new(url).unwrap().with_retries(4, Duration::from_millis(1))
+    }
+
+    fn signed_headers(timestamp: i64, body: &[u8]) -> HeaderMap {
+        let signer = WebhookSigner::new(keys(), Algorithm::HmacSha256, "hooks").unwrap();
+        let mut headers = HeaderMap::new();
+        for (name, value) in signer.headers(timestamp, body).unwrap() {
+            headers.insert(name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[tokio::test]
+    async fn forward_retries_503_then_succeeds() {
+        let (router, counters) = StandInReceiver::new().with_fail_first(2).router();
+        let sink = sink(&serve(router).await);
+
+        let (result, retries) = sink
+            .forward("orders", "order.created", false, &BasicProperties::default(), b"{}")
+            .await;
+        assert_eq!(result, Ok(200));
+        assert_eq!(retries, 2);
+        assert_eq!((counters.received(), counters.accepted()), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn forward_gives_up_on_permanent_errors_and_after_max_attempts() {
+        // Receiver đòi chữ ký, sink không ký → 401: gửi lại cũng vậy → không retry
+        let (router, counters) = StandInReceiver::new()
+            .with_verification(keys(), Duration::from_secs(300))
+            .router();
+        let (result, retries) = sink(&serve(router).await)
+            .forward("orders", "order.created", false, &BasicProperties::default(), b"{}")
+            .await;
+        assert!(matches!(result, Err(WebhookError::Status { status: 401, .. })));
+        assert_eq!((retries, counters.received()), (0, 1));
+
+        let (router, counters) = StandInReceiver::new().with_fail_first(10).router();
+        let (result, retries) = sink(&serve(router).await)
+            .forward("orders", "order.created", false, &BasicProperties::default(), b"{}")
+            .await;
+        assert!(matches!(result, Err(WebhookError::Status { status: 503, .. })));
+        assert_eq!((retries, counters.received()), (3, 4));
+    }
+
+    #[tokio::test]
+    async fn signed_sink_is_accepted_by_verifying_receiver() {
+        let (router, counters) = StandInReceiver::new()
+            .with_verification(keys(), Duration::from_secs(300))
+            .router();
+        let signer = WebhookSigner::new(keys(), Algorithm::HmacSha256, "hooks").unwrap();
+        let (result, _) = sink(&serve(router).await)
+            .with_signer(signer)
+            .forward("orders", "order.created", false, &BasicProperties::default(), b"{}")
+            .await;
+        assert_eq!(result, Ok(200));
+        assert_eq!(counters.accepted(), 1);
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        let status = |status| WebhookError::Status {
+            status,
+            body: String::new(),
+        };
+        for code in [408, 425, 429, 500, 502, 503] {
+            assert!(status(code).is_retryable(), "{}", code);
+        }
+        for code in [400, 401, 403, 404, 409, 422] {
+            assert!(!status(code).is_retryable(), "{}", code);
+        }
+        assert!(WebhookError::Transport("connection refused".to_string()).is_retryable());
+        assert!(!WebhookError::Stale { age_secs: 600 }.is_retryable());
+    }
+
+    #[test]
+    fn verify_accepts_signed_and_rejects_tampered_or_stale() {
+        let tolerance = Duration::from_secs(300);
+        let body = br#"{"id":1}"#;
+        let headers = signed_headers(now_secs(), body);
+        assert_eq!(verify_webhook(&keys(), &headers, body, tolerance), Ok("hooks".to_string()));
+
+        assert_eq!(
+            verify_webhook(&keys(), &headers, br#"{"id":2}"#, tolerance),
+            Err(WebhookError::Signature(EnvelopeError::BadSignature {
+                key_id: "hooks".to_string()
+            }))
+        );
+
+        let stale = signed_headers(now_secs() - 600, body);
+        assert!(matches!(
+            verify_webhook(&keys(), &stale, body, tolerance),
+            Err(WebhookError::Stale { age_secs }) if age_secs >= 600
+        ));
+
+        // Timestamp nằm trong phần ký → sửa timestamp cho "mới" lại cũng không qua
+        let mut replayed = stale.clone();
+        replayed.insert(TIMESTAMP_HEADER, now_secs().to_string().parse().unwrap());
+        assert!(matches!(
+            verify_webhook(&keys(), &replayed, body, tolerance),
+            Err(WebhookError::Signature(EnvelopeError::BadSignature { .. }))
+        ));
+        assert_eq!(
+            verify_webhook(&keys(), &HeaderMap::new(), body, tolerance),
+            Err(WebhookError::Signature(EnvelopeError::Unsigned))
+        );
+    }
+
+    // Backend giả: ghi lại message, trả `outcome` cố định
+    struct RecordingBackend {
+        outcome: PublishOutcome,
+        published: Mutex<Vec<OutgoingMessage>>,
+    }
+
+    impl BridgeBackend for RecordingBackend {
+        fn publish(&self, message: OutgoingMessage) -> BoxFuture<'_, PublishOutcome> {
+            self.published.lock().unwrap().push(message);
+            futures::future::ready(self.outcome.clone()).boxed()
+        }
+
+        fn health(&self) -> (bool, bool) {
+            (true, false)
+        }
+    }
+
+    async fn bridge(outcome: PublishOutcome) -> (String, Arc<RecordingBackend>) {
+        let backend = Arc::new(RecordingBackend {
+            outcome,
+            published: Mutex::new(Vec::new()),
+        });
+        let router = HttpBridge::with_backend(backend.clone())
+            .with_allowed_exchanges(["orders", DEFAULT_EXCHANGE_ALIAS])
+            .router();
+        (serve(router).await, backend)
+    }
+
+    #[tokio::test]
+    async fn bridge_enforces_allowlist_and_maps_headers() {
+        let (base_url, backend) = bridge(PublishOutcome::Acked).await;
+        let http = reqwest::Client::new();
+
+        let forbidden = http
+            .post(format!("{}/exchanges/amq.topic/publish?routing_key=x", base_url))
+            .body("{}")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(forbidden.status(), 403);
+        assert!(backend.published.lock().unwrap().is_empty());
+
+        let response = http
+            .post(format!("{}/exchanges/orders/publish?routing_key=order.created&persistent=true", base_url))
+            .header("content-type", "application/json")
+            .header("x-message-id", "m-1")
+            .body(r#"{"id":1}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.json::<Value>().await.unwrap(), json!({ "routed": true }));
+
+        let default = http
+            .post(format!("{}/exchanges/amq.default/publish?routing_key=hello", base_url))
+            .body("hi")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(default.status(), 200);
+
+        let published = backend.published.lock().unwrap();
+        let message = &published[0];
+        assert_eq!((message.exchange.as_str(), message.routing_key.as_str()), ("orders", "order.created"));
+        assert_eq!(message.payload, br#"{"id":1}"#);
+        assert_eq!(message.properties.message_id().as_ref().unwrap().as_str(), "m-1");
+        assert_eq!(message.properties.content_type().as_ref().unwrap().as_str(), "application/json");
+        assert_eq!(*message.properties.delivery_mode(), Some(2));
+        assert_eq!((published[1].exchange.as_str(), published[1].routing_key.as_str()), ("", "hello"));
+    }
+
+    #[tokio::test]
+    async fn bridge_maps_publish_outcomes_to_status() {
+        let cases = [
+            (
+                PublishOutcome::Returned {
+                    reply_code: 312,
+                    reply_text: "NO_ROUTE".to_string(),
+                },
+                200,
+            ),
+            (PublishOutcome::Nacked, 503),
+            (PublishOutcome::Failed("NOT_FOUND - no exchange 'orders'".to_string()), 404),
+            (PublishOutcome::Failed("channel closed".to_string()), 502),
+        ];
+        for (outcome, expected) in cases {
+            let (base_url, _) = bridge(outcome.clone()).await;
+            let response = reqwest::Client::new()
+                .post(format!("{}/exchanges/orders/publish", base_url))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{:?}", outcome);
+        }
+
+        let (base_url, _) = bridge(PublishOutcome::Acked).await;
+        let health = reqwest::get(format!("{}/health", base_url)).await.unwrap();
+        assert_eq!(health.status(), 200);
+    }
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use lapin::{options::*, types::FieldTable};
+use learn_rabbitmq::outbox::{self, NewOutboxEvent, OutboxRelay, RelayConfig};
+use std::sync::Arc;
+
+// Row hợp lệ còn in-flight khi row phía sau (exchange không tồn tại) làm broker đóng channel
+// → chỉ row hỏng bị tính lỗi, row hợp lệ vẫn được gửi
+#[tokio::test]
+#[ignore = "needs a broker: RABBITMQ_TEST_URL"]
+async fn poison_row_does_not_blame_in_flight_rows() {
+    let conn = Arc::new(common::connect(&common::broker_url()).await);
+    let channel = conn.create_channel().await.unwrap();
+    let queue = common::unique("outbox.relay");
+    channel
+        .queue_declare(&queue, QueueDeclareOptions { auto_delete: true, ..Default::default() }, FieldTable::default())
+        .await
+        .unwrap();
+    channel
+        .queue_bind(&queue, "amq.topic", &queue, QueueBindOptions::default(), FieldTable::default())
+        .await
+        .unwrap();
+
+    let path = std::env::temp_dir().join(format!("learn_rabbitmq_outbox_relay_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = outbox::open_db(&path).unwrap();
+    let payload = serde_json::json!({"id": 1});
+    outbox::enqueue(&db, &NewOutboxEvent::json("good", "amq.topic", &queue, &payload).unwrap()).unwrap();
+    let missing = common::unique("missing_exchange");
+    outbox::enqueue(&db, &NewOutboxEvent::json("poison", &missing, &queue, &payload).unwrap()).unwrap();
+
+    let mut relay = OutboxRelay::new(RelayConfig::new(&path, "relay-test"), conn.clone()).await.unwrap();
+    let stats = relay.run_once().await.unwrap();
+    assert_eq!((stats.sent, stats.failed, stats.parked), (1, 1, 0));
+
+    let counts = outbox::counts(&db).unwrap();
+    assert_eq!((counts.sent, counts.failing), (1, 1));
+    let delivered = common::drain(&channel, &queue).await;
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].properties.message_id().as_ref().map(|id| id.as_str()), Some("good"));
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use lapin::{BasicProperties, options::*};
+use learn_rabbitmq::batch::BatchPublisher;
+use learn_rabbitmq::partition::{PARTITION_KEY_HEADER, PartitionStrategy, Partitioner};
+use std::collections::BTreeMap;
+
+// Event của nhiều key xen kẽ nhau → trong mỗi partition queue, message cùng key giữ đúng thứ tự publish
+// và mỗi key chỉ nằm ở 1 partition
+#[tokio::test]
+#[ignore = "needs a broker: RABBITMQ_TEST_URL"]
+async fn interleaved_keys_keep_per_key_order() {
+    let conn = common::connect(&common::broker_url()).await;
+    let channel = conn.create_channel().await.unwrap();
+    let partitioner = Partitioner::new(&common::unique("orders.partitioned"), 4, PartitionStrategy::ClientHash);
+    partitioner.declare(&channel).await.unwrap();
+
+    let mut messages = Vec::new();
+    for seq in 0..5 {
+        for order in 0..12 {
+            let key = format!("order-{}", order);
+            messages.push(partitioner.outgoing(&key, seq.to_string().into_bytes(), BasicProperties::default()));
+        }
+    }
+    let mut publisher = BatchPublisher::new(conn.create_channel().await.unwrap()).await.unwrap();
+    assert_eq!(publisher.publish_batch(messages).await.failed(), 0);
+
+    let mut seen: BTreeMap<String, (u32, Vec<u32>)> = BTreeMap::new();
+    for partition in 0..partitioner.partitions() {
+        let queue = partitioner.queue_name(partition);
+        for delivery in common::drain(&channel, &queue).await {
+            let headers = delivery.properties.headers().clone().unwrap();
+            let key = match headers.inner().get(PARTITION_KEY_HEADER) {
+                Some(lapin::types::AMQPValue::LongString(key)) => key.to_string(),
+                other => panic!("missing partition key header: {:?}", other),
+            };
+            assert_eq!(partitioner.partition_of(&key), Some(partition));
+            let (owner, sequence) = seen.entry(key).or_insert((partition, Vec::new()));
+            assert_eq!(*owner, partition);
+            sequence.push(String::from_utf8(delivery.data).unwrap().parse().unwrap());
+        }
+        channel.queue_delete(&queue, QueueDeleteOptions::default()).await.unwrap();
+    }
+    channel
+        .exchange_delete(partitioner.exchange(), ExchangeDeleteOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(seen.len(), 12);
+    for (key, (_, sequence)) in seen {
+        assert_eq!(sequence, [0, 1, 2, 3, 4], "{}", key);
+    }
+}
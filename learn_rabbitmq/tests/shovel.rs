@@ -0,0 +1,159 @@
+mod common;
+
+use lapin::{BasicProperties, Channel, ExchangeKind, options::*, types::FieldTable};
+use learn_rabbitmq::batch::{BatchPublisher, OutgoingMessage};
+use learn_rabbitmq::config::RabbitMQConfig;
+use learn_rabbitmq::shovel::{Endpoint, SOURCE_HEADER, Shovel};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+// Nguồn = RABBITMQ_TEST_URL, đích = RABBITMQ_TEST_DEST_URL (nên là vhost khác), mặc định cùng URL
+fn dest_url() -> String {
+    std::env::var("RABBITMQ_TEST_DEST_URL").unwrap_or_else(|_| common::broker_url())
+}
+
+fn endpoint(url: &str) -> Endpoint {
+    Endpoint::from_config(&RabbitMQConfig {
+        url: url.to_string(),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+struct Topology {
+    source: Channel,
+    destination: Channel,
+    queue: String,
+    exchange: String,
+    dest_queue: String,
+}
+
+impl Topology {
+    // Queue nguồn có `count` message (message_id m-0..), exchange đích fanout → dest_queue
+    async fn new(source: &lapin::Connection, destination: &lapin::Connection, count: usize) -> Topology {
+        let topology = Topology {
+            source: source.create_channel().await.unwrap(),
+            destination: destination.create_channel().await.unwrap(),
+            queue: common::unique("shovel.source"),
+            exchange: common::unique("shovel.exchange"),
+            dest_queue: common::unique("shovel.destination"),
+        };
+        topology
+            .source
+            .queue_declare(&topology.queue, QueueDeclareOptions::default(), FieldTable::default())
+            .await
+            .unwrap();
+        topology.declare_destination().await;
+
+        let messages = (0..count).map(|i| {
+            OutgoingMessage::new("", &topology.queue, format!("{}", i).into_bytes())
+                .with_properties(BasicProperties::default().with_message_id(format!("m-{}", i).into()))
+        });
+        let mut publisher = BatchPublisher::new(source.create_channel().await.unwrap()).await.unwrap();
+        assert_eq!(publisher.publish_batch(messages).await.failed(), 0);
+        topology
+    }
+
+    async fn declare_destination(&self) {
+        self.destination
+            .exchange_declare(&self.exchange, ExchangeKind::Fanout, ExchangeDeclareOptions::default(), FieldTable::default())
+            .await
+            .unwrap();
+        self.destination
+            .queue_declare(&self.dest_queue, QueueDeclareOptions::default(), FieldTable::default())
+            .await
+            .unwrap();
+        self.destination
+            .queue_bind(&self.dest_queue, &self.exchange, "", QueueBindOptions::default(), FieldTable::default())
+            .await
+            .unwrap();
+    }
+
+    async fn count(channel: &Channel, queue: &str) -> u32 {
+        let passive = QueueDeclareOptions {
+            passive: true,
+            ..Default::default()
+        };
+        channel
+            .queue_declare(queue, passive, FieldTable::default())
+            .await
+            .unwrap()
+            .message_count()
+    }
+
+    fn shovel(&self) -> Shovel {
+        Shovel::new(endpoint(&common::broker_url()), &self.queue, endpoint(&dest_url()), &self.exchange)
+            .with_idle_exit(Duration::from_millis(500))
+            .with_reconnect_delay(Duration::from_millis(100))
+    }
+
+    async fn cleanup(&self) {
+        let _ = self.source.queue_delete(&self.queue, QueueDeleteOptions::default()).await;
+        let _ = self.destination.queue_delete(&self.dest_queue, QueueDeleteOptions::default()).await;
+        let _ = self.destination.exchange_delete(&self.exchange, ExchangeDeleteOptions::default()).await;
+    }
+}
+
+// Message chỉ rời queue nguồn khi đích đã confirm; mọi message tới đích kèm x-shovel-source
+#[tokio::test]
+#[ignore = "needs a broker: RABBITMQ_TEST_URL"]
+async fn acks_source_after_destination_confirms() {
+    let source = common::connect(&common::broker_url()).await;
+    let destination = common::connect(&dest_url()).await;
+    let topology = Topology::new(&source, &destination, 100).await;
+
+    let stats = topology.shovel().with_prefetch(20, 10).run().await.unwrap();
+    assert_eq!((stats.shovelled, stats.requeued, stats.unroutable), (100, 0, 0));
+    assert_eq!(Topology::count(&topology.source, &topology.queue).await, 0);
+
+    let delivered = common::drain(&topology.destination, &topology.dest_queue).await;
+    let ids: BTreeSet<String> = delivered
+        .iter()
+        .map(|d| d.properties.message_id().as_ref().unwrap().to_string())
+        .collect();
+    assert_eq!(ids.len(), 100);
+    let headers = delivered[0].properties.headers().clone().unwrap();
+    assert!(headers.inner().contains_key(SOURCE_HEADER));
+
+    topology.cleanup().await;
+}
+
+// Đích chết giữa chừng (exchange đích bị xóa → channel đích đóng, shovel dừng) → message chưa confirm vẫn ở
+// nguồn; dựng lại đích rồi chạy lại → tiếp tục, không mất message (có thể trùng: at-least-once)
+#[tokio::test]
+#[ignore = "needs a broker: RABBITMQ_TEST_URL"]
+async fn resumes_after_destination_dies() {
+    let source = common::connect(&common::broker_url()).await;
+    let destination = common::connect(&dest_url()).await;
+    let total = 2000;
+    let topology = Topology::new(&source, &destination, total).await;
+
+    let shovel = topology.shovel().with_prefetch(1, 1);
+    let first_run = tokio::spawn(async move { shovel.run().await });
+    while Topology::count(&topology.destination, &topology.dest_queue).await < 50 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    topology
+        .destination
+        .exchange_delete(&topology.exchange, ExchangeDeleteOptions::default())
+        .await
+        .unwrap();
+    // Exchange đích không tồn tại → lỗi không phục hồi được → run dừng
+    assert!(first_run.await.unwrap().is_err());
+    let remaining = Topology::count(&topology.source, &topology.queue).await;
+    assert!(remaining > 0, "shovel finished before the destination died");
+
+    topology.declare_destination().await;
+    let stats = topology.shovel().run().await.unwrap();
+    assert!(stats.shovelled >= u64::from(remaining));
+    assert_eq!(Topology::count(&topology.source, &topology.queue).await, 0);
+
+    let ids: BTreeSet<String> = common::drain(&topology.destination, &topology.dest_queue)
+        .await
+        .iter()
+        .map(|d| d.properties.message_id().as_ref().unwrap().to_string())
+        .collect();
+    assert_eq!(ids, (0..total).map(|i| format!("m-{}", i)).collect());
+
+    topology.cleanup().await;
+}